@@ -0,0 +1,134 @@
+//! Displaying right-to-left text (like Hebrew and Arabic).
+//! This is a simplification of the unicode bidi algorithm:
+//! a line is right to left if its first letter is,
+//! and runs of left-to-right text in it keep their order.
+//! The cursor still moves through the text in logical order.
+
+use ratatui::{style::Style, text::{Line, Span}, layout::Alignment};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::parse::whitespace::TABSIZE;
+
+/// If a character belongs to a script written right to left
+pub fn is_rtl(c: char) -> bool {
+    matches!(c as u32,
+        // Hebrew, Arabic, Syriac, Thaana, NKo, Samaritan, Mandaic
+        0x0590..=0x08FF
+        // presentation forms
+        | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF
+        // historic scripts and Arabic mathematical symbols
+        | 0x10800..=0x10FFF | 0x1E800..=0x1EFFF
+    )
+}
+
+/// If a line is written right to left, which is decided by its first letter
+pub fn line_is_rtl(line: &str) -> bool {
+    line.chars().find(|c| c.is_alphabetic()).is_some_and(is_rtl)
+}
+
+/// If any line of the content is written right to left
+pub fn has_rtl(content: &str) -> bool {
+    content.chars().any(is_rtl) && content.lines().any(line_is_rtl)
+}
+
+/// The order the graphemes of a right-to-left line are displayed in, from the left.
+/// Runs of left-to-right text (like latin words and numbers) keep their order,
+/// whitespace and punctuation between them belongs to the run.
+pub fn visual_order(line: &str) -> Vec<usize> {
+    let graphemes: Vec<&str> = line.graphemes(true).collect();
+    // Some(true) for left to right, None for neutral
+    let strong: Vec<Option<bool>> = graphemes.iter().map(|g| {
+        if g.chars().any(is_rtl) {
+            Some(false)
+        } else if g.chars().any(char::is_alphanumeric) {
+            Some(true)
+        } else {
+            None
+        }
+    }).collect();
+    let ltr: Vec<bool> = (0..graphemes.len()).map(|i| match strong[i] {
+        Some(ltr) => ltr,
+        None => {
+            let before = strong[..i].iter().rev().find_map(|s| *s);
+            let after = strong[i..].iter().find_map(|s| *s);
+            before == Some(true) && after == Some(true)
+        },
+    }).collect();
+
+    let mut order: Vec<usize> = (0..graphemes.len()).rev().collect();
+    let mut i = 0;
+    while i < order.len() {
+        if !ltr[order[i]] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < order.len() && ltr[order[i]] {
+            i += 1;
+        }
+        order[start..i].reverse();
+    }
+    order
+}
+
+/// Display a right-to-left line mirrored and aligned to the right.
+/// Also returns the display order of its graphemes.
+pub fn mirror(line: &Line) -> (Line<'static>, Vec<usize>) {
+    let graphemes: Vec<(String, Style)> = line.spans.iter()
+        .flat_map(|span| span.content.graphemes(true).map(|g| (g.to_string(), span.style)))
+        .collect();
+    let text: String = graphemes.iter().map(|(g, _)| g.as_str()).collect();
+    let order = visual_order(&text);
+    let mut spans: Vec<Span> = vec![];
+    for &i in &order {
+        let (grapheme, style) = &graphemes[i];
+        match spans.last_mut() {
+            Some(span) if span.style == *style => span.content.to_mut().push_str(grapheme),
+            _ => spans.push(Span::styled(grapheme.clone(), *style)),
+        }
+    }
+    (Line::from(spans).style(line.style).alignment(Alignment::Right), order)
+}
+
+/// The index of the grapheme after `prefix` in the displayed line, where every tab is expanded
+pub fn display_index(prefix: &str) -> usize {
+    prefix.graphemes(true).map(|g| if g == "\t" { TABSIZE } else { 1 }).sum()
+}
+
+/// The column on screen of the grapheme at `index` of a mirrored line in an area `width` wide.
+/// The end of the line is left of it.
+pub fn visual_column(order: &[usize], index: usize, width: usize) -> usize {
+    let start = width.saturating_sub(order.len());
+    match order.iter().position(|i| *i == index) {
+        Some(position) => start + position,
+        None => start.saturating_sub(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_rtl_lines() {
+        assert!(line_is_rtl("  שלום world"));
+        assert!(line_is_rtl("1. مرحبا"));
+        assert!(!line_is_rtl("hello שלום"));
+        assert!(!line_is_rtl("  123 "));
+        assert!(has_rtl("fn main() {}\n// שלום\n"));
+        assert!(!has_rtl("// hello שלום\n"));
+    }
+
+    #[test]
+    fn orders_rtl_lines() {
+        // א ב, space, "ab c", space, ג
+        let line = "אב ab c ג";
+        let order = visual_order(line);
+        let visual: String = order.iter().map(|i| line.graphemes(true).nth(*i).unwrap()).collect();
+        assert_eq!(visual, "ג ab c בא");
+        assert_eq!(visual_column(&order, 0, 20), 20 - 9 + 8);
+        assert_eq!(visual_column(&order, 9, 20), 10);
+        // the accent is part of the first grapheme
+        assert_eq!(display_index("\tא\u{5B8}ב"), 6);
+    }
+}
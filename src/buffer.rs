@@ -1,10 +1,16 @@
-use std::{cmp, collections::HashMap, fs::File, io::{self, Read, Seek, Stderr, Write}, os::fd::IntoRawFd, process::{self, Stdio}, sync::{Arc, Mutex}, usize};
+use std::{cell::Cell, cmp, collections::HashMap, fs::{File, Metadata}, os::unix::fs::MetadataExt, io::{self, Read, Seek, Stderr, Write}, os::fd::IntoRawFd, ops::RangeBounds, path::{Path, PathBuf}, process::{self, Stdio}, sync::{Arc, Mutex}, usize};
+use encoding_rs::{Encoding, UTF_8};
 use syntect::parsing::{SyntaxSet, SyntaxReference};
 use tracing::{debug, info};
 use unicode_segmentation::UnicodeSegmentation;
 
 
 use crate::parse::*;
+use crate::encoding;
+use crate::columns::{self, Columns};
+use crate::wrap;
+use crate::transform;
+use crate::undo::{Change, UndoState};
 
 pub static PRIVESC_CMD: &'static str = "run0";
 
@@ -20,32 +26,92 @@ pub struct Buffer {
     pub readonly: bool,
     /// How far the buffer is scrolled
     pub top: usize,
+    /// How far the buffer is scrolled horizontally (in columns)
+    pub left: usize,
     /// Which column the cursor wants to be in (that's vague I know)
     pub prefered_col: Option<usize>,
     /// The cached parse states for this buffer
     pub parse_cache: HashMap<usize, CachedParseState>,
     pub syntax: Option<SyntaxReference>,
     pub highlights: Vec<(usize, usize)>,
+    /// What the highlights were searched for
+    pub query: Option<String>,
+    /// How the query matches case
+    pub case: Case,
+    /// The selected byte range
+    pub selection: Option<(usize, usize)>,
+    /// The encoding of the file on disk
+    pub encoding: &'static Encoding,
+    /// The file started with a byte order mark
+    pub bom: bool,
+    /// Where the opened path links to if it is a symlink.
+    /// Saving writes through the link, so the target is what gets edited.
+    pub symlink: Option<PathBuf>,
+    /// Display the fields separated by this delimiter as aligned columns
+    pub delimiter: Option<char>,
+    /// The permissions and ownership of the file when it was opened
+    pub metadata: Option<Metadata>,
+    /// Append what gets written to the file (like `tail -f`)
+    pub follow: bool,
+    /// How many bytes of the file were read, following continues from there
+    file_offset: u64,
+    /// The start of a character the last read of a followed file ended in the middle of
+    partial: Vec<u8>,
+    /// The canonical path of the file, to tell if it is already open
+    pub canonical: Option<PathBuf>,
+    /// The changes that can be undone
+    pub undo: UndoState,
+    /// A throwaway buffer that never has unsaved changes
+    pub scratch: bool,
+    /// It was told that the right-to-left lines of this buffer are displayed mirrored
+    pub rtl_noticed: bool,
+    /// How the file is indented, `None` if that's unknown (or detection is off)
+    pub indent: Option<Indent>,
+    /// The changes made by typing since they were last taken for undo
+    pub changes: Vec<Change>,
+    /// The last result of `dirty`, with the undo generation and length of the content it was for
+    dirty_cache: Cell<Option<(usize, usize, bool)>>,
 }
 
 
 impl Buffer {
-    pub fn new(name: String, mut file: File, readonly: bool) -> Self {
-        let mut content = String::new();
-        file.read_to_string(&mut content).unwrap();
-        return Self {
-            name,
-            content: content,
+    pub fn new(name: String, mut file: File, readonly: bool, encoding: Option<&'static Encoding>) -> io::Result<Self> {
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        let decoded = encoding::decode(&bytes, encoding)?;
+        let metadata = file.metadata()?;
+        return Ok(Self {
+            content: decoded.content,
             file: Some(Arc::new(Mutex::new(file))),
             position: 0,
             readonly: false,
             opened_readonly: readonly,
             top: 0,
+            left: 0,
             prefered_col: None,
             parse_cache: HashMap::new(),
             syntax: None,
             highlights: vec![],
-        }
+            case: Case::default(),
+            query: None,
+            selection: None,
+            encoding: decoded.encoding,
+            bom: decoded.bom,
+            symlink: None,
+            delimiter: columns::delimiter_for(&name),
+            metadata: Some(metadata),
+            follow: false,
+            file_offset: bytes.len() as u64,
+            partial: vec![],
+            canonical: std::fs::canonicalize(&name).ok(),
+            undo: UndoState::default(),
+            scratch: false,
+            rtl_noticed: false,
+            indent: None,
+            changes: vec![],
+            dirty_cache: Cell::new(None),
+            name,
+        })
     }
 
     /// awful bug fix for a dumb design flaw.
@@ -67,10 +133,29 @@ impl Buffer {
             readonly: false,
             opened_readonly: false,
             top: 0,
+            left: 0,
             prefered_col: None,
             parse_cache: HashMap::new(),
             syntax: None,
             highlights: vec![],
+            case: Case::default(),
+            query: None,
+            selection: None,
+            encoding: UTF_8,
+            bom: false,
+            symlink: None,
+            delimiter: None,
+            metadata: None,
+            follow: false,
+            file_offset: 0,
+            partial: vec![],
+            canonical: None,
+            undo: UndoState::default(),
+            scratch: false,
+            rtl_noticed: false,
+            indent: None,
+            changes: vec![],
+            dirty_cache: Cell::new(None),
         }
     }
 
@@ -91,11 +176,22 @@ impl Buffer {
         self.prefered_col = Some(x as usize);
     }
 
+    /// The byte offset and grapheme index of the cursor within its line
+    pub fn line_offsets(&self) -> (usize, usize) {
+        let mut end = cmp::min(self.position, self.content.len());
+        while !self.content.is_char_boundary(end) {
+            end -= 1;
+        }
+        let before = &self.content[..end];
+        let line = before.rsplit('\n').next().unwrap_or_default();
+        (self.position - (before.len() - line.len()), line.graphemes(true).count())
+    }
+
     /// Get position as column and row (of the total buffer not the viewport)
     pub fn cursor_pos(&self) -> (u16, u16) {
         let mut row = 0;
         let mut col = 0;
-        for (index, chr) in self.content.chars().enumerate() {
+        for (index, chr) in self.content.char_indices() {
             if index >= self.position {
                 break;
             }
@@ -112,14 +208,26 @@ impl Buffer {
         return (col, row)
     }
 
-    pub fn move_left(&mut self) {
+    /// Move to the previous grapheme.
+    /// At the start of a line the cursor only goes to the previous line if `wrap` is set.
+    pub fn move_left(&mut self, wrap: bool) {
         self.prefered_col = None;
-        self.position = self.position.saturating_sub(1);
+        match self.prev_grapheme() {
+            Some((_, "\n" | "\r\n")) if !wrap => {},
+            Some((start, _)) => self.position = start,
+            None => {},
+        }
     }
-    
-    pub fn move_right(&mut self) {
+
+    /// Move to the next grapheme.
+    /// At the end of a line the cursor only goes to the next line if `wrap` is set.
+    pub fn move_right(&mut self, wrap: bool) {
         self.prefered_col = None;
-        self.position = cmp::min(self.position + 1, self.content.len());
+        match self.cur_grapheme() {
+            Some((_, "\n" | "\r\n")) if !wrap => {},
+            Some((start, grapheme)) => self.position = start + grapheme.len(),
+            None => {},
+        }
     }
     
     pub fn move_up(&mut self) {
@@ -148,18 +256,136 @@ impl Buffer {
         }
     }
 
-    pub fn page_up(&mut self, height: usize) {
+    /// The line starting at byte `start` (without its newline)
+    /// and where its rows start when it is wrapped at `width`
+    fn wrapped_line(&self, start: usize, width: usize) -> (&str, Vec<usize>) {
+        let line = self.content[start..].split('\n').next().unwrap_or_default();
+        (line, wrap::get_linebreak_locations(line, width))
+    }
+
+    /// Move up a row of the lines wrapped at `width`.
+    /// The preferred column is the column within the row.
+    pub fn move_up_wrapped(&mut self, width: usize) {
+        let start = self.start_of_line_at(self.position);
+        let (line, breaks) = self.wrapped_line(start, width);
+        let (row, col) = wrap::row_and_column(line, &breaks, self.position - start);
+        let col = self.prefered_col.unwrap_or(col);
+        if row > 0 {
+            self.position = start + wrap::byte_at(line, &breaks, row - 1, col);
+        } else if start > 0 {
+            let previous = self.start_of_line_at(start - 1);
+            let (line, breaks) = self.wrapped_line(previous, width);
+            self.position = previous + wrap::byte_at(line, &breaks, breaks.len(), col);
+        } else {
+            self.position = 0;
+        }
+        self.prefered_col = Some(col);
+    }
+
+    /// Move down a row of the lines wrapped at `width`
+    pub fn move_down_wrapped(&mut self, width: usize) {
+        let start = self.start_of_line_at(self.position);
+        let (line, breaks) = self.wrapped_line(start, width);
+        let (row, col) = wrap::row_and_column(line, &breaks, self.position - start);
+        let col = self.prefered_col.unwrap_or(col);
+        if row < breaks.len() {
+            self.position = start + wrap::byte_at(line, &breaks, row + 1, col);
+        } else if start + line.len() < self.content.len() {
+            let next = start + line.len() + 1;
+            let (line, breaks) = self.wrapped_line(next, width);
+            self.position = next + wrap::byte_at(line, &breaks, 0, col);
+        } else {
+            self.position = self.content.len();
+        }
+        self.prefered_col = Some(col);
+    }
+
+    /// Scroll up a page, keeping `overlap` lines of the current page in view
+    pub fn page_up(&mut self, height: usize, overlap: usize) {
         let (col, mut row) = self.cursor_pos();
         row = row.saturating_sub(self.top as u16);
-        self.top = self.top.saturating_sub(height);
+        self.top = self.top.saturating_sub(page_step(height, overlap));
         self.set_viewport_cursor_pos(self.prefered_col.unwrap_or(col as usize) as u16, row);
     }
     
-    pub fn page_down(&mut self, height: usize) {
-        let (col, mut row) = self.cursor_pos();
-        row = row.saturating_sub(self.top as u16);
-        self.top = cmp::min(self.top + height - 1, self.content.lines().count().saturating_sub(height) + 1);
-        self.set_viewport_cursor_pos(self.prefered_col.unwrap_or(col as usize) as u16, row);
+    /// Scroll down a page, keeping `overlap` lines of the current page in view.
+    /// The last page ends with the last line,
+    /// when there is no more to scroll the cursor goes to the last line.
+    pub fn page_down(&mut self, height: usize, overlap: usize) {
+        let (col, row) = self.cursor_pos();
+        let last = self.content.split('\n').count() - 1;
+        let bottom = (last + 1).saturating_sub(height);
+        let at_bottom = self.top >= bottom;
+        let row = (row as usize).saturating_sub(self.top);
+        self.top = cmp::min(self.top + page_step(height, overlap), bottom);
+        // the same row on screen, but not past the end
+        let row = match at_bottom {
+            true => last - self.top,
+            false => cmp::min(row, last - self.top),
+        };
+        self.set_viewport_cursor_pos(self.prefered_col.unwrap_or(col as usize) as u16, row as u16);
+    }
+
+    /// Scroll so the cursor is in view of a viewport `height` lines tall.
+    /// The cursor is kept `scrolloff` lines away from the edges where possible.
+    pub fn scroll_view(&mut self, height: usize, scrolloff: usize) {
+        let (_, cursor_y) = self.cursor_pos();
+        let cursor_y = cursor_y as usize;
+        // the margin can't take up more than half the viewport
+        let scrolloff = cmp::min(scrolloff, height.saturating_sub(1) / 2);
+        if cursor_y < self.top + scrolloff {
+            self.top = cursor_y.saturating_sub(scrolloff);
+        } else if cursor_y + scrolloff >= self.top + height {
+            // don't scroll past the content just to keep the margin
+            let line_count = self.content.chars().filter(|c| *c == '\n').count() + 1;
+            let max_top = cmp::max(line_count.saturating_sub(height), (cursor_y + 1).saturating_sub(height));
+            self.top = cmp::min((cursor_y + scrolloff + 1).saturating_sub(height), max_top);
+        }
+    }
+
+    /// Scroll down until the row of the cursor is in view when lines wrap at `width`
+    pub fn scroll_view_wrapped(&mut self, height: usize, width: usize) {
+        let (_, cursor_y) = self.cursor_pos();
+        while self.top < cursor_y as usize && wrap::row_of(&self.content, &wrap::screen_rows(&self.content, self.top, height, width), self.position).is_none() {
+            self.top += 1;
+        }
+    }
+
+    /// Scroll sideways so the cursor column is within `width`
+    pub fn scroll_view_horizontally(&mut self, width: usize, height: usize) {
+        let cursor_x = self.display_column(height);
+        if cursor_x < self.left {
+            self.left = cursor_x;
+        } else if cursor_x >= self.left + width {
+            self.left = cursor_x + 1 - width;
+        }
+    }
+
+    /// The column of the cursor on screen, with the padding of aligned columns among the `height` lines in view
+    pub fn display_column(&self, height: usize) -> usize {
+        let Some(columns) = self.visible_columns(height) else {
+            return self.cursor_pos().0 as usize;
+        };
+        let line_start = self.content[..self.position].rfind('\n').map_or(0, |i| i + 1);
+        let line = self.content[line_start..].lines().next().unwrap_or_default();
+        columns.column(line, self.position - line_start)
+    }
+
+    /// The column widths of the lines in view when columns are aligned
+    pub fn visible_columns(&self, height: usize) -> Option<Columns> {
+        let delimiter = self.delimiter?;
+        Some(Columns::measure(self.content.lines().skip(self.top).take(height), delimiter))
+    }
+
+    /// Toggle aligning columns, guessing the delimiter from the first line
+    pub fn toggle_aligned_columns(&mut self) {
+        self.delimiter = match self.delimiter {
+            Some(_) => None,
+            None => columns::delimiter_for(&self.name).or_else(|| {
+                let first_line = self.content.lines().next().unwrap_or_default();
+                Some(if first_line.matches('\t').count() > first_line.matches(',').count() { '\t' } else { ',' })
+            }),
+        };
     }
 
     pub fn to_top(&mut self) {
@@ -188,6 +414,10 @@ impl Buffer {
         return 0;
     }
 
+    fn start_of_line_at(&self, position: usize) -> usize {
+        self.content[..position].rfind('\n').map_or(0, |i| i + 1)
+    }
+
     fn start_of_prev_line(&self) -> Option<usize> {
         let start_of_line = self.start_of_line();
         if start_of_line == 0 {
@@ -201,39 +431,30 @@ impl Buffer {
         return Some(0);
     }
 
-    pub fn move_word_left(&mut self) {
-        let mut next = self.content.chars().nth(self.position.saturating_sub(1)).unwrap();
-        if next.is_whitespace() {
-            while next.is_whitespace() && self.position > 0 && self.start_of_line() != self.position {
-                self.position -= 1;
-                next = self.content.chars().nth(self.position.saturating_sub(1)).unwrap();
-            }
-        } else if next.is_alphanumeric() {
-            while (next.is_alphanumeric() || next == '_') && self.position > 0 && self.start_of_line() != self.position {
-                self.position -= 1;
-                next = self.content.chars().nth(self.position.saturating_sub(1)).unwrap();
-            }
-        } else {
-            while !next.is_alphanumeric()  && !next.is_whitespace() && self.position > 0 && self.start_of_line() != self.position {
-                self.position -= 1;
-                next = self.content.chars().nth(self.position.saturating_sub(1)).unwrap();
+    /// Move to the start of the previous word.
+    /// Words consist of alphanumerics and any of `word_chars`.
+    pub fn move_word_left(&mut self, word_chars: &str) {
+        if let Some(first) = self.previous_char() {
+            // the start of the line is as far as it goes
+            while let Some(c) = self.previous_char() {
+                if c == '\n' || !same_kind(first, c, word_chars) {
+                    break;
+                }
+                self.position -= c.len_utf8();
             }
         }
         self.prefered_col = None;
     }
 
-    pub fn move_word_right(&mut self) {
-        if self.current_char().is_whitespace() {
-            while self.current_char().is_whitespace() && self.position+1 != self.content.len() && self.current_char() != '\n' {
-                self.position += 1;
-            }
-        } else if self.current_char().is_alphanumeric() {
-            while (self.current_char().is_alphanumeric() || self.current_char() == '_') && self.position+1 != self.content.len() {
-                self.position += 1;
-            }
-        } else {
-            while !self.current_char().is_alphanumeric()  && !self.current_char().is_whitespace() && self.position+1 != self.content.len() {
-                self.position += 1;
+    /// Move to the end of the next word.
+    /// Words consist of alphanumerics and any of `word_chars`.
+    pub fn move_word_right(&mut self, word_chars: &str) {
+        if let Some(first) = self.current_char() {
+            while let Some(c) = self.current_char() {
+                if c == '\n' || !same_kind(first, c, word_chars) {
+                    break;
+                }
+                self.position += c.len_utf8();
             }
         }
         self.prefered_col = None;
@@ -244,6 +465,15 @@ impl Buffer {
         self.prefered_col = None;
     }
 
+    /// Go to the first non-whitespace character of the line,
+    /// or to the start of the line if the cursor is already there
+    pub fn goto_first_non_whitespace(&mut self) {
+        let start = self.start_of_line();
+        let indent = self.content[start..].len() - self.content[start..].trim_start_matches([' ', '\t']).len();
+        self.position = if self.position == start + indent { start } else { start + indent };
+        self.prefered_col = None;
+    }
+
     pub fn goto_end_of_line(&mut self) {
         self.position = match self.start_of_next_line() {
             Some(start_of_next_line) => start_of_next_line - 1,
@@ -252,45 +482,627 @@ impl Buffer {
         self.prefered_col = None;
     }
 
-    fn current_char(&self) -> char {
-        return self.content.chars().nth(self.position).unwrap();
+    /// The character at the cursor (`position` is a byte offset)
+    fn current_char(&self) -> Option<char> {
+        self.content.get(self.position..)?.chars().next()
+    }
+
+    /// The character before the cursor
+    fn previous_char(&self) -> Option<char> {
+        self.content.get(..self.position)?.chars().next_back()
     }
 
     pub fn insert(&mut self, chr: char) {
         if !self.readonly {
-            self.content.insert(self.position + self.magic_unicode_offset_bug_fix(), chr);
-            self.move_right();
+            self.splice(self.position + self.magic_unicode_offset_bug_fix(), 0, chr.encode_utf8(&mut [0; 4]));
+            self.position += chr.len_utf8();
+            self.prefered_col = None;
             // invalidating from top is faster than figuring out the current line
             // and you render from the top anyway
             self.parse_cache.invalidate_from(self.top);
         }
     }
 
-    pub fn find(&mut self, query: String) {
-        let matches: Vec<_> = self.content.match_indices(&query).map(|(start, match_)| {
-            (start, start + match_.len())
+    /// Replace `len` bytes at `at` with `inserted`, remembering the change for undo.
+    /// Whoever takes the changes fills in where the cursor was.
+    fn splice(&mut self, at: usize, len: usize, inserted: &str) {
+        let removed: String = self.content.drain(at..at + len).collect();
+        self.content.insert_str(at, inserted);
+        self.changes.push(Change { at, removed, inserted: inserted.to_owned(), cursor_before: 0, cursor_after: 0 });
+    }
+
+    /// Insert a level of indentation: a tab, or spaces up to the next level if the file is indented with spaces
+    pub fn insert_indent(&mut self) {
+        match self.indent {
+            Some(Indent::Spaces(width)) => {
+                let col = self.cursor_pos().0 as usize;
+                for _ in 0..width - col % width {
+                    self.insert(' ');
+                }
+            },
+            _ => self.insert('\t'),
+        }
+    }
+
+    /// Insert a newline indented like the current line,
+    /// one level deeper after an opening bracket.
+    /// Between a pair of brackets the closing one goes on a line of its own.
+    pub fn insert_newline_indented(&mut self) {
+        if self.readonly {
+            return;
+        }
+        let line = &self.content[self.start_of_line()..self.position];
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        let unit = match (indent.starts_with('\t'), self.indent) {
+            (true, _) | (false, Some(Indent::Tabs)) => String::from("\t"),
+            (false, Some(Indent::Spaces(width))) => " ".repeat(width),
+            (false, None) => String::from("    "),
+        };
+        let opened = line.trim_end().chars().last().and_then(closing_bracket);
+        let mut insert = format!("\n{indent}");
+        let mut position = self.position + insert.len();
+        if let Some(close) = opened {
+            insert.push_str(&unit);
+            position += unit.len();
+            if self.content[self.position..].starts_with(close) {
+                insert.push_str(&format!("\n{indent}"));
+            }
+        }
+        self.splice(self.position, 0, &insert);
+        self.position = position;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+    }
+
+    /// Start a new line after the current one, indented like it if `indent` is set
+    pub fn open_line_below(&mut self, indent: bool) {
+        if self.readonly {
+            return;
+        }
+        self.goto_end_of_line();
+        match indent {
+            true => self.insert_newline_indented(),
+            false => self.insert('\n'),
+        }
+    }
+
+    /// Start a new line before the current one, indented like it if `indent` is set
+    pub fn open_line_above(&mut self, indent: bool) {
+        if self.readonly {
+            return;
+        }
+        let start = self.start_of_line();
+        let line = &self.content[start..];
+        let indent = match indent {
+            true => &line[..line.len() - line.trim_start_matches([' ', '\t']).len()],
+            false => "",
+        };
+        let position = start + indent.len();
+        self.content.insert_str(start, &format!("{indent}\n"));
+        self.position = position;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+    }
+
+    /// Give a line that only has whitespace before the cursor
+    /// the indentation of the line with the bracket that `close` closes.
+    pub fn dedent_closing_bracket(&mut self, close: char) {
+        let start = self.start_of_line();
+        if self.readonly || !self.content[start..self.position].trim().is_empty() {
+            return;
+        }
+        let mut depth = 0;
+        let opener = self.content[..start].char_indices().rev().find(|(_, c)| {
+            if *c == close {
+                depth += 1;
+            } else if closing_bracket(*c) == Some(close) {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        });
+        if let Some((opener, _)) = opener {
+            let opener_line = self.start_of_line_at(opener);
+            let line = &self.content[opener_line..];
+            let indent = line[..line.len() - line.trim_start_matches([' ', '\t']).len()].to_owned();
+            self.splice(start, self.position - start, &indent);
+            self.position = start + indent.len();
+            self.parse_cache.invalidate_from(self.top);
+        }
+    }
+
+    /// Delete from the cursor to the end of the line.
+    /// At the end of a line the newline is deleted instead.
+    /// Returns the deleted text.
+    pub fn delete_to_end_of_line(&mut self) -> Option<String> {
+        if self.readonly {
+            return None;
+        }
+        let end = match self.start_of_next_line() {
+            Some(start_of_next_line) if start_of_next_line - 1 == self.position => start_of_next_line,
+            Some(start_of_next_line) => start_of_next_line - 1,
+            None => self.content.len(),
+        };
+        if end == self.position {
+            return None;
+        }
+        let killed = self.content.drain(self.position..end).collect();
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+        Some(killed)
+    }
+
+    /// Join the line with the next one, the newline and the indentation
+    /// of the next line are replaced by a single space.
+    /// With a selection all selected lines are joined.
+    pub fn join_lines(&mut self) {
+        if self.readonly {
+            return;
+        }
+        let joins = match self.selection.take() {
+            Some((start, end)) => {
+                self.position = start;
+                // a selection ending on a newline doesn't include the next line
+                let selected = &self.content[start..end];
+                cmp::max(selected.strip_suffix('\n').unwrap_or(selected).matches('\n').count(), 1)
+            },
+            None => 1,
+        };
+        for _ in 0..joins {
+            let Some(newline) = self.content[self.position..].find('\n').map(|i| self.position + i) else {
+                break;
+            };
+            let next = &self.content[newline + 1..];
+            let indent = next.len() - next.trim_start_matches([' ', '\t']).len();
+            let line_end = self.content[..newline].trim_end_matches([' ', '\t']).len();
+            let next_empty = next[indent..].starts_with('\n') || next.len() == indent;
+            let separator = if line_end == self.start_of_line_at(newline) || next_empty { "" } else { " " };
+            self.content.replace_range(line_end..newline + 1 + indent, separator);
+            self.position = line_end;
+        }
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+    }
+
+    /// The byte range of the selected lines (or the current line) without the last newline
+    fn selected_lines(&self) -> (usize, usize) {
+        let (start, end) = self.selection.unwrap_or((self.position, self.position));
+        let start = self.start_of_line_at(start);
+        // a selection ending on a newline doesn't include the next line
+        let end = if end > start && self.content[..end].ends_with('\n') { end - 1 } else { end };
+        let end = self.content[end..].find('\n').map_or(self.content.len(), |i| end + i);
+        (start, end)
+    }
+
+    /// Comment the selected lines (or current line) with a line comment,
+    /// or uncomment them if they all are.
+    pub fn toggle_line_comment(&mut self, marker: &str) {
+        if self.readonly {
+            return;
+        }
+        let (start, end) = self.selected_lines();
+        let lines: Vec<&str> = self.content[start..end].split('\n').collect();
+        let indent = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+        let filled = || lines.iter().filter(|line| !line.trim().is_empty());
+        let commented = filled().all(|line| line.trim_start().starts_with(marker));
+        let min_indent = filled().map(|line| indent(line)).min().unwrap_or(0);
+        let toggled: Vec<String> = lines.iter().map(|line| {
+            if line.trim().is_empty() {
+                line.to_string()
+            } else if commented {
+                let rest = &line[indent(line) + marker.len()..];
+                format!("{}{}", &line[..indent(line)], rest.strip_prefix(' ').unwrap_or(rest))
+            } else {
+                format!("{}{marker} {}", &line[..min_indent], &line[min_indent..])
+            }
+        }).collect();
+        self.replace_lines(start, end, toggled.join("\n"));
+    }
+
+    /// Pad the selected lines so the first `target` in each of them lands in the same column.
+    /// Lines without it are left alone. Returns the amount of lines changed.
+    pub fn align_selection_on(&mut self, target: &str) -> usize {
+        if self.readonly || target.is_empty() {
+            return 0;
+        }
+        let (start, end) = self.selected_lines();
+        let lines: Vec<&str> = self.content[start..end].split('\n').collect();
+        let column = |line: &str| line.find(target).map(|i| (i, wrap::display_width(&line[..i])));
+        let Some(max) = lines.iter().filter_map(|line| column(line)).map(|(_, col)| col).max() else {
+            return 0;
+        };
+        let mut changed = 0;
+        let aligned: Vec<String> = lines.iter().map(|line| match column(line) {
+            Some((i, col)) if col < max => {
+                changed += 1;
+                format!("{}{}{}", &line[..i], " ".repeat(max - col), &line[i..])
+            },
+            _ => line.to_string(),
         }).collect();
+        if changed > 0 {
+            self.replace_lines(start, end, aligned.join("\n"));
+        }
+        changed
+    }
+
+    /// Remove ANSI escape sequences from the selection (or whole buffer).
+    /// Returns the amount of bytes removed.
+    pub fn strip_ansi(&mut self) -> usize {
+        if self.readonly {
+            return 0;
+        }
+        let (start, end) = self.selection.unwrap_or((0, self.content.len()));
+        let stripped = transform::strip_ansi(&self.content[start..end]);
+        let removed = (end - start) - stripped.len();
+        if removed == 0 {
+            return 0;
+        }
+        // keep the cursor on the same character, or before the sequence it was in
+        let mut position = self.position.clamp(start, end);
+        let mut before = transform::strip_ansi(&self.content[start..position]);
+        while !stripped.starts_with(&before) {
+            position = self.content[..position].char_indices().next_back().map_or(start, |(i, _)| i);
+            before = transform::strip_ansi(&self.content[start..position]);
+        }
+        let position = start + before.len();
+        self.content.replace_range(start..end, &stripped);
+        if self.selection.is_some() {
+            self.selection = Some((start, start + stripped.len()));
+        }
+        self.position = position + self.position.saturating_sub(end);
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+        removed
+    }
+
+    /// Wrap the selection (or current line) in a block comment,
+    /// or unwrap it if it already is.
+    pub fn toggle_block_comment(&mut self, open: &str, close: &str) {
+        if self.readonly {
+            return;
+        }
+        let (start, end) = match self.selection {
+            Some(selection) => selection,
+            None => self.selected_lines(),
+        };
+        let text = &self.content[start..end];
+        let trimmed = text.trim();
+        let toggled = match trimmed.strip_prefix(open).and_then(|t| t.strip_suffix(close)) {
+            Some(inner) => {
+                let leading = &text[..text.len() - text.trim_start().len()];
+                let trailing = &text[text.trim_end().len()..];
+                let inner = inner.strip_prefix(' ').unwrap_or(inner);
+                let inner = inner.strip_suffix(' ').unwrap_or(inner);
+                format!("{leading}{inner}{trailing}")
+            },
+            None => {
+                let leading = &text[..text.len() - text.trim_start().len()];
+                let trailing = &text[text.trim_end().len()..];
+                format!("{leading}{open} {trimmed} {close}{trailing}")
+            },
+        };
+        self.replace_lines(start, end, toggled);
+    }
+
+    /// Replace a range, keeping the cursor and selection on it
+    fn replace_lines(&mut self, start: usize, end: usize, replacement: String) {
+        let len = replacement.len();
+        self.content.replace_range(start..end, &replacement);
+        if self.selection.is_some() {
+            self.selection = Some((start, start + len));
+        }
+        self.position = cmp::min(self.position, start + len);
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+    }
+
+    /// Rewrite the leading indentation of space indented lines from `from` to `to` spaces per level.
+    /// Tabs are a level on their own so they are kept, as are spaces that don't make up a level.
+    /// Returns the amount of lines changed.
+    pub fn retab(&mut self, from: usize, to: usize) -> usize {
+        if self.readonly || from == 0 {
+            return 0;
+        }
+        let mut changed = 0;
+        let mut offset = 0;
+        let mut position = None;
+        let mut content = String::with_capacity(self.content.len());
+        for line in self.content.split_inclusive('\n') {
+            let tabs = line.len() - line.trim_start_matches('\t').len();
+            let spaces = line[tabs..].len() - line[tabs..].trim_start_matches(' ').len();
+            let indent = format!("{}{}", "\t".repeat(tabs), " ".repeat(spaces / from * to + spaces % from));
+            // keep the cursor on the same character
+            if position.is_none() && self.position < offset + line.len() {
+                let column = self.position - offset;
+                position = Some(content.len() + match column >= tabs + spaces {
+                    true => column - (tabs + spaces) + indent.len(),
+                    false => cmp::min(column, indent.len()),
+                });
+            }
+            if indent.len() != tabs + spaces {
+                changed += 1;
+            }
+            content.push_str(&indent);
+            content.push_str(&line[tabs + spaces..]);
+            offset += line.len();
+        }
+        let position = position.unwrap_or(content.len());
+        self.content = content;
+        self.position = position;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(0);
+        changed
+    }
+
+    /// Delete from the start of the line to the cursor.
+    /// Returns the deleted text.
+    pub fn delete_to_start_of_line(&mut self) -> Option<String> {
+        let start = self.start_of_line();
+        if self.readonly || start == self.position {
+            return None;
+        }
+        let killed = self.content.drain(start..self.position).collect();
+        self.position = start;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+        Some(killed)
+    }
+
+    /// The grapheme before the cursor and its byte offset
+    fn prev_grapheme(&self) -> Option<(usize, &str)> {
+        self.content.get(..self.position)?.grapheme_indices(true).next_back()
+    }
+
+    /// The grapheme under the cursor and its byte offset
+    fn cur_grapheme(&self) -> Option<(usize, &str)> {
+        self.content.get(self.position..)?.grapheme_indices(true).next()
+            .map(|(i, g)| (self.position + i, g))
+    }
+
+    /// Swap the graphemes around the cursor and move past them.
+    /// At the end of a line the two graphemes before the cursor are swapped.
+    pub fn transpose_graphemes(&mut self) {
+        if self.readonly {
+            return;
+        }
+        let (first, second) = match self.cur_grapheme() {
+            None | Some((_, "\n")) => {
+                let Some(last) = self.prev_grapheme() else { return };
+                (self.content[..last.0].grapheme_indices(true).next_back(), Some(last))
+            },
+            cur => (self.prev_grapheme(), cur),
+        };
+        let (Some((start, prev)), Some((cur_start, cur))) = (first, second) else { return };
+        if prev == "\n" || cur == "\n" {
+            return;
+        }
+        let end = cur_start + cur.len();
+        let swapped = format!("{cur}{prev}");
+        self.content.replace_range(start..end, &swapped);
+        self.position = end;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+    }
+
+    /// Swap the word at or before the cursor with the word after it.
+    /// Words consist of alphanumerics and any of `word_chars`.
+    pub fn transpose_words(&mut self, word_chars: &str) {
+        if self.readonly {
+            return;
+        }
+        let mut words: Vec<(usize, usize)> = vec![];
+        let mut word_start = None;
+        for (i, c) in self.content.char_indices().chain([(self.content.len(), ' ')]) {
+            match (is_word_char(c, word_chars) && i < self.content.len(), word_start) {
+                (true, None) => word_start = Some(i),
+                (false, Some(start)) => {
+                    word_start = None;
+                    words.push((start, i));
+                },
+                _ => {},
+            }
+        }
+        // the word the cursor is in or else the last one before it
+        let Some(first) = words.iter().rposition(|(start, _)| *start < self.position) else { return };
+        let (Some(&(start1, end1)), Some(&(start2, end2))) = (words.get(first), words.get(first + 1)) else { return };
+        let swapped = format!("{}{}{}", &self.content[start2..end2], &self.content[end1..start2], &self.content[start1..end1]);
+        self.content.replace_range(start1..end2, &swapped);
+        self.position = end2;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+    }
+
+    /// Select the entire buffer
+    pub fn select_all(&mut self) {
+        self.selection = Some((0, self.content.len()));
+        self.position = self.content.len();
+        self.prefered_col = None;
+    }
+
+    /// Wrap the selection in a pair of characters, the selection stays on the original text
+    pub fn surround_selection(&mut self, open: char, close: char) {
+        let Some((start, end)) = self.selection else { return };
+        if self.readonly {
+            return;
+        }
+        // insert the end first so the start stays valid
+        self.splice(end, 0, close.encode_utf8(&mut [0; 4]));
+        self.splice(start, 0, open.encode_utf8(&mut [0; 4]));
+        let shift = open.len_utf8();
+        self.selection = Some((start + shift, end + shift));
+        self.position = end + shift;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+    }
+
+    /// Replace the selection with `f` applied to it, the result stays selected
+    pub fn transform_selection(&mut self, f: impl Fn(&str) -> String) {
+        let Some((start, end)) = self.selection else { return };
+        if self.readonly {
+            return;
+        }
+        let transformed = f(&self.content[start..end]);
+        self.content.replace_range(start..end, &transformed);
+        self.selection = Some((start, start + transformed.len()));
+        self.position = start + transformed.len();
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(self.top);
+    }
+
+    /// Revert the last group of changes, returns if there was one.
+    /// See `jump_after_undo` for `keep_cursor`.
+    pub fn undo(&mut self, keep_cursor: Option<usize>) -> bool {
+        if self.readonly {
+            return false;
+        }
+        let before = self.content.clone();
+        let Some(position) = self.undo.undo(&mut self.content) else { return false };
+        self.jump_after_undo(&before, position, keep_cursor);
+        true
+    }
+
+    /// Apply the last undone group of changes again, returns if there was one.
+    /// See `jump_after_undo` for `keep_cursor`.
+    pub fn redo(&mut self, keep_cursor: Option<usize>) -> bool {
+        if self.readonly {
+            return false;
+        }
+        let before = self.content.clone();
+        let Some(position) = self.undo.redo(&mut self.content) else { return false };
+        self.jump_after_undo(&before, position, keep_cursor);
+        true
+    }
+
+    /// Move the cursor to where the changes were made.
+    /// With `keep_cursor` (the height of the viewport) the cursor stays
+    /// where it was instead if all changed lines are in view.
+    fn jump_after_undo(&mut self, before: &str, mut position: usize, keep_cursor: Option<usize>) {
+        if let (Some(height), Some(change)) = (keep_cursor, Change::between(before, &self.content, self.position, position)) {
+            let first = self.content[..change.at].matches('\n').count();
+            let last = first + change.inserted.matches('\n').count();
+            if first >= self.top && last < self.top + height {
+                position = change.shift(self.position);
+            }
+        }
+        self.position = cmp::min(position, self.content.len());
+        self.selection = None;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(0);
+        self.refresh_highlights();
+    }
+
+    pub fn selected_text(&self) -> Option<&str> {
+        self.selection.map(|(start, end)| &self.content[start..end])
+    }
+
+    /// Remove the selected text, returns if there was a selection
+    pub fn delete_selection(&mut self) -> bool {
+        if self.readonly {
+            return false;
+        }
+        match self.selection.take() {
+            Some((start, end)) => {
+                self.splice(start, end - start, "");
+                self.position = start;
+                self.prefered_col = None;
+                self.parse_cache.invalidate_from(self.top);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Delete the character before the cursor
+    pub fn backspace(&mut self) {
+        if !self.readonly && self.position > 0 {
+            let Some(removed) = self.content[..self.position].chars().next_back() else { return };
+            self.position -= removed.len_utf8();
+            self.splice(self.position, removed.len_utf8(), "");
+            self.prefered_col = None;
+            self.parse_cache.invalidate_from(self.top);
+        }
+    }
+
+    /// Delete the character under the cursor
+    pub fn delete(&mut self) {
+        if !self.readonly && self.position < self.content.len() {
+            let len = self.content[self.position..].chars().next().map_or(0, char::len_utf8);
+            self.splice(self.position, len, "");
+            self.parse_cache.invalidate_from(self.top);
+        }
+    }
+
+    pub fn find(&mut self, query: String, case: Case) {
+        self.query = Some(query);
+        self.case = case;
+        self.refresh_highlights();
 
         // scroll to first match
-        if let Some((start, _end)) = matches.iter().find(|(start, _end)| start >= &self.position) {
+        if let Some((start, _end)) = self.highlights.iter().find(|(start, _end)| start >= &self.position) {
             self.position = *start
         }
+    }
+
+    /// Move to the next search result after the cursor, wrapping around to the first
+    pub fn jump_next_highlight(&mut self) {
+        let next = self.highlights.iter().find(|(start, _)| *start > self.position).or(self.highlights.first());
+        if let Some((start, _)) = next {
+            self.position = *start;
+            self.prefered_col = None;
+        }
+    }
+
+    /// Replace the search results starting in `starts` with `replacement`, the cursor stays on the same text.
+    /// Returns how many were replaced.
+    pub fn replace_highlights(&mut self, starts: impl RangeBounds<usize>, replacement: &str) -> usize {
+        if self.readonly {
+            return 0;
+        }
+        let highlights: Vec<(usize, usize)> = std::mem::take(&mut self.highlights).into_iter()
+            .filter(|(start, _)| starts.contains(start))
+            .collect();
+        for (start, end) in highlights.iter().rev() {
+            self.content.replace_range(start..end, replacement);
+            if self.position >= *end {
+                self.position = self.position - (end - start) + replacement.len();
+            } else if self.position > *start {
+                self.position = *start;
+            }
+        }
+        self.selection = None;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(0);
+        self.refresh_highlights();
+        highlights.len()
+    }
+
+    /// Move to the previous search result before the cursor, wrapping around to the last
+    pub fn jump_previous_highlight(&mut self) {
+        let previous = self.highlights.iter().rev().find(|(start, _)| *start < self.position).or(self.highlights.last());
+        if let Some((start, _)) = previous {
+            self.position = *start;
+            self.prefered_col = None;
+        }
+    }
 
-        self.highlights = matches;
+    /// Search for the last query again (after the content changed)
+    pub fn refresh_highlights(&mut self) {
+        self.highlights = match self.query.as_deref() {
+            Some(query) if !query.is_empty() => find_matches(&self.content, query, self.case),
+            _ => vec![],
+        };
     }
 
     // Tries to find and set a syntax
     pub fn find_syntax<'a>(&mut self, syntax_set: &'a SyntaxSet) -> Option<&'a SyntaxReference> {
         let extension = self.name.split('.').last().unwrap_or("");
-        let syntax = match syntax_set.find_syntax_by_extension(extension) {
-            Some(syntax) => Some(syntax),
-            None => {
-                match self.content.lines().next() {
-                    Some(first_line) => syntax_set.find_syntax_by_first_line(&first_line),
-                    None => None,
-                }
-            },
-        };
+        // a modeline is explicit so it wins over the extension
+        let syntax = modeline_syntax(&self.content).and_then(|name| syntax_set.find_syntax_by_token(name))
+            .or_else(|| syntax_set.find_syntax_by_extension(extension))
+            .or_else(|| syntax_set.find_syntax_by_first_line(self.content.lines().next()?))
+            .or_else(|| syntax_set.find_syntax_by_token(shebang_syntax(&self.content)?));
         if let Some(syntax) = syntax {
             self.syntax = Some(syntax.clone());
         }
@@ -303,25 +1115,81 @@ impl Buffer {
             return Err(io::Error::other("Buffer is readonly"))
         }
         if self.opened_readonly {
-            return Err(io::Error::other("No write permission to file"))
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "No write permission to file"))
+        }
+        if self.scratch {
+            return Err(io::Error::other("Scratch buffers are not saved"))
         }
         if self.file.is_none() {
             let file = File::options().create(true).write(true).open(self.name.clone())?;
             self.file = Some(Arc::new(Mutex::new(file)));
         }
+        let bytes = encoding::encode(&self.content, self.encoding, self.bom)?;
         let binding = self.file.clone().unwrap();
         let mut file = binding.lock().unwrap();
         file.rewind()?;
-        file.write_all(self.content.as_bytes())?;
-        file.set_len(self.content.len() as u64)?;
+        file.write_all(&bytes)?;
+        file.set_len(bytes.len() as u64)?;
 
-        info!("Wrote {} bytes to {}", self.content.as_bytes().len(), self.name);
+        self.restore_metadata(&file)?;
+        self.dirty_cache.set(Some((self.undo.generation(), self.content.len(), false)));
+
+        match &self.symlink {
+            Some(target) => info!("Wrote {} bytes to {} (via {})", bytes.len(), target.display(), self.name),
+            None => info!("Wrote {} bytes to {}", bytes.len(), self.name),
+        }
 
         Ok(())
     }
 
+    /// Write the selection to a new file in the encoding of this buffer.
+    /// The file only appears once it is completely written, an existing file is never replaced.
+    /// Returns the amount of bytes written.
+    pub fn write_selection(&self, path: &Path) -> io::Result<usize> {
+        let selection = self.selected_text().ok_or_else(|| io::Error::other("Nothing is selected"))?;
+        let bytes = encoding::encode(selection, self.encoding, false)?;
+        let name = path.file_name().ok_or_else(|| io::Error::other("Not a file name"))?;
+        // written next to the new file, so it can be linked into place
+        let temp = path.with_file_name(format!(".{}.atto-{}", name.to_string_lossy(), process::id()));
+        let mut file = File::options().write(true).create_new(true).open(&temp)?;
+        let written = file.write_all(&bytes).and_then(|_| file.sync_all()).and_then(|_| {
+            // unlike renaming, linking fails if the file exists
+            match std::fs::hard_link(&temp, path) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists && !path.try_exists()? => std::fs::rename(&temp, path),
+                result => result,
+            }
+        });
+        if let Err(e) = std::fs::remove_file(&temp) {
+            debug!("Could not remove {}: {e}", temp.display());
+        }
+        written?;
+        info!("Wrote {} bytes of the selection in {} to {}", bytes.len(), self.name, path.display());
+        Ok(bytes.len())
+    }
+
+    /// Give the file the mode and owner it had when it was opened.
+    /// Changing the owner is only attempted and may not be permitted.
+    fn restore_metadata(&self, file: &File) -> io::Result<()> {
+        let Some(original) = &self.metadata else {
+            return Ok(());
+        };
+        let current = file.metadata()?;
+        if current.mode() != original.mode() {
+            debug!("Restoring mode {:o} of {}", original.mode(), self.name);
+            file.set_permissions(original.permissions())?;
+        }
+        if current.uid() != original.uid() || current.gid() != original.gid() {
+            if let Err(e) = std::os::unix::fs::fchown(file, Some(original.uid()), Some(original.gid())) {
+                debug!("Could not restore the owner of {}: {e}", self.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// dd writes into the existing file, so its mode and owner are kept
     #[tracing::instrument(skip(self), level="debug")]
     pub fn save_as_root(&mut self) -> io::Result<()> {
+        let bytes = encoding::encode(&self.content, self.encoding, self.bom)?;
         let (reader, mut writer) = std::pipe::pipe()?;
         let mut dd = process::Command::new(PRIVESC_CMD)
             .args(vec!["dd", "bs=4k", &format!("of={}", self.name)])
@@ -329,12 +1197,15 @@ impl Buffer {
             .stdout(Stdio::null())
             .stderr(Stdio::piped())
             .spawn()?;
-        writer.write_all(self.content.as_bytes())?;
+        writer.write_all(&bytes)?;
         writer.flush()?;
         nix::unistd::close(writer.into_raw_fd())?;
         let status = dd.wait()?;
         match status.success() {
-            true => Ok(()),
+            true => {
+                self.dirty_cache.set(None);
+                Ok(())
+            },
             false => {
                 let mut stderr = String::new();
                 dd.stderr.unwrap().read_to_string(&mut stderr)?;
@@ -343,25 +1214,923 @@ impl Buffer {
         }
     }
 
+    /// Append what was written to the file since it was read.
+    /// A cursor at the end of the buffer stays there.
+    /// Returns if anything was appended.
+    pub fn read_appended(&mut self) -> io::Result<bool> {
+        let Some(file) = &self.file else {
+            return Ok(false);
+        };
+        let mut appended = std::mem::take(&mut self.partial);
+        {
+            let mut file = file.lock().unwrap();
+            // a truncated (or rotated) file is followed from its start again
+            if file.metadata()?.len() < self.file_offset {
+                self.file_offset = 0;
+                appended.clear();
+            }
+            file.seek(io::SeekFrom::Start(self.file_offset))?;
+            self.file_offset += file.read_to_end(&mut appended)? as u64;
+        }
+        // a character that is only partly written yet is appended once it is complete
+        self.partial = appended.split_off(complete_utf8_len(&appended));
+        if appended.is_empty() {
+            return Ok(false);
+        }
+        let at_end = self.position == self.content.len();
+        self.content.push_str(&String::from_utf8_lossy(&appended));
+        if at_end {
+            self.position = self.content.len();
+        }
+        Ok(true)
+    }
+
+    /// The name to show for this buffer
+    pub fn label(&self) -> &str {
+        if self.scratch && self.name.is_empty() { "[Scratch]" } else { &self.name }
+    }
+
     pub fn dirty(&self) -> io::Result<bool> {
+        if self.scratch {
+            return Ok(false);
+        }
         match &self.file {
             Some(file) => {
-                let mut filecontent = String::new();
+                let mut filecontent = vec![];
                 let mut file = file.lock().unwrap();
                 file.rewind()?;
-                file.read_to_string(&mut filecontent)?;
-                Ok(filecontent != self.content)
+                file.read_to_end(&mut filecontent)?;
+                // content that can't be encoded can't be on disk either
+                Ok(encoding::encode(&self.content, self.encoding, self.bom).map_or(true, |bytes| bytes != filecontent))
             },
-            None => Ok(self.content.is_empty()),
+            // text that was typed but has nowhere to be saved yet
+            None => Ok(!self.content.is_empty()),
+        }
+    }
+
+    /// Whether there are unsaved changes, like `dirty` but the file is only read again
+    /// after the buffer changed (or was saved). Errors count as unsaved changes.
+    pub fn unsaved(&self) -> bool {
+        let (generation, len) = (self.undo.generation(), self.content.len());
+        if let Some((_, _, dirty)) = self.dirty_cache.get().filter(|(g, l, _)| (*g, *l) == (generation, len)) {
+            return dirty;
+        }
+        let dirty = self.dirty().unwrap_or_else(|e| {
+            tracing::error!("{:?}", e);
+            true
+        });
+        self.dirty_cache.set(Some((generation, len, dirty)));
+        dirty
+    }
+
+    /// Fit a pasted block to the indentation of the current line
+    pub fn reindent_paste(&self, content: &str) -> String {
+        let start = self.start_of_line();
+        let line = &self.content[start..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        let at_indent = self.content[start..self.position].trim().is_empty();
+        reindent(content, indent, at_indent)
+    }
+
+    /// The name of the syntax, plain text if there is none
+    pub fn syntax_name(&self) -> &str {
+        self.syntax.as_ref().map_or("Plain Text", |syntax| syntax.name.as_str())
+    }
+
+    /// Replace the whole content (like with a formatted version of it),
+    /// keeping the cursor on the same line and column if they still exist
+    pub fn replace_content(&mut self, content: String) {
+        if self.readonly {
+            return;
+        }
+        let line = self.content[..self.position].matches('\n').count();
+        let (column, _) = self.line_offsets();
+        self.content = content;
+        let start = self.content.split_inclusive('\n').take(line).map(str::len).sum::<usize>();
+        let end = self.content[start..].find('\n').map_or(self.content.len(), |i| start + i);
+        let mut position = cmp::min(start + column, end);
+        while !self.content.is_char_boundary(position) {
+            position -= 1;
         }
+        self.position = position;
+        self.selection = None;
+        self.prefered_col = None;
+        self.parse_cache.invalidate_from(0);
     }
 
     pub fn paste(&mut self, content: &str) {
         if !self.readonly {
             self.prefered_col = None;
-            self.content.insert_str(self.position, content);
+            self.splice(self.position, 0, content);
             self.position += content.len();
+            self.parse_cache.invalidate_from(self.top);
+        }
+    }
+
+}
+
+/// The line comment and block comment markers of a syntax
+pub fn comment_markers(syntax: &str) -> (Option<&'static str>, Option<(&'static str, &'static str)>) {
+    match syntax {
+        "Rust" | "C" | "C++" | "C#" | "Java" | "JavaScript" | "TypeScript" | "TypeScriptReact"
+        | "Go" | "Scala" | "Objective-C" | "Objective-C++" | "PHP" | "D" | "Groovy" => (Some("//"), Some(("/*", "*/"))),
+        "Python" | "Ruby" | "Perl" | "Bourne Again Shell (bash)" | "Shell-Unix-Generic" | "Makefile"
+        | "YAML" | "TOML" | "R" | "Graphviz (DOT)" => (Some("#"), None),
+        "SQL" => (Some("--"), Some(("/*", "*/"))),
+        "Lua" => (Some("--"), Some(("--[[", "]]"))),
+        "Haskell" => (Some("--"), Some(("{-", "-}"))),
+        "LaTeX" | "TeX" | "Erlang" | "MATLAB" => (Some("%"), None),
+        "Lisp" | "Clojure" => (Some(";"), None),
+        "CSS" => (None, Some(("/*", "*/"))),
+        "HTML" | "XML" | "Markdown" => (None, Some(("<!--", "-->"))),
+        _ => (None, None),
+    }
+}
+
+/// The length of `bytes` without an incomplete UTF-8 sequence at its end
+fn complete_utf8_len(bytes: &[u8]) -> usize {
+    for back in 1..=cmp::min(3, bytes.len()) {
+        let len = match bytes[bytes.len() - back] {
+            0x80..=0xBF => continue,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        return if len > back { bytes.len() - back } else { bytes.len() };
+    }
+    bytes.len()
+}
+
+/// Replace the indentation that all lines of a pasted block share with `indent`.
+/// The first line goes where the cursor is, so it is only stripped if `strip_first` is set
+/// and it doesn't count towards the shared indentation.
+pub fn reindent(text: &str, indent: &str, strip_first: bool) -> String {
+    let leading = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    let common = text.split('\n').skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(leading)
+        .min()
+        .unwrap_or(0);
+    text.split('\n').enumerate().map(|(i, line)| {
+        if i == 0 {
+            if strip_first { line.trim_start_matches([' ', '\t']).to_owned() } else { line.to_owned() }
+        } else if line.trim().is_empty() {
+            String::new()
+        } else {
+            format!("{indent}{}", &line[common..])
         }
+    }).collect::<Vec<_>>().join("\n")
+}
+
+/// The bracket that closes an opening bracket
+fn closing_bracket(open: char) -> Option<char> {
+    match open {
+        '{' => Some('}'),
+        '(' => Some(')'),
+        '[' => Some(']'),
+        _ => None,
     }
+}
+
+/// How far paging scrolls, at least a line
+fn page_step(height: usize, overlap: usize) -> usize {
+    cmp::max(height.saturating_sub(overlap), 1)
+}
+
+/// What a level of indentation is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indent {
+    Tabs,
+    Spaces(usize),
+}
 
-}
\ No newline at end of file
+/// How many non-blank lines are looked at to detect the indentation
+const INDENT_DETECTION_LINES: usize = 1000;
+
+/// How `content` is indented, judged by its first lines: tabs if more lines start with a tab than with spaces,
+/// otherwise the most common difference between the indentation of consecutive lines.
+/// Differences of one space don't count, they are mostly the ` *` of block comments.
+pub fn detect_indentation(content: &str) -> Option<Indent> {
+    let (mut tabs, mut spaces) = (0, 0);
+    let mut steps: HashMap<usize, usize> = HashMap::new();
+    let mut previous = 0;
+    for line in content.lines().filter(|line| !line.trim().is_empty()).take(INDENT_DETECTION_LINES) {
+        if line.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        let width = line.len() - line.trim_start_matches(' ').len();
+        if width > 0 {
+            spaces += 1;
+        }
+        let step = width.abs_diff(previous);
+        if step > 1 {
+            *steps.entry(step).or_default() += 1;
+        }
+        previous = width;
+    }
+    if tabs > spaces {
+        return Some(Indent::Tabs);
+    }
+    // ties go to the smaller step
+    steps.into_iter()
+        .max_by_key(|(step, count)| (*count, cmp::Reverse(*step)))
+        .map(|(step, _)| Indent::Spaces(step))
+}
+
+/// How searching matches upper and lower case
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Case {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the query has upper case letters (like vim's smartcase)
+    Smart,
+}
+
+impl Case {
+    /// If `query` matches regardless of case
+    fn ignored(self, query: &str) -> bool {
+        match self {
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+            Case::Smart => !query.chars().any(char::is_uppercase),
+        }
+    }
+}
+
+/// The byte ranges of the non-overlapping matches of `query`
+pub fn find_matches(content: &str, query: &str, case: Case) -> Vec<(usize, usize)> {
+    if !case.ignored(query) {
+        return content.match_indices(query).map(|(start, match_)| (start, start + match_.len())).collect();
+    }
+    let same = |a: char, b: char| a == b || a.to_lowercase().eq(b.to_lowercase());
+    let mut matches = vec![];
+    let mut next = 0;
+    for (start, _) in content.char_indices() {
+        if start < next {
+            continue;
+        }
+        let mut chars = content[start..].char_indices();
+        let matched = query.chars().all(|q| chars.next().is_some_and(|(_, c)| same(c, q)));
+        if matched {
+            let end = chars.next().map_or(content.len(), |(i, _)| start + i);
+            matches.push((start, end));
+            next = end;
+        }
+    }
+    matches
+}
+
+/// If a character is part of a word, `word_chars` are considered part of words besides alphanumerics
+pub fn is_word_char(c: char, word_chars: &str) -> bool {
+    c.is_alphanumeric() || word_chars.contains(c)
+}
+
+/// If both characters are whitespace, part of a word or neither
+fn same_kind(a: char, b: char, word_chars: &str) -> bool {
+    match (a.is_whitespace(), b.is_whitespace()) {
+        (true, true) => true,
+        (false, false) => is_word_char(a, word_chars) == is_word_char(b, word_chars),
+        _ => false,
+    }
+}
+
+/// The lines at the top and bottom of a file that are checked for a modeline
+const MODELINE_LINES: usize = 5;
+
+/// The filetype set by a vim modeline (like `# vim: ft=rust`) near the top or bottom
+pub fn modeline_syntax(content: &str) -> Option<&str> {
+    let lines: Vec<&str> = content.lines().collect();
+    let bottom = lines.len().saturating_sub(MODELINE_LINES).max(MODELINE_LINES);
+    lines.iter().take(MODELINE_LINES).chain(lines.iter().skip(bottom)).find_map(|line| {
+        let (_, options) = line.split_once("vim:").or_else(|| line.split_once("vi:"))?;
+        options.split(|c: char| c == ':' || c.is_whitespace()).find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            matches!(key, "ft" | "filetype" | "syntax").then_some(value)
+        })
+    })
+}
+
+/// The interpreter named by a shebang (like `#!/usr/bin/env python3`)
+pub fn shebang_syntax(content: &str) -> Option<&str> {
+    let mut words = content.lines().next()?.strip_prefix("#!")?.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|word| !word.starts_with('-'))?;
+    }
+    // python3.11 -> python
+    let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    Some(match interpreter {
+        "node" | "nodejs" | "deno" => "js",
+        "runghc" | "runhaskell" => "hs",
+        "dash" | "ash" | "ksh" => "sh",
+        other => other,
+    })
+}
+
+/// A path in the temporary directory for a test, removed (with everything in it) when dropped
+#[cfg(test)]
+pub struct TempPath(PathBuf);
+
+#[cfg(test)]
+impl TempPath {
+    /// Nothing is created at the path yet
+    pub fn new(test: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("atto-{test}-test-{}", process::id())))
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = match self.0.is_dir() {
+            true => std::fs::remove_dir_all(&self.0),
+            false => std::fs::remove_file(&self.0),
+        };
+    }
+}
+
+/// Write `content` to a file and open it in a buffer
+#[cfg(test)]
+pub fn file_buffer(path: &Path, content: &str, readonly: bool) -> Buffer {
+    std::fs::write(path, content).unwrap();
+    let file = File::options().read(true).write(!readonly).open(path).unwrap();
+    Buffer::new(path.to_string_lossy().into_owned(), file, readonly, None).unwrap()
+}
+
+/// A buffer of a temporary file with `content`, the file is removed when the path is dropped
+#[cfg(test)]
+pub fn temp_buffer(test: &str, content: &str, readonly: bool) -> (Buffer, TempPath) {
+    let path = TempPath::new(test);
+    (file_buffer(&path, content, readonly), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(content: &str) -> Buffer {
+        let mut buffer = Buffer::empty();
+        buffer.content = content.to_owned();
+        buffer
+    }
+
+    #[test]
+    fn modeline() {
+        assert_eq!(modeline_syntax("# vim: ft=rust\nfn main() {}\n"), Some("rust"));
+        assert_eq!(modeline_syntax("a\nb\nc\nd\ne\nf\ng\n/* vim: set filetype=c : */\n"), Some("c"));
+        assert_eq!(modeline_syntax("a\nb\nc\nd\ne\n# vim: ft=rust\nf\ng\nh\ni\nj\nk\n"), None);
+        assert_eq!(modeline_syntax("# vim: ts=4\n"), None);
+    }
+
+    #[test]
+    fn shebang() {
+        assert_eq!(shebang_syntax("#!/bin/bash\n"), Some("bash"));
+        assert_eq!(shebang_syntax("#!/usr/bin/env python3.11\n"), Some("python"));
+        assert_eq!(shebang_syntax("#!/usr/bin/env -S node --flag\n"), Some("js"));
+        assert_eq!(shebang_syntax("echo hi\n"), None);
+    }
+
+    #[test]
+    fn caches_unsaved_state() {
+        let (mut buf, path) = temp_buffer("unsaved", "a", false);
+        assert!(!buf.unsaved());
+        // the file isn't read again while the buffer is unchanged
+        std::fs::write(&path, "b").unwrap();
+        assert!(!buf.unsaved());
+        buf.content.push('b');
+        assert!(buf.unsaved());
+        buf.save().unwrap();
+        assert!(!buf.unsaved());
+    }
+
+    #[test]
+    fn follows_appended_bytes() {
+        let (mut buf, path) = temp_buffer("follow", "\u{FEFF}start\n", true);
+        let mut append = |bytes: &[u8]| File::options().append(true).open(&path).unwrap().write_all(bytes).unwrap();
+        // the byte order mark isn't in the content but it is in the file
+        append("é".as_bytes());
+        assert!(buf.read_appended().unwrap());
+        assert_eq!(buf.content, "start\né");
+        // half of a character waits for the other half
+        append(&"€".as_bytes()[..1]);
+        assert!(!buf.read_appended().unwrap());
+        append(&"€".as_bytes()[1..]);
+        assert!(buf.read_appended().unwrap());
+        assert_eq!(buf.content, "start\né€");
+        std::fs::write(&path, "new\n").unwrap();
+        assert!(buf.read_appended().unwrap());
+        assert_eq!(buf.content, "start\né€new\n");
+    }
+
+    #[test]
+    fn writes_selection_to_a_new_file() {
+        let dir = TempPath::new("write-selection");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut buf = buffer("one two");
+        buf.selection = Some((4, 7));
+        assert_eq!(buf.write_selection(&dir.join("two")).unwrap(), 3);
+        assert_eq!(std::fs::read_to_string(dir.join("two")).unwrap(), "two");
+        buf.selection = Some((0, 3));
+        assert_eq!(buf.write_selection(&dir.join("two")).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(dir.join("two")).unwrap(), "two");
+        // only the written file is left
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn bom_round_trip() {
+        let original = "\u{FEFF}first\nsecond\n";
+        let (mut buf, path) = temp_buffer("bom", original, false);
+        assert_eq!(buf.content, "first\nsecond\n");
+        assert!(!buf.dirty().unwrap());
+        buf.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn select_all_covers_buffer() {
+        let mut buf = buffer("fn main() {\n    println!(\"hi\");\n}\n");
+        buf.select_all();
+        assert_eq!(buf.selected_text(), Some(buf.content.as_str()));
+        assert_eq!(buf.position, buf.content.len());
+        assert!(buf.delete_selection());
+        assert!(buf.content.is_empty());
+        assert_eq!(buf.selection, None);
+    }
+
+    #[test]
+    fn surround_selection() {
+        let mut buf = buffer("let x = a + b;");
+        buf.selection = Some((8, 13));
+        buf.surround_selection('(', ')');
+        assert_eq!(buf.content, "let x = (a + b);");
+        assert_eq!(buf.selected_text(), Some("a + b"));
+    }
+
+    #[test]
+    fn move_at_line_edges() {
+        let mut buf = buffer("ab\ncd");
+        buf.position = 2;
+        buf.move_right(false);
+        assert_eq!(buf.position, 2);
+        buf.move_right(true);
+        assert_eq!(buf.position, 3);
+        buf.move_left(false);
+        assert_eq!(buf.position, 3);
+        buf.move_left(true);
+        assert_eq!(buf.position, 2);
+        // the start and end of the buffer are edges too
+        buf.position = 0;
+        buf.move_left(true);
+        assert_eq!(buf.position, 0);
+        buf.position = 5;
+        buf.move_right(true);
+        assert_eq!(buf.position, 5);
+    }
+
+    #[test]
+    fn move_over_graphemes() {
+        let mut buf = buffer("ae\u{301}\r\nx");
+        buf.position = 1;
+        buf.move_right(false);
+        assert_eq!(buf.position, 4);
+        // a carriage return and newline are passed together
+        buf.move_right(true);
+        assert_eq!(buf.position, 6);
+        buf.move_left(true);
+        buf.move_left(true);
+        assert_eq!(buf.position, 1);
+        buf.backspace();
+        assert_eq!(buf.content, "e\u{301}\r\nx");
+    }
+
+    #[test]
+    fn move_words_in_accented_text() {
+        // "é" and "ö" take two bytes so byte and char offsets differ
+        let mut buf = buffer("héllo, wörld\nnext");
+        buf.move_word_right("_");
+        assert_eq!(buf.position, 6);
+        buf.move_word_right("_");
+        assert_eq!(buf.position, 7);
+        buf.move_word_right("_");
+        assert_eq!(buf.position, 8);
+        buf.move_word_right("_");
+        assert_eq!(buf.position, 14);
+        // the end of the line stops the jump
+        buf.move_word_right("_");
+        assert_eq!(buf.position, 14);
+        buf.move_word_left("_");
+        assert_eq!(buf.position, 8);
+        buf.move_word_left("_");
+        assert_eq!(buf.position, 7);
+        buf.move_word_left("_");
+        buf.move_word_left("_");
+        assert_eq!(buf.position, 0);
+        buf.move_word_left("_");
+        assert_eq!(buf.position, 0);
+        // at the end of the buffer
+        buf.position = buf.content.len();
+        buf.move_word_right("_");
+        assert_eq!(buf.position, buf.content.len());
+        buf.move_word_left("_");
+        assert_eq!(buf.position, 15);
+    }
+
+    #[test]
+    fn reindent_pasted_block() {
+        let code = "\t\tif x {\n\t\t\ty();\n\n\t\t}";
+        assert_eq!(reindent(code, "    ", true), "if x {\n    \ty();\n\n    }");
+        // copied from the middle of a line
+        assert_eq!(reindent("x {\n      y\n    }", "  ", false), "x {\n    y\n  }");
+
+        let mut buf = buffer("fn f() {\n    \n}");
+        buf.position = 13;
+        assert_eq!(buf.reindent_paste("  a\n  b"), "a\n    b");
+    }
+
+    #[test]
+    fn replace_content_keeps_the_cursor() {
+        let mut buf = buffer("fn main(){\nlet  x=1;\n}");
+        buf.position = 18;
+        buf.replace_content(String::from("fn main() {\n    let x = 1;\n}\n"));
+        assert_eq!(buf.position, 19);
+        buf.position = buf.content.len();
+        buf.replace_content(String::from("short"));
+        assert_eq!(buf.position, 5);
+    }
+
+    #[test]
+    fn jump_between_highlights() {
+        let mut buf = buffer("a x b x c x");
+        buf.find(String::from("x"), Case::Sensitive);
+        assert_eq!(buf.position, 2);
+        buf.jump_next_highlight();
+        assert_eq!(buf.position, 6);
+        buf.jump_next_highlight();
+        buf.jump_next_highlight();
+        assert_eq!(buf.position, 2);
+        buf.jump_previous_highlight();
+        assert_eq!(buf.position, 10);
+        buf.jump_previous_highlight();
+        assert_eq!(buf.position, 6);
+    }
+
+    #[test]
+    fn smartcase() {
+        let content = "Foo foo FOO föö FÖÖ";
+        let texts = |case| find_matches(content, "foo", case).iter().map(|(s, e)| &content[*s..*e]).collect::<Vec<_>>();
+        assert_eq!(texts(Case::Sensitive), vec!["foo"]);
+        assert_eq!(texts(Case::Smart), vec!["Foo", "foo", "FOO"]);
+        let texts = |query, case| find_matches(content, query, case).iter().map(|(s, e)| &content[*s..*e]).collect::<Vec<_>>();
+        // upper case makes it case sensitive
+        assert_eq!(texts("Foo", Case::Smart), vec!["Foo"]);
+        assert_eq!(texts("FÖÖ", Case::Smart), vec!["FÖÖ"]);
+        assert_eq!(texts("föö", Case::Smart), vec!["föö", "FÖÖ"]);
+        // the explicit toggle wins
+        assert_eq!(texts("Foo", Case::Insensitive), vec!["Foo", "foo", "FOO"]);
+        assert_eq!(texts("oo", Case::Insensitive), vec!["oo", "oo", "OO"]);
+    }
+
+    #[test]
+    fn page_down_stops_at_the_end() {
+        // shorter than the viewport
+        let mut buf = buffer("one\ntwo");
+        buf.page_down(10, 1);
+        assert_eq!((buf.top, buf.cursor_pos()), (0, (0, 1)));
+        buf.page_down(10, 1);
+        assert_eq!((buf.top, buf.cursor_pos()), (0, (0, 1)));
+
+        // a line longer than the viewport
+        let content: Vec<String> = (0..11).map(|i| i.to_string()).collect();
+        let mut buf = buffer(&content.join("\n"));
+        buf.page_down(10, 1);
+        assert_eq!((buf.top, buf.cursor_pos()), (1, (0, 1)));
+        buf.page_down(10, 1);
+        assert_eq!((buf.top, buf.cursor_pos()), (1, (0, 10)));
+    }
+
+    #[test]
+    fn paging_overlaps() {
+        let content: Vec<String> = (0..30).map(|i| i.to_string()).collect();
+        let mut buf = buffer(&content.join("\n"));
+        buf.page_down(10, 2);
+        assert_eq!(buf.top, 8);
+        buf.page_down(10, 2);
+        assert_eq!(buf.top, 16);
+        buf.page_up(10, 2);
+        assert_eq!(buf.top, 8);
+        buf.page_up(10, 2);
+        buf.page_up(10, 2);
+        assert_eq!(buf.top, 0);
+        // an overlap as large as the page still moves
+        buf.page_down(10, 20);
+        assert_eq!(buf.top, 1);
+    }
+
+    #[test]
+    fn transform_selection() {
+        let mut buf = buffer("say hello");
+        buf.selection = Some((4, 9));
+        buf.transform_selection(|text| text.to_uppercase() + "!");
+        assert_eq!(buf.content, "say HELLO!");
+        assert_eq!(buf.selected_text(), Some("HELLO!"));
+    }
+
+    #[test]
+    fn save_keeps_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = TempPath::new("mode");
+        std::fs::write(&path, "secret\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let file = File::options().read(true).write(true).open(&path).unwrap();
+        let mut buf = Buffer::new(path.to_string_lossy().into_owned(), file, false, None).unwrap();
+        buf.content.push_str("more\n");
+        // something else loosened the mode while editing
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        buf.save().unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn line_offsets() {
+        let mut buf = buffer("a\n\té🦀x\n");
+        buf.position = 2 + 1 + 2 + 4;
+        assert_eq!(buf.line_offsets(), (7, 3));
+    }
+
+    #[test]
+    fn join_lines() {
+        let mut buf = buffer("foo  \n    bar\nbaz\n\nqux");
+        buf.position = 1;
+        buf.join_lines();
+        assert_eq!(buf.content, "foo bar\nbaz\n\nqux");
+        assert_eq!(buf.position, 3);
+        buf.selection = Some((4, 13));
+        buf.join_lines();
+        assert_eq!(buf.content, "foo bar baz\nqux");
+        buf.position = 14;
+        buf.join_lines();
+        assert_eq!(buf.content, "foo bar baz\nqux");
+    }
+
+    #[test]
+    fn auto_indent() {
+        let mut buf = buffer("    if x {}");
+        buf.position = 10;
+        buf.insert_newline_indented();
+        assert_eq!(buf.content, "    if x {\n        \n    }");
+        assert_eq!(buf.position, 19);
+        buf.insert_newline_indented();
+        assert_eq!(buf.content, "    if x {\n        \n        \n    }");
+    }
+
+    #[test]
+    fn scrolls_to_padded_columns() {
+        let mut buf = buffer("aaaaaaaaaa,b\na,bbbbbbbb");
+        buf.delimiter = Some(',');
+        buf.position = buf.content.len();
+        assert_eq!(buf.display_column(10), 19);
+        buf.scroll_view_horizontally(10, 10);
+        assert_eq!(buf.left, 10);
+    }
+
+    #[test]
+    fn replaces_highlights() {
+        let mut buf = buffer("foo bar foo baz foo");
+        buf.find(String::from("foo"), Case::Sensitive);
+        buf.position = 12;
+        assert_eq!(buf.replace_highlights(.., "x"), 3);
+        assert_eq!(buf.content, "x bar x baz x");
+        assert_eq!(buf.position, 8);
+        assert!(buf.highlights.is_empty());
+        assert_eq!(buf.replace_highlights(.., "x"), 0);
+        buf.find(String::from("x"), Case::Sensitive);
+        assert_eq!(buf.replace_highlights(6.., "y"), 2);
+        assert_eq!(buf.content, "x bar y baz y");
+    }
+
+    #[test]
+    fn detects_indentation() {
+        assert_eq!(detect_indentation("fn main() {\n\tif x {\n\t\ty\n\t}\n}\n"), Some(Indent::Tabs));
+        assert_eq!(detect_indentation("a:\n  b:\n    c: 1\n  d: 2\n"), Some(Indent::Spaces(2)));
+        assert_eq!(detect_indentation("/**\n * doc\n */\nfn a() {\n    b\n}\n"), Some(Indent::Spaces(4)));
+        assert_eq!(detect_indentation("no\nindentation\n"), None);
+    }
+
+    #[test]
+    fn indents_like_the_file() {
+        let mut buf = buffer("if x {}");
+        buf.indent = Some(Indent::Spaces(2));
+        buf.position = 6;
+        buf.insert_newline_indented();
+        assert_eq!(buf.content, "if x {\n  \n}");
+        let mut buf = buffer("a");
+        buf.indent = Some(Indent::Spaces(4));
+        buf.position = 1;
+        buf.insert_indent();
+        assert_eq!(buf.content, "a   ");
+        buf.indent = Some(Indent::Tabs);
+        buf.insert_indent();
+        assert_eq!(buf.content, "a   \t");
+    }
+
+    #[test]
+    fn align_on() {
+        let mut buf = buffer("a = 1\nlonger = 2\nnone\n\tb = 3 = 4\n");
+        buf.selection = Some((0, buf.content.len()));
+        assert_eq!(buf.align_selection_on("="), 2);
+        assert_eq!(buf.content, "a      = 1\nlonger = 2\nnone\n\tb  = 3 = 4\n");
+        assert_eq!(buf.align_selection_on("="), 0);
+        assert_eq!(buf.align_selection_on("//"), 0);
+    }
+
+    #[test]
+    fn strip_ansi() {
+        let mut buf = buffer("$ ls\n\x1b[34msrc\x1b[0m\n");
+        buf.position = 14;
+        assert_eq!(buf.strip_ansi(), 9);
+        assert_eq!(buf.content, "$ ls\nsrc\n");
+        assert_eq!(buf.position, 8);
+        assert_eq!(buf.strip_ansi(), 0);
+        let mut buf = buffer("\x1b[1mbold\x1b[0m");
+        buf.position = 6;
+        buf.strip_ansi();
+        assert_eq!(buf.position, 2);
+    }
+
+    #[test]
+    fn open_lines() {
+        let mut buf = buffer("    if x {\n    }");
+        buf.position = 6;
+        buf.open_line_below(true);
+        assert_eq!(buf.content, "    if x {\n        \n    }");
+        assert_eq!(buf.position, 19);
+        buf.open_line_above(true);
+        assert_eq!(buf.content, "    if x {\n        \n        \n    }");
+        assert_eq!(buf.position, 19);
+        buf.position = 0;
+        buf.open_line_above(false);
+        assert_eq!(buf.content, "\n    if x {\n        \n        \n    }");
+        assert_eq!(buf.position, 0);
+    }
+
+    #[test]
+    fn dedent_closing_bracket() {
+        let mut buf = buffer("  fn x() {\n    {}\n      ");
+        buf.position = buf.content.len();
+        buf.dedent_closing_bracket('}');
+        assert_eq!(buf.content, "  fn x() {\n    {}\n  ");
+        assert_eq!(buf.position, buf.content.len());
+    }
+
+    #[test]
+    fn line_comment() {
+        let mut buf = buffer("fn x() {\n    y();\n\n    z();\n}\n");
+        buf.selection = Some((9, 28));
+        buf.toggle_line_comment("//");
+        assert_eq!(buf.content, "fn x() {\n    // y();\n\n    // z();\n}\n");
+        buf.toggle_line_comment("//");
+        assert_eq!(buf.content, "fn x() {\n    y();\n\n    z();\n}\n");
+    }
+
+    #[test]
+    fn block_comment() {
+        let mut buf = buffer("a {\n  color: red;\n}\n");
+        buf.position = 6;
+        buf.toggle_block_comment("/*", "*/");
+        assert_eq!(buf.content, "a {\n  /* color: red; */\n}\n");
+        buf.toggle_block_comment("/*", "*/");
+        assert_eq!(buf.content, "a {\n  color: red;\n}\n");
+    }
+
+    #[test]
+    fn smart_home() {
+        let mut buf = buffer("a\n    b\n");
+        buf.position = 7;
+        buf.goto_first_non_whitespace();
+        assert_eq!(buf.position, 6);
+        buf.goto_first_non_whitespace();
+        assert_eq!(buf.position, 2);
+        buf.goto_first_non_whitespace();
+        assert_eq!(buf.position, 6);
+    }
+
+    #[test]
+    fn retab() {
+        let mut buf = buffer("a\n  b\n    c\n\t  d\n   e\n");
+        buf.position = 10;
+        assert_eq!(buf.retab(2, 4), 4);
+        assert_eq!(buf.content, "a\n    b\n        c\n\t    d\n     e\n");
+        assert_eq!(&buf.content[buf.position..buf.position+1], "c");
+    }
+
+    #[test]
+    fn kill_line() {
+        let mut buf = buffer("hello world\nfoo\n");
+        buf.position = 5;
+        assert_eq!(buf.delete_to_end_of_line().as_deref(), Some(" world"));
+        // at the end of the line the newline is deleted
+        assert_eq!(buf.delete_to_end_of_line().as_deref(), Some("\n"));
+        assert_eq!(buf.content, "hellofoo\n");
+        assert_eq!(buf.delete_to_start_of_line().as_deref(), Some("hello"));
+        assert_eq!(buf.content, "foo\n");
+        assert_eq!(buf.position, 0);
+    }
+
+    #[test]
+    fn transpose_graphemes() {
+        // the e has a combining accent
+        let mut buf = buffer("ae\u{301}b\ncd");
+        buf.position = 1;
+        buf.transpose_graphemes();
+        assert_eq!(buf.content, "e\u{301}ab\ncd");
+        assert_eq!(buf.position, 4);
+        // at the end of the line the previous two are swapped
+        buf.position = 5;
+        buf.transpose_graphemes();
+        assert_eq!(buf.content, "e\u{301}ba\ncd");
+        // no-op at the start of a line
+        buf.position = 6;
+        buf.transpose_graphemes();
+        assert_eq!(buf.content, "e\u{301}ba\ncd");
+        assert_eq!(buf.position, 6);
+        // and on empty lines or with a single grapheme
+        let mut buf = buffer("ab\n\nc");
+        for position in [3, 5] {
+            buf.position = position;
+            buf.transpose_graphemes();
+            assert_eq!(buf.content, "ab\n\nc");
+            assert_eq!(buf.position, position);
+        }
+    }
+
+    #[test]
+    fn transpose_words() {
+        let mut buf = buffer("one two, three");
+        buf.position = 1;
+        buf.transpose_words("_");
+        assert_eq!(buf.content, "two one, three");
+        buf.transpose_words("_");
+        assert_eq!(buf.content, "two three, one");
+        // what makes up a word is configured
+        for (word_chars, transposed) in [("-", "c a-b"), ("", "b-a c")] {
+            let mut buf = buffer("a-b c");
+            buf.position = 1;
+            buf.transpose_words(word_chars);
+            assert_eq!(buf.content, transposed);
+        }
+    }
+
+    #[test]
+    fn move_through_wrapped_rows() {
+        // wrapped at 6: "the " "quick " "brown " "fox"
+        let mut buf = buffer("the quick brown fox\nab\nlast line here");
+        buf.position = 8;
+        buf.move_down_wrapped(6);
+        assert_eq!(buf.position, 14);
+        // the last row is shorter than the column
+        buf.move_down_wrapped(6);
+        assert_eq!(buf.position, 19);
+        buf.move_down_wrapped(6);
+        assert_eq!(buf.position, 22);
+        buf.move_down_wrapped(6);
+        assert_eq!(buf.position, 27);
+        // the column is kept on the way back up
+        for position in [22, 19, 14, 8, 3, 0] {
+            buf.move_up_wrapped(6);
+            assert_eq!(buf.position, position);
+        }
+    }
+
+    #[test]
+    fn move_to_the_end_of_a_broken_row() {
+        let mut buf = buffer("abc\nthe quick");
+        buf.position = 3;
+        buf.prefered_col = Some(5);
+        buf.move_down_wrapped(6);
+        // "the " is broken after the space, the cursor stays on its row
+        assert_eq!(buf.position, 7);
+        buf.move_down_wrapped(6);
+        assert_eq!(buf.position, 13);
+    }
+}
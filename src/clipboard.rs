@@ -0,0 +1,16 @@
+//! Copying and pasting text within the editor
+
+#[derive(Debug, Default)]
+pub struct Clipboard {
+    content: String,
+}
+
+impl Clipboard {
+    pub fn set(&mut self, content: String) {
+        self.content = content;
+    }
+
+    pub fn get(&self) -> &str {
+        &self.content
+    }
+}
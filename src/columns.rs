@@ -0,0 +1,121 @@
+//! Aligning the columns of delimited data (like CSV) for display
+
+use crate::parse::whitespace::TABSIZE;
+
+/// The delimiter of files that are aligned when opened
+pub fn delimiter_for(name: &str) -> Option<char> {
+    match name.rsplit('.').next()? {
+        "csv" => Some(','),
+        "tsv" => Some('\t'),
+        _ => None,
+    }
+}
+
+/// The display widths of the columns in a set of lines
+#[derive(Debug, Clone, PartialEq)]
+pub struct Columns {
+    pub delimiter: char,
+    pub widths: Vec<usize>,
+}
+
+impl Columns {
+    pub fn measure<'a>(lines: impl Iterator<Item = &'a str>, delimiter: char) -> Self {
+        let mut widths: Vec<usize> = vec![];
+        for line in lines {
+            for (i, (start, end)) in fields(line, delimiter).into_iter().enumerate() {
+                let width = display_width(&line[start..end]);
+                match widths.get_mut(i) {
+                    Some(max) => *max = (*max).max(width),
+                    None => widths.push(width),
+                }
+            }
+        }
+        Self { delimiter, widths }
+    }
+
+    /// The padding to insert at byte offsets of a line (before each delimiter)
+    pub fn padding(&self, line: &str) -> Vec<(usize, usize)> {
+        let fields = fields(line, self.delimiter);
+        let last = fields.len().saturating_sub(1);
+        fields.into_iter().enumerate()
+            .take(last)
+            // lines out of view can have more fields than were measured
+            .map(|(i, (start, end))| (end, self.widths.get(i).copied().unwrap_or(0).saturating_sub(display_width(&line[start..end]))))
+            .filter(|(_, padding)| *padding > 0)
+            .collect()
+    }
+
+    /// The display column of a byte offset into a line
+    pub fn column(&self, line: &str, byte: usize) -> usize {
+        let padding: usize = self.padding(line).into_iter()
+            .filter(|(offset, _)| *offset <= byte)
+            .map(|(_, padding)| padding)
+            .sum();
+        display_width(&line[..byte.min(line.len())]) + padding
+    }
+
+    /// The byte offset into a line that is displayed at a column
+    pub fn byte_at(&self, line: &str, column: usize) -> usize {
+        line.char_indices().map(|(i, _)| i).chain([line.len()])
+            .take_while(|i| self.column(line, *i) <= column)
+            .last()
+            .unwrap_or(0)
+    }
+}
+
+/// The byte ranges of the fields in a line, delimiters inside quotes are ignored
+pub fn fields(line: &str, delimiter: char) -> Vec<(usize, usize)> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let mut fields = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == delimiter && !quoted {
+            fields.push((start, i));
+            start = i + c.len_utf8();
+        }
+    }
+    fields.push((start, line.len()));
+    fields
+}
+
+fn display_width(s: &str) -> usize {
+    s.chars().map(|c| if c == '\t' { TABSIZE } else { 1 }).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pads_fields() {
+        let lines = ["a,bbb,c", "aaa,b,\"c,d\""];
+        let columns = Columns::measure(lines.into_iter(), ',');
+        assert_eq!(columns.widths, vec![3, 3, 5]);
+        assert_eq!(columns.padding(lines[0]), vec![(1, 2)]);
+        assert_eq!(columns.padding(lines[1]), vec![(5, 2)]);
+    }
+
+    #[test]
+    fn maps_columns() {
+        let lines = ["a,bbb,c", "aaa,b,c"];
+        let columns = Columns::measure(lines.into_iter(), ',');
+        // "a  ,bbb,c"
+        assert_eq!(columns.column(lines[0], 1), 3);
+        assert_eq!(columns.column(lines[0], 2), 4);
+        assert_eq!(columns.byte_at(lines[0], 4), 2);
+        assert_eq!(columns.byte_at(lines[0], 2), 0);
+        assert_eq!(columns.byte_at(lines[0], 3), 1);
+        assert_eq!(columns.byte_at(lines[0], 20), 7);
+    }
+
+    #[test]
+    fn lines_with_more_fields_than_measured() {
+        let columns = Columns::measure(["aaa,b"].into_iter(), ',');
+        let line = "a,b,c,d";
+        assert_eq!(columns.padding(line), vec![(1, 2)]);
+        assert_eq!(columns.column(line, 7), 9);
+    }
+}
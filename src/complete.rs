@@ -0,0 +1,138 @@
+//! Completing words from the words already in the buffer, and paths
+
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::buffer::is_word_char;
+
+/// How long the word before the cursor must be for completions to show while typing
+pub const AUTO_COMPLETE_PREFIX: usize = 2;
+
+/// How many completions are offered at most
+const MAX_COMPLETIONS: usize = 50;
+
+/// The start of the word that ends at `position`
+pub fn prefix_start(content: &str, position: usize, word_chars: &str) -> usize {
+    content[..position].char_indices().rev()
+        .take_while(|(_, c)| is_word_char(*c, word_chars))
+        .last()
+        .map_or(position, |(i, _)| i)
+}
+
+/// The words in `content` starting with `prefix` (but longer),
+/// the ones closest to `position` (where the prefix is typed) first
+pub fn words(content: &str, prefix: &str, position: usize, word_chars: &str) -> Vec<String> {
+    let mut found: Vec<(usize, &str)> = vec![];
+    let mut start = None;
+    for (i, c) in content.char_indices().chain([(content.len(), ' ')]) {
+        match (is_word_char(c, word_chars) && i < content.len(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                start = None;
+                let word = &content[s..i];
+                // the word being typed itself doesn't count
+                if word.len() > prefix.len() && word.starts_with(prefix) && !(s..=i).contains(&position) {
+                    found.push((s.abs_diff(position), word));
+                }
+            },
+            _ => {},
+        }
+    }
+    found.sort_by_key(|(distance, _)| *distance);
+    let mut words: Vec<String> = vec![];
+    for (_, word) in found {
+        if !words.iter().any(|w| w == word) {
+            words.push(word.to_string());
+        }
+        if words.len() == MAX_COMPLETIONS {
+            break;
+        }
+    }
+    words
+}
+
+/// Complete a path relative to `dir` as far as all matching files agree.
+/// Returns the completed path and the names of the matching files, directories end with a `/`.
+pub fn path(path: &str, dir: &Path) -> (String, Vec<String>) {
+    let (parent, prefix) = path.rsplit_once('/').map_or(("", path), |(parent, prefix)| (parent, prefix));
+    let listed = listed_dir(parent, path.starts_with('/'), dir, &dirs::home_dir().unwrap_or_default());
+    let Ok(entries) = fs::read_dir(listed) else { return (path.to_owned(), vec![]) };
+    let mut names: Vec<String> = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().into_string().ok()?;
+        // hidden files only when asked for
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            return None;
+        }
+        match entry.path().is_dir() {
+            true => Some(format!("{name}/")),
+            false => Some(name),
+        }
+    }).collect();
+    names.sort();
+    let Some(first) = names.first() else { return (path.to_owned(), vec![]) };
+    let common = names.iter().fold(first.as_str(), |common, name| {
+        let len = common.char_indices().zip(name.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(common.len().min(name.len()), |((i, _), _)| i);
+        &common[..len]
+    });
+    let completed = match path.rsplit_once('/') {
+        Some((parent, _)) => format!("{parent}/{common}"),
+        None => common.to_owned(),
+    };
+    (completed, names)
+}
+
+/// The directory `parent` (of an absolute path or not) refers to,
+/// relative to `dir` or with a leading `~` standing for `home`
+fn listed_dir(parent: &str, absolute: bool, dir: &Path, home: &Path) -> PathBuf {
+    match (parent, absolute) {
+        ("", true) => Path::new("/").to_path_buf(),
+        ("", false) => dir.to_path_buf(),
+        ("~", _) => home.to_path_buf(),
+        (parent, _) => match parent.strip_prefix("~/") {
+            Some(rest) => home.join(rest),
+            None => dir.join(parent),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::TempPath;
+
+    #[test]
+    fn finds_words() {
+        let content = "let value = 1;\nlet valid = va\nvalue_of(vault)";
+        let position = content.find(" va\n").unwrap() + 3;
+        assert_eq!(prefix_start(content, position, "_"), position - 2);
+        assert_eq!(words(content, "va", position, "_"), vec!["value_of", "valid", "vault", "value"]);
+        assert_eq!(words(content, "val", position, ""), vec!["value", "valid"]);
+        assert!(words(content, "x", position, "_").is_empty());
+    }
+
+    #[test]
+    fn completes_paths() {
+        let dir = TempPath::new("complete");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        for file in ["src/main.rs", "src/model.rs", ".hidden"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        assert_eq!(path("s", &dir), (String::from("src/"), vec![String::from("src/")]));
+        assert_eq!(path("src/m", &dir), (String::from("src/m"), vec![String::from("main.rs"), String::from("model.rs")]));
+        assert_eq!(path("src/mo", &dir), (String::from("src/model.rs"), vec![String::from("model.rs")]));
+        assert_eq!(path("", &dir).1, vec![String::from("src/")]);
+        assert_eq!(path(".h", &dir).0, ".hidden");
+        assert_eq!(path("nope/x", &dir), (String::from("nope/x"), vec![]));
+    }
+
+    #[test]
+    fn expands_home_in_paths() {
+        let (dir, home) = (Path::new("/work"), Path::new("/home/user"));
+        assert_eq!(listed_dir("~", false, dir, home), home);
+        assert_eq!(listed_dir("~/src/bin", false, dir, home), home.join("src/bin"));
+        assert_eq!(listed_dir("src/~", false, dir, home), dir.join("src/~"));
+        assert_eq!(listed_dir("", true, dir, home), Path::new("/"));
+    }
+}
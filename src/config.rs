@@ -0,0 +1,288 @@
+//! The configuration file
+
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+use anyhow::anyhow;
+use crossterm::cursor::SetCursorStyle;
+use ratatui::style::Color;
+use serde::{Deserialize, Deserializer};
+
+use crate::status;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// visualize whitespace
+    pub whitespace: bool,
+    /// Lines to keep between the cursor and the edge of the viewport
+    pub scrolloff: usize,
+    /// Lines of the previous page that stay in view when paging
+    pub page_overlap: usize,
+    /// Lines scrolled per tick of the mouse wheel
+    pub scroll_lines: usize,
+    /// scroll further when the wheel ticks in quick succession
+    pub scroll_acceleration: bool,
+    /// use modal (normal/insert) editing
+    pub modal: bool,
+    /// The cursor shape (modal editing decides the shape itself)
+    pub cursor_shape: CursorShape,
+    /// flash the screen when a search or action fails
+    pub flash: bool,
+    /// Characters besides alphanumerics that are part of words
+    pub word_chars: String,
+    /// show line numbers in a gutter
+    pub line_numbers: bool,
+    /// number lines relative to the cursor (shows the gutter)
+    pub relative_numbers: bool,
+    /// going to the start of the line stops at the indentation first
+    pub smart_home: bool,
+    /// moving left or right past the edge of a line continues on the next or previous line
+    pub wrap_cursor: bool,
+    /// undo and redo leave the cursor where it is if the changes are in view
+    pub undo_keeps_cursor: bool,
+    /// search case insensitively unless the query has upper case letters
+    pub smartcase: bool,
+    /// run shell commands in the directory of the current file (instead of where atto was started)
+    pub shell_in_buffer_dir: bool,
+    /// show the output of shell commands in a read-only buffer instead of a notification
+    pub shell_output_buffer: bool,
+    /// copy paths relative to the project root instead of absolute
+    pub copy_relative_path: bool,
+    /// What of the path `insert-path` inserts without an argument
+    pub inserted_path: PathStyle,
+    /// What the column in the status bar counts
+    pub column_indicator: ColumnIndicator,
+    /// What the right side of the status bar shows, placeholders like `{line}`
+    /// are filled in (see `status::PLACEHOLDERS`)
+    pub status_format: String,
+    /// How quitting asks about buffers with unsaved changes
+    pub quit_prompt: QuitPrompt,
+    /// indent new lines like the previous line (and deeper after brackets)
+    pub auto_indent: bool,
+    /// indent with tabs or spaces like the opened file already is
+    pub detect_indentation: bool,
+    /// give pasted lines the indentation of the current line
+    pub reindent_paste: bool,
+    /// show completions from the words in the buffer while typing a word
+    pub auto_complete: bool,
+    /// ask before pasting more than `large_paste` bytes
+    pub confirm_large_paste: bool,
+    /// capture the mouse (off leaves selecting text to the terminal)
+    pub mouse: bool,
+    /// ask before replacing all search results
+    pub confirm_replace_all: bool,
+    /// How many bytes a paste can have before it counts as large
+    pub large_paste: usize,
+    /// draw a line at every level of indentation
+    pub indent_guides: bool,
+    /// highlight the line and column of the cursor (in the theme's line highlight color)
+    pub crosshair: bool,
+    /// wrap long lines at word boundaries
+    pub wrap: bool,
+    /// display lines of right-to-left scripts (like Hebrew and Arabic) right to left
+    pub bidi: bool,
+    /// How wide the text is in the centered reading layout
+    pub reading_width: u16,
+    /// color brackets by how deep they are nested
+    pub rainbow_brackets: bool,
+    /// The colors of the nesting levels of brackets (names like "red" or "#ff5555")
+    #[serde(deserialize_with = "colors")]
+    pub bracket_colors: Vec<Color>,
+    /// Commands that format a buffer piped through them, by syntax name (like `Rust = "rustfmt"`)
+    pub formatters: HashMap<String, String>,
+    /// format every buffer that has a formatter before saving it
+    pub format_on_save: bool,
+    /// The syntaxes that are formatted before saving even if `format_on_save` is off
+    pub format_on_save_syntaxes: Vec<String>,
+    /// The root of the project (found with `root_markers` if not set)
+    pub root: Option<PathBuf>,
+    /// Files or directories marking the root of a project
+    pub root_markers: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            whitespace: false,
+            scrolloff: 0,
+            page_overlap: 2,
+            scroll_lines: 3,
+            scroll_acceleration: false,
+            modal: false,
+            cursor_shape: CursorShape::Default,
+            flash: true,
+            word_chars: String::from("_"),
+            line_numbers: false,
+            relative_numbers: false,
+            smart_home: false,
+            wrap_cursor: false,
+            undo_keeps_cursor: false,
+            smartcase: true,
+            shell_in_buffer_dir: true,
+            shell_output_buffer: false,
+            copy_relative_path: true,
+            inserted_path: PathStyle::Relative,
+            column_indicator: ColumnIndicator::Visual,
+            status_format: String::from(status::DEFAULT),
+            quit_prompt: QuitPrompt::Each,
+            auto_indent: true,
+            detect_indentation: true,
+            reindent_paste: false,
+            auto_complete: false,
+            confirm_large_paste: true,
+            confirm_replace_all: false,
+            mouse: true,
+            large_paste: 256 * 1024,
+            indent_guides: false,
+            crosshair: false,
+            wrap: false,
+            bidi: false,
+            reading_width: 80,
+            rainbow_brackets: false,
+            bracket_colors: vec![Color::Yellow, Color::Magenta, Color::Cyan],
+            formatters: HashMap::new(),
+            format_on_save: false,
+            format_on_save_syntaxes: vec![],
+            root: None,
+            root_markers: vec![String::from(".git"), String::from("Cargo.toml")],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CursorShape {
+    /// Whatever the terminal uses
+    Default,
+    Block,
+    Bar,
+    Underline,
+}
+
+impl CursorShape {
+    pub fn style(self) -> SetCursorStyle {
+        match self {
+            CursorShape::Default => SetCursorStyle::DefaultUserShape,
+            CursorShape::Block => SetCursorStyle::SteadyBlock,
+            CursorShape::Bar => SetCursorStyle::SteadyBar,
+            CursorShape::Underline => SetCursorStyle::SteadyUnderScore,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnIndicator {
+    /// The column on screen (tabs are expanded)
+    Visual,
+    /// The visual column and the grapheme in the line
+    Grapheme,
+    /// The visual column and the byte offset in the line
+    Byte,
+    /// All of the above
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuitPrompt {
+    /// Ask about every buffer in turn
+    Each,
+    /// Ask once to save or discard all of them
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathStyle {
+    /// Just the name of the file
+    Name,
+    /// The path relative to the project root
+    Relative,
+    /// The absolute path
+    Absolute,
+}
+
+fn colors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Color>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?.iter()
+        .map(|name| name.parse().map_err(|_| serde::de::Error::custom(format!("invalid color {name:?}"))))
+        .collect()
+}
+
+/// Where the config file is read from if no path is given
+pub fn default_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("atto").join("config.toml"))
+}
+
+/// Read the config file.
+/// A missing file at the default location is not an error.
+pub fn load(path: Option<PathBuf>) -> anyhow::Result<Config> {
+    let (path, explicit) = match path {
+        Some(path) => (path, true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) if !explicit && e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(anyhow!("{}: {e}", path.display())),
+    };
+    parse(&source, &path.to_string_lossy())
+}
+
+/// Parse a config, errors point at the line and column in `name`
+pub fn parse(source: &str, name: &str) -> anyhow::Result<Config> {
+    toml::from_str(source).map_err(|e| {
+        let message = e.message().trim();
+        match e.span() {
+            Some(span) => {
+                let (line, col) = line_col(source, span.start);
+                anyhow!("{name}:{line}:{col}: {message}")
+            },
+            None => anyhow!("{name}: {message}"),
+        }
+    })
+}
+
+/// Turn a byte offset into a 1-based line and column
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_config() {
+        let config = parse("scrolloff = 3\nwhitespace = true\n", "config.toml").unwrap();
+        assert_eq!(config.scrolloff, 3);
+        assert!(config.whitespace);
+        assert!(!config.modal);
+    }
+
+    #[test]
+    fn error_points_at_line() {
+        let err = parse("whitespace = true\n\nscrolloff = \"three\"\n", "config.toml").unwrap_err();
+        assert!(err.to_string().starts_with("config.toml:3:13:"), "{err}");
+    }
+
+    #[test]
+    fn unknown_key_is_an_error() {
+        let err = parse("scrolof = 3\n", "config.toml").unwrap_err();
+        assert!(err.to_string().starts_with("config.toml:1:1:"), "{err}");
+    }
+
+    #[test]
+    fn parses_bracket_colors() {
+        let config = parse("bracket_colors = [\"red\", \"#00ff00\"]\n", "config.toml").unwrap();
+        assert_eq!(config.bracket_colors, vec![Color::Red, Color::Rgb(0, 255, 0)]);
+        let err = parse("bracket_colors = [\"reddish\"]\n", "config.toml").unwrap_err();
+        assert!(err.to_string().contains("invalid color"));
+    }
+}
@@ -0,0 +1,68 @@
+//! Typing characters that are not on the keyboard,
+//! by their code point (like `U+2603`) or a two character digraph (like `a:` for ä)
+
+/// Digraphs as in RFC 1345 (which vim also uses), the common ones
+pub const DIGRAPHS: &[(&str, char)] = &[
+    // accents
+    ("a:", 'ä'), ("e:", 'ë'), ("i:", 'ï'), ("o:", 'ö'), ("u:", 'ü'), ("y:", 'ÿ'),
+    ("A:", 'Ä'), ("E:", 'Ë'), ("I:", 'Ï'), ("O:", 'Ö'), ("U:", 'Ü'),
+    ("a'", 'á'), ("e'", 'é'), ("i'", 'í'), ("o'", 'ó'), ("u'", 'ú'), ("y'", 'ý'),
+    ("A'", 'Á'), ("E'", 'É'), ("I'", 'Í'), ("O'", 'Ó'), ("U'", 'Ú'),
+    ("a!", 'à'), ("e!", 'è'), ("i!", 'ì'), ("o!", 'ò'), ("u!", 'ù'),
+    ("A!", 'À'), ("E!", 'È'), ("I!", 'Ì'), ("O!", 'Ò'), ("U!", 'Ù'),
+    ("a>", 'â'), ("e>", 'ê'), ("i>", 'î'), ("o>", 'ô'), ("u>", 'û'),
+    ("a?", 'ã'), ("n?", 'ñ'), ("o?", 'õ'), ("N?", 'Ñ'),
+    ("c,", 'ç'), ("C,", 'Ç'), ("aa", 'å'), ("AA", 'Å'),
+    ("ae", 'æ'), ("AE", 'Æ'), ("o/", 'ø'), ("O/", 'Ø'), ("ss", 'ß'),
+    // punctuation
+    ("<<", '«'), (">>", '»'), ("!I", '¡'), ("?I", '¿'),
+    ("-N", '–'), ("-M", '—'), (".,", '…'), ("SE", '§'), ("PI", '¶'),
+    ("\"6", '“'), ("\"9", '”'), ("'6", '‘'), ("'9", '’'),
+    // currency
+    ("Eu", '€'), ("Pd", '£'), ("Ye", '¥'), ("Ct", '¢'),
+    // symbols
+    ("Co", '©'), ("Rg", '®'), ("TM", '™'), ("DG", '°'), ("My", 'µ'),
+    ("+-", '±'), ("*X", '×'), ("-:", '÷'), ("!=", '≠'), ("=<", '≤'), (">=", '≥'),
+    ("?=", '≅'), ("?2", '≈'), ("00", '∞'), ("RT", '√'), ("OK", '✓'), ("XX", '✗'),
+    ("->", '→'), ("<-", '←'), ("-!", '↑'), ("-v", '↓'), ("=>", '⇒'), ("==", '⇔'),
+    ("1S", '¹'), ("2S", '²'), ("3S", '³'), ("12", '½'), ("14", '¼'), ("34", '¾'),
+    // greek
+    ("a*", 'α'), ("b*", 'β'), ("g*", 'γ'), ("d*", 'δ'), ("e*", 'ε'), ("l*", 'λ'),
+    ("m*", 'μ'), ("p*", 'π'), ("s*", 'σ'), ("t*", 'τ'), ("f*", 'φ'), ("w*", 'ω'),
+    ("D*", 'Δ'), ("S*", 'Σ'), ("W*", 'Ω'),
+];
+
+/// The character meant by a code point (`U+2603`, `0x2603` or `\u2603`) or a digraph.
+/// Code points need the prefix, otherwise digraphs like `12` and `ae` would read as hex.
+pub fn parse(input: &str) -> Result<char, String> {
+    let input = input.trim();
+    if let Some((_, c)) = DIGRAPHS.iter().find(|(digraph, _)| *digraph == input) {
+        return Ok(*c);
+    }
+    let code = ["U+", "u+", "0x", "\\u"].iter()
+        .find_map(|prefix| input.strip_prefix(prefix))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| format!("{input} is not a code point (like U+2603) or digraph"))?;
+    char::from_u32(code).ok_or_else(|| format!("U+{code:04X} is not a valid code point"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_code_points_and_digraphs() {
+        assert_eq!(parse("U+2603"), Ok('☃'));
+        assert_eq!(parse("0x41"), Ok('A'));
+        assert_eq!(parse(" \\ue9 "), Ok('é'));
+        // without a prefix it is a digraph
+        assert_eq!(parse("12"), Ok('½'));
+        assert_eq!(parse("ae"), Ok('æ'));
+        assert!(parse("e9").is_err());
+        assert_eq!(parse("a:"), Ok('ä'));
+        assert_eq!(parse("Eu"), Ok('€'));
+        assert_eq!(parse("U+D800"), Err(String::from("U+D800 is not a valid code point")));
+        assert_eq!(parse("U+110000"), Err(String::from("U+110000 is not a valid code point")));
+        assert!(parse("snow").is_err());
+    }
+}
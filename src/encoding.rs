@@ -0,0 +1,97 @@
+//! Converting between the bytes of a file and the content of a buffer.
+//! Buffers are always UTF-8 internally, other encodings only exist on disk.
+
+use std::io;
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use tracing::warn;
+
+/// File content converted to UTF-8
+pub struct Decoded {
+    pub content: String,
+    pub encoding: &'static Encoding,
+    /// The file started with a byte order mark
+    pub bom: bool,
+}
+
+/// Decode the bytes of a file, stripping any BOM.
+/// Without an explicit encoding a UTF-16 BOM is honored,
+/// and content that isn't valid UTF-8 is assumed to be Windows-1252.
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> io::Result<Decoded> {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => match Encoding::for_bom(bytes) {
+            Some((encoding, _)) => encoding,
+            None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+            None => {
+                warn!("Content is not valid UTF-8, falling back to {}", WINDOWS_1252.name());
+                WINDOWS_1252
+            },
+        },
+    };
+    let (bytes, bom) = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, bom_length)) if bom_encoding == encoding => (&bytes[bom_length..], true),
+        _ => (bytes, false),
+    };
+    encoding.decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|content| Decoded { content: content.into_owned(), encoding, bom })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("file is not valid {}", encoding.name())))
+}
+
+/// Encode content to be written to a file, optionally starting with a BOM
+pub fn encode(content: &str, encoding: &'static Encoding, bom: bool) -> io::Result<Vec<u8>> {
+    if is_utf16(encoding) {
+        // encoding_rs can only decode UTF-16
+        let mut bytes = Vec::with_capacity(content.len() * 2 + 2);
+        let bom = if bom { Some(0xFEFF) } else { None };
+        for unit in bom.into_iter().chain(content.encode_utf16()) {
+            bytes.extend_from_slice(&if encoding == UTF_16LE { unit.to_le_bytes() } else { unit.to_be_bytes() });
+        }
+        return Ok(bytes);
+    }
+    let (encoded, _, unmappable) = encoding.encode(content);
+    if unmappable {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("content cannot be represented in {}", encoding.name())));
+    }
+    let mut bytes = if bom && encoding == UTF_8 { "\u{FEFF}".as_bytes().to_vec() } else { vec![] };
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
+}
+
+fn is_utf16(encoding: &'static Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_windows_1252() {
+        let bytes = b"caf\xe9";
+        let decoded = decode(bytes, None).unwrap();
+        assert_eq!(decoded.content, "café");
+        assert_eq!(decoded.encoding, WINDOWS_1252);
+        assert_eq!(encode(&decoded.content, decoded.encoding, decoded.bom).unwrap(), bytes);
+        assert!(encode("☃", decoded.encoding, false).is_err());
+    }
+
+    #[test]
+    fn sniffs_utf16() {
+        let bytes = b"\xff\xfeh\x00i\x00";
+        let decoded = decode(bytes, None).unwrap();
+        assert_eq!(decoded.content, "hi");
+        assert_eq!(decoded.encoding, UTF_16LE);
+        assert_eq!(encode(&decoded.content, decoded.encoding, decoded.bom).unwrap(), bytes);
+    }
+
+    #[test]
+    fn strips_utf8_bom() {
+        let bytes = "\u{FEFF}first\nsecond\n".as_bytes();
+        let decoded = decode(bytes, None).unwrap();
+        assert_eq!(decoded.content.lines().next(), Some("first"));
+        assert_eq!(decoded.encoding, UTF_8);
+        assert!(decoded.bom);
+        assert_eq!(encode(&decoded.content, decoded.encoding, decoded.bom).unwrap(), bytes);
+    }
+}
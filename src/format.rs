@@ -0,0 +1,44 @@
+//! Formatting buffers with external formatters (like `rustfmt`)
+
+use std::{io::Write, process::{Command, Stdio}, thread};
+
+/// Pipe `content` through the shell command `formatter` and return what it writes.
+/// Fails if the formatter can't be run or exits with an error.
+pub fn run(formatter: &str, content: &str) -> Result<String, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(formatter)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {formatter}: {e}"))?;
+
+    // written from another thread so a formatter writing before it read everything can't block
+    let mut stdin = child.stdin.take().unwrap();
+    let input = content.to_owned();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output().map_err(|e| format!("Failed to run {formatter}: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or("no output");
+        return Err(format!("{formatter} failed ({}): {reason}", output.status));
+    }
+    if let Ok(Err(e)) = writer.join() {
+        return Err(format!("Failed to write to {formatter}: {e}"));
+    }
+    String::from_utf8(output.stdout).map_err(|_| format!("{formatter} did not write UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_formatters() {
+        assert_eq!(run("tr a-z A-Z", "hello\n"), Ok(String::from("HELLO\n")));
+        let error = run("echo oops >&2; exit 3", "hello\n").unwrap_err();
+        assert!(error.contains("oops"), "{error}");
+    }
+}
@@ -4,50 +4,204 @@ use anyhow::Ok;
 use crossterm::event::{self, KeyCode, KeyModifiers, MouseButton};
 use tracing::{debug, trace};
 
-use crate::model::{Model, Message};
+use ratatui::style::{Color, Style};
+
+use crate::{config::CursorShape, model::{Model, Message}};
+
+/// The editing mode when modal editing is enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Keys are commands
+    Normal,
+    /// Keys insert text
+    Insert,
+}
 
 pub struct EventState {
     /// For word jumping
     space_down: bool,
     /// For reverse word jumping with space
     movement_key_down: Option<char>,
+    /// If the terminal reports key releases.
+    /// Without those the space modifier cannot be tracked.
+    keyboard_enhanced: bool,
+    /// If modal editing is enabled
+    modal: bool,
+    /// The current mode, always insert if not modal
+    pub mode: Mode,
+    /// The keys typed so far of a sequence like `C-x C-s`
+    pending: Vec<String>,
+    /// When the last key of the pending sequence was typed
+    pending_since: time::Instant,
+}
+
+impl EventState {
+    pub fn new(keyboard_enhanced: bool, modal: bool) -> Self {
+        Self {
+            space_down: false,
+            movement_key_down: None,
+            keyboard_enhanced,
+            modal,
+            mode: if modal { Mode::Normal } else { Mode::Insert },
+            pending: vec![],
+            pending_since: time::Instant::now(),
+        }
+    }
+
+    /// Track space being held down so it can act as a modifier.
+    /// This never decides whether a space gets inserted,
+    /// a space press without Alt always inserts a space.
+    /// Space only acts as a modifier in combination with Alt.
+    fn track_space(&mut self, key: &event::KeyEvent) {
+        // this relies on release events, so only when the terminal reports them
+        if !self.keyboard_enhanced || key.code != KeyCode::Char(' ') {
+            return;
+        }
+        match key.kind {
+            event::KeyEventKind::Press | event::KeyEventKind::Repeat => self.space_down = true,
+            event::KeyEventKind::Release => self.space_down = false,
+        }
+    }
+
+    /// The cursor shape for the current mode
+    pub fn cursor_shape(&self, configured: CursorShape) -> CursorShape {
+        if !self.modal {
+            return configured;
+        }
+        match self.mode {
+            Mode::Normal => CursorShape::Block,
+            Mode::Insert => CursorShape::Bar,
+        }
+    }
+
+    /// Forget a pending sequence that wasn't continued in time.
+    /// Returns if there was one.
+    fn expire_pending(&mut self) -> bool {
+        if self.pending.is_empty() || self.pending_since.elapsed() < SEQUENCE_TIMEOUT {
+            return false;
+        }
+        debug!("Sequence {:?} timed out", self.pending);
+        self.pending.clear();
+        true
+    }
+
+    /// Forget any held keys.
+    /// Used when releases can no longer be observed, like when losing focus.
+    fn reset(&mut self) {
+        self.space_down = false;
+        self.movement_key_down = None;
+    }
 }
 
-impl Default for EventState {
-    fn default() -> Self {
-        Self { space_down: false, movement_key_down: None }
+/// How long to wait for the next key of a sequence
+const SEQUENCE_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/// What a sequence of keys is bound to
+enum Sequence {
+    Complete(Message),
+    /// More keys can follow
+    Prefix,
+    Unbound,
+}
+
+/// Bindings of more than one key.
+/// `keys` are named like `C-x`, `A-x` or `x`, `normal` is set in the normal mode of modal editing.
+fn sequence(keys: &[String], normal: bool) -> Sequence {
+    let keys = keys.join(" ");
+    let msg = match keys.as_str() {
+        "C-x" => return Sequence::Prefix,
+        "C-x C-s" => Message::Save,
+        "C-x C-c" => Message::Quit,
+        "C-x k" => Message::CloseBuffer,
+        "C-x u" => Message::Undo,
+        "g" if normal => return Sequence::Prefix,
+        "g g" if normal => Message::ToTop,
+        _ => return Sequence::Unbound,
+    };
+    Sequence::Complete(msg)
+}
+
+/// The name of a key as used in sequences
+fn key_name(key: &event::KeyEvent) -> String {
+    let mut name = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        name.push_str("C-");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        name.push_str("A-");
+    }
+    match key.code {
+        KeyCode::Char(c) => name.push(c),
+        code => name.push_str(&format!("{code:?}")),
+    }
+    name
+}
+
+/// Keys that start or continue a sequence.
+/// Returns None if the key should be handled as usual.
+fn handle_sequence(key: event::KeyEvent, state: &mut EventState) -> Option<Option<Message>> {
+    state.expire_pending();
+    let mut keys = state.pending.clone();
+    keys.push(key_name(&key));
+    match sequence(&keys, state.modal && state.mode == Mode::Normal) {
+        Sequence::Complete(msg) => {
+            state.pending.clear();
+            Some(Some(msg))
+        },
+        Sequence::Prefix => {
+            state.pending_since = time::Instant::now();
+            let prefix = keys.join(" ");
+            state.pending = keys;
+            Some(Some(Message::KeyPrefix(prefix)))
+        },
+        Sequence::Unbound if state.pending.is_empty() => None,
+        Sequence::Unbound => {
+            state.pending.clear();
+            Some(Some(Message::Notification(
+                format!("{} is not bound", keys.join(" ")),
+                Style::new().bg(Color::White).fg(Color::Black)
+            )))
+        },
     }
 }
 
-pub fn handle_event(_m: &Model, state: &mut EventState) -> anyhow::Result<Option<Message>> {
+pub fn handle_event(m: &Model, state: &mut EventState) -> anyhow::Result<Option<Message>> {
     if event::poll(time::Duration::from_millis(100))? {
         match event::read()?  {
-            event::Event::Key(key) =>  Ok(handle_key(key, state)),
+            event::Event::Key(key) => {
+                let mode = state.mode;
+                let msg = handle_key(key, state, m.utility.is_some());
+                if state.mode != mode {
+                    crate::tui::set_cursor_shape(state.cursor_shape(m.config.cursor_shape));
+                }
+                Ok(msg)
+            },
             event::Event::Mouse(mouse) => Ok(handle_mouse(mouse)),
             event::Event::Resize(x, y) => Ok(Some(Message::Resize(x, y))),
             event::Event::Paste(paste) => Ok(Some(Message::Paste(paste))),
+            event::Event::FocusLost => {
+                state.reset();
+                Ok(None)
+            },
             _ => Ok(None),
         }
+    } else if state.expire_pending() {
+        Ok(Some(Message::KeyPrefix(String::new())))
+    } else if m.flashing || m.notification.is_some() || m.buffers.iter().any(|b| b.follow) {
+        Ok(Some(Message::Tick))
     } else {
         Ok(None)
     }
 }
 
 #[tracing::instrument(level="trace", skip(state))]
-fn handle_key(key: event::KeyEvent, state: &mut EventState) -> Option<Message> {
+fn handle_key(key: event::KeyEvent, state: &mut EventState, utility_open: bool) -> Option<Message> {
 
     // Space as a modifier key
-    if key.code == KeyCode::Char(' ') {
-        if state.space_down && key.kind == crossterm::event::KeyEventKind::Release {
-            state.space_down = false;
-        }
-        if !state.space_down && key.kind == crossterm::event::KeyEventKind::Press {
-            state.space_down = true;
-        }
-    }
+    state.track_space(&key);
 
     // If a movement key is held (so space can jump in that direction)
-    if let KeyCode::Char('j') | KeyCode::Char('f') = key.code {
+    if state.keyboard_enhanced && matches!(key.code, KeyCode::Char('j') | KeyCode::Char('f')) {
         if let KeyCode::Char(char) = key.code {
             match key.kind {
                 event::KeyEventKind::Press => {
@@ -68,6 +222,17 @@ fn handle_key(key: event::KeyEvent, state: &mut EventState) -> Option<Message> {
     }
 
     if key.kind == crossterm::event::KeyEventKind::Press || key.kind == crossterm::event::KeyEventKind::Repeat {
+        // Utilities always receive the keys as typed
+        if !utility_open {
+            if let Some(msg) = handle_sequence(key, state) {
+                return msg;
+            }
+        }
+        if state.modal && !utility_open {
+            if let Some(msg) = handle_modal_key(key, state) {
+                return msg;
+            }
+        }
         if key.modifiers.contains(KeyModifiers::ALT) {
             match key.code {
                 KeyCode::Char('u') => if key.modifiers.contains(KeyModifiers::CONTROL)  {
@@ -96,8 +261,26 @@ fn handle_key(key: event::KeyEvent, state: &mut EventState) -> Option<Message> {
                         Some(Message::MoveLeft)
                     }
                 },
+                // Word jumping for terminals without the space modifier
+                KeyCode::Char('b') => Some(Message::JumpWordLeft),
+                KeyCode::Char('w') => Some(Message::JumpWordRight),
                 KeyCode::Char('a') => Some(Message::GotoStartOfLine),
                 KeyCode::Char('e') => Some(Message::GotoEndOfLine),
+                KeyCode::Char('t') => Some(Message::TransposeWords),
+                KeyCode::Char('.') => Some(Message::RepeatLastEdit),
+                KeyCode::Char('l') => Some(Message::ToggleRelativeNumbers),
+                KeyCode::Char('J') => Some(Message::JoinLines),
+                KeyCode::Char('o') => Some(Message::OpenLineBelow),
+                KeyCode::Char('O') => Some(Message::OpenLineAbove),
+                KeyCode::Char('d') => Some(Message::DuplicateBuffer),
+                KeyCode::Char('z') => Some(Message::ToggleWrap),
+                KeyCode::Char('U') => Some(Message::OpenInsertUnicode),
+                KeyCode::Char('/') => Some(Message::Complete),
+                KeyCode::Char('c') => Some(Message::ToggleCase),
+                KeyCode::Char('r') => Some(Message::OpenReplace),
+                KeyCode::Char('R') => Some(Message::OpenReplaceEach),
+                KeyCode::Char('[') => Some(Message::PreviousUndoBranch),
+                KeyCode::Char(']') => Some(Message::NextUndoBranch),
                 // Reverse word jumping
                 KeyCode::Char(' ') => match state.movement_key_down {
                     Some('j') => Some(Message::JumpWordRight),
@@ -110,12 +293,26 @@ fn handle_key(key: event::KeyEvent, state: &mut EventState) -> Option<Message> {
             match key.code {
                 KeyCode::Right => Some(Message::NextBuffer),
                 KeyCode::Left => Some(Message::PreviousBuffer),
+                KeyCode::Char('a') => Some(Message::SelectAll),
+                KeyCode::Char('k') => Some(Message::DeleteToEndOfLine),
+                KeyCode::Char('u') => Some(Message::DeleteToStartOfLine),
+                KeyCode::Char('y') => Some(Message::Yank),
+                KeyCode::Char('t') => Some(Message::TransposeGraphemes),
+                KeyCode::Char('T') => Some(Message::ReopenClosed),
+                KeyCode::Char('n') => Some(Message::NewScratchBuffer),
                 KeyCode::Char('q') => Some(Message::Quit),
                 KeyCode::Char('s') => Some(Message::Save),
                 KeyCode::Char('S') => Some(Message::SaveAsRootConfirmation),
+                KeyCode::Char('z') => Some(Message::Undo),
+                KeyCode::Char('Z') => Some(Message::Redo),
                 KeyCode::Char('h') => Some(Message::OpenHelp),
                 KeyCode::Char('f') => Some(Message::OpenFind),
                 KeyCode::Char('b') => Some(Message::OpenShell),
+                KeyCode::Char('e') => Some(Message::OpenCommand),
+                KeyCode::Char('r') => Some(Message::OpenInsertFile),
+                KeyCode::Char('w') => Some(Message::OpenWriteSelection),
+                // most terminals send C-/ as C-7
+                KeyCode::Char('/') | KeyCode::Char('7') => Some(Message::ToggleComment),
                 _ => None,
             }
         } else {
@@ -129,6 +326,8 @@ fn handle_key(key: event::KeyEvent, state: &mut EventState) -> Option<Message> {
                 KeyCode::Down => Some(Message::MoveDown),
                 KeyCode::PageUp => Some(Message::PageUp),
                 KeyCode::PageDown => Some(Message::PageDown),
+                KeyCode::Home => Some(Message::GotoStartOfLine),
+                KeyCode::End => Some(Message::GotoEndOfLine),
                 KeyCode::Backspace => Some(Message::Backspace),
                 KeyCode::Delete => Some(Message::Delete),
                 KeyCode::F(12) => Some(Message::DeveloperKey),
@@ -141,11 +340,170 @@ fn handle_key(key: event::KeyEvent, state: &mut EventState) -> Option<Message> {
     }
 }
 
+/// Keys that behave differently in modal editing.
+/// Returns None if the key should be handled as usual.
+fn handle_modal_key(key: event::KeyEvent, state: &mut EventState) -> Option<Option<Message>> {
+    if key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+        return None;
+    }
+    match state.mode {
+        Mode::Insert => match key.code {
+            KeyCode::Esc => {
+                debug!("mode = Normal");
+                state.mode = Mode::Normal;
+                Some(Some(Message::Escape))
+            },
+            _ => None,
+        },
+        Mode::Normal => {
+            let insert = |state: &mut EventState, msg: Message| {
+                debug!("mode = Insert");
+                state.mode = Mode::Insert;
+                Some(Some(msg))
+            };
+            match key.code {
+                KeyCode::Char('i') => insert(state, Message::NoMessage),
+                KeyCode::Char('a') => insert(state, Message::MoveRight),
+                KeyCode::Char('I') => insert(state, Message::GotoStartOfLine),
+                KeyCode::Char('A') => insert(state, Message::GotoEndOfLine),
+                KeyCode::Char('o') => insert(state, Message::OpenLineBelow),
+                KeyCode::Char('O') => insert(state, Message::OpenLineAbove),
+                KeyCode::Char('h') => Some(Some(Message::MoveLeft)),
+                KeyCode::Char('j') => Some(Some(Message::MoveDown)),
+                KeyCode::Char('k') => Some(Some(Message::MoveUp)),
+                KeyCode::Char('l') => Some(Some(Message::MoveRight)),
+                KeyCode::Char('w') => Some(Some(Message::JumpWordRight)),
+                KeyCode::Char('b') => Some(Some(Message::JumpWordLeft)),
+                KeyCode::Char('0') => Some(Some(Message::GotoStartOfLine)),
+                KeyCode::Char('$') => Some(Some(Message::GotoEndOfLine)),
+                KeyCode::Char('G') => Some(Some(Message::ToBottom)),
+                KeyCode::Char('x') => Some(Some(Message::Delete)),
+                KeyCode::Char('.') => Some(Some(Message::RepeatLastEdit)),
+                KeyCode::Char('J') => Some(Some(Message::JoinLines)),
+                KeyCode::Char('u') => Some(Some(Message::Undo)),
+                KeyCode::Char('U') => Some(Some(Message::Redo)),
+                KeyCode::Enter => Some(Some(Message::MoveDown)),
+                KeyCode::Backspace => Some(Some(Message::MoveLeft)),
+                // letters don't type in normal mode
+                KeyCode::Char(_) | KeyCode::Tab => Some(None),
+                _ => None,
+            }
+        },
+    }
+}
+
 fn handle_mouse(mouse: event::MouseEvent) -> Option<Message> {
     match mouse.kind {
         event::MouseEventKind::ScrollDown => Some(Message::ScrollDown),
         event::MouseEventKind::ScrollUp => Some(Message::ScrollUp),
+        event::MouseEventKind::ScrollLeft => Some(Message::ScrollLeft),
+        event::MouseEventKind::ScrollRight => Some(Message::ScrollRight),
         event::MouseEventKind::Down(MouseButton::Left) => Some(Message::MouseLeft(mouse.column, mouse.row)),
         _ => None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers};
+
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers, kind: KeyEventKind) -> KeyEvent {
+        KeyEvent { code, modifiers, kind, state: KeyEventState::NONE }
+    }
+
+    fn space(kind: KeyEventKind) -> KeyEvent {
+        key(KeyCode::Char(' '), KeyModifiers::NONE, kind)
+    }
+
+    #[test]
+    fn space_press_inserts_space() {
+        let mut state = EventState::new(true, false);
+        assert!(matches!(handle_key(space(KeyEventKind::Press), &mut state, false), Some(Message::InsertChar(' '))));
+        assert!(state.space_down);
+        assert!(matches!(handle_key(space(KeyEventKind::Repeat), &mut state, false), Some(Message::InsertChar(' '))));
+        assert!(handle_key(space(KeyEventKind::Release), &mut state, false).is_none());
+        assert!(!state.space_down);
+    }
+
+    #[test]
+    fn space_modifier_jumps_words() {
+        let mut state = EventState::new(true, false);
+        handle_key(space(KeyEventKind::Press), &mut state, false);
+        let jump = handle_key(key(KeyCode::Char('j'), KeyModifiers::ALT, KeyEventKind::Press), &mut state, false);
+        assert!(matches!(jump, Some(Message::JumpWordRight)));
+        handle_key(space(KeyEventKind::Release), &mut state, false);
+        let mv = handle_key(key(KeyCode::Char('j'), KeyModifiers::ALT, KeyEventKind::Press), &mut state, false);
+        assert!(matches!(mv, Some(Message::MoveRight)));
+    }
+
+    #[test]
+    fn space_never_sticks_without_enhancement() {
+        let mut state = EventState::new(false, false);
+        assert!(matches!(handle_key(space(KeyEventKind::Press), &mut state, false), Some(Message::InsertChar(' '))));
+        assert!(!state.space_down);
+        let mv = handle_key(key(KeyCode::Char('j'), KeyModifiers::ALT, KeyEventKind::Press), &mut state, false);
+        assert!(matches!(mv, Some(Message::MoveRight)));
+    }
+
+    #[test]
+    fn reset_releases_space() {
+        let mut state = EventState::new(true, false);
+        handle_key(space(KeyEventKind::Press), &mut state, false);
+        state.reset();
+        let mv = handle_key(key(KeyCode::Char('f'), KeyModifiers::ALT, KeyEventKind::Press), &mut state, false);
+        assert!(matches!(mv, Some(Message::MoveLeft)));
+    }
+
+    #[test]
+    fn alt_keys() {
+        let mut state = EventState::new(true, false);
+        let alt = |c| key(KeyCode::Char(c), KeyModifiers::ALT, KeyEventKind::Press);
+        assert!(matches!(handle_key(alt('u'), &mut state, false), Some(Message::PageUp)));
+        assert!(matches!(handle_key(alt('U'), &mut state, false), Some(Message::OpenInsertUnicode)));
+    }
+
+    #[test]
+    fn sequences() {
+        let mut state = EventState::new(true, false);
+        let ctrl = |c| key(KeyCode::Char(c), KeyModifiers::CONTROL, KeyEventKind::Press);
+        assert!(matches!(handle_key(ctrl('x'), &mut state, false), Some(Message::KeyPrefix(prefix)) if prefix == "C-x"));
+        assert!(matches!(handle_key(ctrl('s'), &mut state, false), Some(Message::Save)));
+        // on its own it's saving again
+        assert!(matches!(handle_key(ctrl('s'), &mut state, false), Some(Message::Save)));
+        handle_key(ctrl('x'), &mut state, false);
+        assert!(matches!(handle_key(ctrl('q'), &mut state, false), Some(Message::Notification(..))));
+        assert!(state.pending.is_empty());
+        // sequences time out
+        handle_key(ctrl('x'), &mut state, false);
+        state.pending_since -= SEQUENCE_TIMEOUT;
+        assert!(matches!(handle_key(ctrl('s'), &mut state, false), Some(Message::Save)));
+        assert!(state.pending.is_empty());
+    }
+
+    #[test]
+    fn modal_sequences() {
+        let mut state = EventState::new(true, true);
+        let press = |c| key(KeyCode::Char(c), KeyModifiers::NONE, KeyEventKind::Press);
+        assert!(matches!(handle_key(press('g'), &mut state, false), Some(Message::KeyPrefix(_))));
+        assert!(matches!(handle_key(press('g'), &mut state, false), Some(Message::ToTop)));
+        handle_key(press('i'), &mut state, false);
+        assert!(matches!(handle_key(press('g'), &mut state, false), Some(Message::InsertChar('g'))));
+    }
+
+    #[test]
+    fn modal_switches_modes() {
+        let mut state = EventState::new(true, true);
+        let press = |c| key(KeyCode::Char(c), KeyModifiers::NONE, KeyEventKind::Press);
+        assert!(matches!(handle_key(press('j'), &mut state, false), Some(Message::MoveDown)));
+        assert!(handle_key(press('q'), &mut state, false).is_none());
+        // utilities still receive typed characters
+        assert!(matches!(handle_key(press('j'), &mut state, true), Some(Message::InsertChar('j'))));
+        handle_key(press('i'), &mut state, false);
+        assert_eq!(state.mode, Mode::Insert);
+        assert!(matches!(handle_key(press('j'), &mut state, false), Some(Message::InsertChar('j'))));
+        handle_key(key(KeyCode::Esc, KeyModifiers::NONE, KeyEventKind::Press), &mut state, false);
+        assert_eq!(state.mode, Mode::Normal);
+    }
+}
@@ -1,32 +1,41 @@
-use std::{fs, io};
+use std::{fs, io, path::{Path, PathBuf}, sync::OnceLock};
 use dirs;
 
 use tracing::{info, level_filters::LevelFilter, Level};
-use tracing_subscriber::{fmt::{format::FmtSpan, writer::MakeWriterExt}, layer::SubscriberExt, Layer, Registry};
+use tracing_subscriber::{fmt::{format::FmtSpan, writer::MakeWriterExt}, layer::SubscriberExt, reload, Layer, Registry};
+
+/// Handle to change the log level at runtime
+static LEVEL_HANDLE: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Where the log is written to
+static LOG_PATH: OnceLock<PathBuf> = OnceLock::new();
 
 pub fn setup_logging(args: &crate::Args) -> io::Result<()> {
+    let path = args.logfile.clone().unwrap_or(
+        dirs::cache_dir().ok_or_else(|| io::Error::other("failed to find cache dir"))?
+        .join("atto.log")
+    );
     let file = fs::File::options()
         .write(true)
         .append(true)
         .create(true)
-        .open(
-            args.logfile.clone().unwrap_or(
-                dirs::cache_dir().ok_or_else(|| io::Error::other("failed to find cache dir"))?
-                .join("atto.log")
-            )
-        )?;
+        .open(&path)?;
+    let _ = LOG_PATH.set(path);
 
     let level = if args.debug || cfg!(debug_assertions) { Level::TRACE } else { Level::INFO };
 
+    let (level_filter, level_handle) = reload::Layer::new(LevelFilter::from_level(level));
+    let _ = LEVEL_HANDLE.set(level_handle);
+
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_line_number(true)
         .with_writer(file)
         .with_target(true)
         .with_ansi(true)
-        .with_span_events(FmtSpan::CLOSE)
-        .with_filter(LevelFilter::from_level(level));
+        .with_span_events(FmtSpan::CLOSE);
 
     let subscriber = Registry::default()
+        .with(level_filter)
         .with(fmt_layer);
 
     let _ = tracing::subscriber::set_global_default(subscriber);
@@ -36,6 +45,26 @@ pub fn setup_logging(args: &crate::Args) -> io::Result<()> {
     Ok(())
 }
 
+/// The path of the log file (if logging was set up)
+pub fn log_path() -> Option<&'static Path> {
+    LOG_PATH.get().map(PathBuf::as_path)
+}
+
+/// The current log level (if logging was set up)
+pub fn log_level() -> Option<LevelFilter> {
+    LEVEL_HANDLE.get()?.clone_current()
+}
+
+/// Change the log level at runtime
+pub fn set_log_level(level: LevelFilter) -> io::Result<()> {
+    LEVEL_HANDLE.get()
+        .ok_or_else(|| io::Error::other("logging is not set up"))?
+        .reload(level)
+        .map_err(io::Error::other)?;
+    info!("log level changed to {level}");
+    Ok(())
+}
+
 pub trait LogError {
     /// If this result is an error, log it as such
     fn log(self) -> Self;
@@ -20,14 +20,29 @@ mod themes;
 mod syntect_tui;
 mod notification;
 mod utilities;
+mod recovery;
+mod config;
+mod clipboard;
+mod encoding;
+mod columns;
+mod project;
+mod wrap;
+mod transform;
+mod undo;
+mod status;
+mod bidi;
+mod digraph;
+mod complete;
+mod format;
 
 use logging::{setup_logging, LogError};
-use ratatui::{prelude::{Backend, CrosstermBackend}, Terminal};
+use ratatui::{prelude::{Backend, CrosstermBackend}, style::{Color, Style}, Terminal};
 use tracing::info;
 use view::View;
 use model::Model;
 use handle_event::handle_event;
 use buffer::Buffer;
+use notification::Notification;
 
 #[cfg(all(feature = "onig", feature = "fancy_regex"))]
 compile_error!("feature \"onig\" and feature \"fancy_regex\" cannot be enabled at the same time");
@@ -49,10 +64,24 @@ struct Args {
     debug: bool,
     #[arg(long, help="use an alternative logfile path")]
     logfile: Option<PathBuf>,
+    #[arg(long, help="use an alternative config file path")]
+    config: Option<PathBuf>,
     #[arg(long, help="do not alter the buffer")]
     readonly: bool,
     #[arg(long, help="visualize whitespace")]
     whitespace: bool,
+    #[arg(long, help="lines to keep visible around the cursor")]
+    scrolloff: Option<usize>,
+    #[arg(long, help="use modal (normal/insert) editing")]
+    modal: bool,
+    #[arg(long, help="read and write files in this encoding (like latin1 or windows-1252)")]
+    encoding: Option<String>,
+    #[arg(long, help="the root of the project (found from the files if not given)")]
+    root: Option<PathBuf>,
+    #[arg(long, help="leave the mouse to the terminal (to select text with it)")]
+    no_mouse: bool,
+    #[arg(short, long, help="search for this pattern on startup")]
+    search: Option<String>,
     files: Option<Vec<String>>
 }
 
@@ -61,36 +90,98 @@ fn main() -> anyhow::Result<()> {
     let _ = setup_logging(&args);
     info!("Launched with {args:?}");
 
+    let (mut config, config_error) = match config::load(args.config.clone()) {
+        Ok(config) => (config, None),
+        Err(e) => {
+            tracing::error!("Failed to load config: {e}");
+            (config::Config::default(), Some(e))
+        },
+    };
+    config.whitespace |= args.whitespace;
+    config.scrolloff = args.scrolloff.unwrap_or(config.scrolloff);
+    config.modal |= args.modal;
+    config.mouse &= !args.no_mouse;
+    let status_error = status::validate(&config.status_format).err();
+    if let Some(e) = &status_error {
+        tracing::warn!("Invalid status_format: {e}");
+        config.status_format = status::DEFAULT.to_owned();
+    }
+
+    let encoding = match &args.encoding {
+        Some(label) => Some(encoding_rs::Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| anyhow::anyhow!("unknown encoding {label:?}")).log()?),
+        None => None,
+    };
+
+    let root = args.root.clone().or(config.root.clone()).unwrap_or_else(|| {
+        let start = project::start_dir(args.files.as_deref().unwrap_or_default());
+        project::find_root(&start, &config.root_markers).unwrap_or(start)
+    });
+    info!("Project root is {root:?}");
+
     let buffers = match args.files {
-        Some(files) => read_files(files),
+        Some(files) => read_files(files, encoding),
         None => io::Result::Ok(vec![Buffer::empty()]),
     }.log()?;
 
+    tui::set_mouse_capture(config.mouse);
     let mut terminal = tui::init().log()?;
 
     tui::install_panic_hook();
 
     let theme_set = themes::theme_set().log()?;
-    let mut model = Model::new(buffers, theme_set, terminal.size().unwrap());
-    model.show_whitespace = args.whitespace;
+    let mut event_state = handle_event::EventState::new(tui::keyboard_enhanced(), config.modal);
 
-    let mut event_state = handle_event::EventState::default();
+    tui::set_cursor_shape(event_state.cursor_shape(config.cursor_shape));
+
+    let mut model = Model::new(buffers, theme_set, terminal.size().unwrap(), config);
+    model.debug |= args.debug;
+    model.root = root;
+    if let Some(pattern) = args.search {
+        model.search(pattern);
+    }
+    if let Some(e) = config_error {
+        model.notification = Some(Notification::new(
+            format!("Error in config: {e}"),
+            Style::new().bg(Color::Red).fg(Color::White)
+        ));
+    } else if let Some(e) = status_error {
+        model.notification = Some(Notification::new(
+            format!("Invalid status_format, using the default: {e}"),
+            Style::new().bg(Color::Yellow).fg(Color::Black)
+        ));
+    } else if let Some(names) = readonly_names(&model.buffers) {
+        model.notification = Some(Notification::new(
+            format!("Opened read-only (no permission to write): {names}"),
+            Style::new().bg(Color::White).fg(Color::Black)
+        ));
+    }
 
     terminal.draw(|frame| model.view(frame))?;
     TERMINAL.set(Mutex::new(terminal)).unwrap();
     while model.running {
         let mut msg = handle_event(&model, &mut event_state)?;
+        if msg.is_none() {
+            continue;
+        }
         while msg.is_some() {
             msg = model.update(msg.unwrap());
             TERMINAL.get().unwrap().lock().unwrap().draw(|frame| model.view(frame))?;
         }
+        recovery::snapshot(&model.buffers);
     }
 
     tui::restore()?;
     Ok(())
 }
 
-fn read_files(paths: Vec<String>) -> io::Result<Vec<Buffer>> {
+/// The files that could only be opened read-only, for telling about it on startup
+fn readonly_names(buffers: &[Buffer]) -> Option<String> {
+    let names: Vec<&str> = buffers.iter().filter(|b| b.opened_readonly).map(|b| b.name.as_str()).collect();
+    (!names.is_empty()).then(|| names.join(", "))
+}
+
+fn read_files(paths: Vec<String>, encoding: Option<&'static encoding_rs::Encoding>) -> io::Result<Vec<Buffer>> {
     let mut buffers: Vec<Buffer> = Vec::with_capacity(paths.len());
     for path in paths.iter() {
         let (file, readonly) = match fs::File::options().create(true).read(true).write(true).open(path) {
@@ -104,28 +195,94 @@ fn read_files(paths: Vec<String>) -> io::Result<Vec<Buffer>> {
             },
         };
 
-        buffers.push(Buffer::new(path.clone(), file, readonly));
+        let mut buffer = Buffer::new(path.clone(), file, readonly, encoding)?;
+        if buffers.iter().any(|b| b.canonical.is_some() && b.canonical == buffer.canonical) {
+            tracing::info!("{path:?} is already open");
+            continue;
+        }
+        if fs::symlink_metadata(path)?.is_symlink() {
+            buffer.symlink = Some(fs::read_link(path)?);
+            tracing::debug!("{path:?} is a symlink to {:?}", buffer.symlink);
+        }
+        buffers.push(buffer);
     }
     Ok(buffers)
 }
 
 mod tui {
-    use std::{io::{self, stdout, Stdout}, panic};
-    use crossterm::{terminal::*, event::*, ExecutableCommand, QueueableCommand};
+    use std::{io::{self, stdout, Stdout}, panic, sync::{atomic::{AtomicBool, Ordering}, Mutex, OnceLock}};
+    use crossterm::{cursor::SetCursorStyle, terminal::*, event::*, ExecutableCommand, QueueableCommand};
+
+    use crate::config::CursorShape;
     use ratatui::{Terminal, backend::{CrosstermBackend, Backend}};
 
+    /// If the terminal supports the kitty keyboard protocol.
+    /// This is only queried once as the query may block.
+    static KEYBOARD_ENHANCEMENT: OnceLock<bool> = OnceLock::new();
+
+    /// The cursor shape to restore after suspending the tui
+    static CURSOR_SHAPE: Mutex<CursorShape> = Mutex::new(CursorShape::Default);
+
+    /// If mouse events are captured, which keeps the terminal from selecting text
+    static MOUSE_CAPTURE: AtomicBool = AtomicBool::new(true);
+
+    /// Capture the mouse or leave it to the terminal
+    pub fn set_mouse_capture(capture: bool) {
+        MOUSE_CAPTURE.store(capture, Ordering::Relaxed);
+        let result = match capture {
+            true => stdout().execute(EnableMouseCapture).map(|_| ()),
+            false => stdout().execute(DisableMouseCapture).map(|_| ()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to change mouse capture: {e:?}");
+        }
+    }
+
+    pub fn mouse_capture() -> bool {
+        MOUSE_CAPTURE.load(Ordering::Relaxed)
+    }
+
+    /// Change the shape of the cursor.
+    /// Terminals that don't support this ignore it.
+    pub fn set_cursor_shape(shape: CursorShape) {
+        *CURSOR_SHAPE.lock().unwrap() = shape;
+        let _ = stdout().execute(shape.style());
+    }
+
+    /// If the terminal reports key releases (and other enhancements)
+    pub fn keyboard_enhanced() -> bool {
+        *KEYBOARD_ENHANCEMENT.get_or_init(|| {
+            let supported = supports_keyboard_enhancement().unwrap_or_else(|e| {
+                tracing::warn!("Failed to query keyboard enhancement support: {e:?}");
+                false
+            });
+            if supported {
+                tracing::info!("Keyboard enhancement supported, using the kitty keyboard protocol");
+            } else {
+                tracing::info!("Keyboard enhancement not supported, using fallback keybinds");
+            }
+            supported
+        })
+    }
+
     pub fn setup() -> io::Result<()> {
         stdout().execute(EnterAlternateScreen)?;
         enable_raw_mode()?;
-        stdout().queue(EnableMouseCapture)?;
+        if mouse_capture() {
+            stdout().queue(EnableMouseCapture)?;
+        }
         // https://docs.rs/crossterm/latest/crossterm/event/struct.KeyboardEnhancementFlags.html
-        stdout().queue(PushKeyboardEnhancementFlags(
-            KeyboardEnhancementFlags::REPORT_EVENT_TYPES
-            | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
-            | KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
-            | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS
-        ))?;
+        if keyboard_enhanced() {
+            stdout().queue(PushKeyboardEnhancementFlags(
+                KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+                | KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS
+            ))?;
+        }
         stdout().queue(EnableBracketedPaste)?;
+        stdout().queue(EnableFocusChange)?;
+        stdout().queue(CURSOR_SHAPE.lock().unwrap().style())?;
         Ok(())
     }
 
@@ -135,10 +292,14 @@ mod tui {
     }
 
     pub fn restore() -> io::Result<()> {
-        stdout().execute(PopKeyboardEnhancementFlags)?;
+        if keyboard_enhanced() {
+            stdout().execute(PopKeyboardEnhancementFlags)?;
+        }
         stdout().execute(DisableMouseCapture)?;
         stdout().execute(LeaveAlternateScreen)?;
         stdout().queue(DisableBracketedPaste)?;
+        stdout().queue(DisableFocusChange)?;
+        stdout().queue(SetCursorStyle::DefaultUserShape)?;
         disable_raw_mode()?;
         Ok(())
     }
@@ -150,6 +311,13 @@ mod tui {
             disable_raw_mode().unwrap();
             tracing::error!("PANIC at {}: {}", info.location().unwrap(), info.payload_as_str().unwrap_or(""));
             original_hook(info);
+            match crate::recovery::dump() {
+                Ok(paths) => for path in paths {
+                    tracing::info!("Recovered unsaved changes to {path:?}");
+                    eprintln!("Recovered unsaved changes to {}", path.display());
+                },
+                Err(e) => tracing::error!("Failed to recover unsaved changes: {e:?}"),
+            }
         }));
     }
 }
@@ -1,12 +1,24 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Mutex};
+use std::{cell::RefCell, cmp, collections::HashMap, io, path::{Path, PathBuf}, rc::Rc, sync::{Arc, Mutex}, time::{Duration, Instant}};
 
-use ratatui::{layout::Size, prelude::Backend, style::{Color, Style}, Terminal};
+use ratatui::{layout::{Position, Rect, Size}, prelude::Backend, style::{Color, Style}, Terminal};
 use syntect::{highlighting::{ThemeSet, Theme}, parsing::SyntaxSet};
-use tracing::{debug, error};
+use tracing::{debug, error, info, level_filters::LevelFilter};
 
-use crate::{buffer::{self, Buffer}, utilities::{self, developer::DeveloperModel, Utility, UtilityWindow}};
+use crate::{buffer::{self, Buffer}, utilities::{self, completion::CompletionModel, developer::DeveloperModel, undo_tree::UndoTreeModel, Utility, UtilityWindow}};
 use crate::parse::ParseCache;
 use crate::notification::Notification;
+use crate::logging;
+use crate::config::{Config, PathStyle, QuitPrompt};
+use crate::clipboard::Clipboard;
+use crate::transform;
+use crate::wrap;
+use crate::view::View;
+use crate::bidi;
+use crate::digraph;
+use crate::complete;
+use crate::format;
+use crate::project;
+use crate::undo::{Change, UndoState};
 
 pub struct Model {
     /// What buffer is selected
@@ -29,10 +41,66 @@ pub struct Model {
     pub notification: Option<Notification>,
     /// visualize whitespace
     pub show_whitespace: bool,
+    /// number lines relative to the cursor
+    pub relative_numbers: bool,
+    /// draw a line at every level of indentation
+    pub show_indent_guides: bool,
+    /// highlight the line and column of the cursor
+    pub show_crosshair: bool,
+    /// color brackets by their depth
+    pub show_rainbow_brackets: bool,
+    /// wrap long lines instead of scrolling sideways
+    pub wrap: bool,
+    /// display right-to-left lines right to left
+    pub bidi: bool,
+    /// center the text with margins, like a book
+    pub reading: bool,
+    /// How searches match case
+    pub case: buffer::Case,
+    /// run shell commands in the directory of the current file
+    pub shell_in_buffer_dir: bool,
+    /// show the output of shell commands in a buffer instead of a notification
+    pub shell_output_in_buffer: bool,
+    /// The keys typed so far of a sequence like `C-x C-s`
+    pub key_prefix: String,
+    /// What slow operation is running (like saving)
+    pub busy: Option<String>,
+    /// Where utilities are drawn (updated when drawing)
+    pub utility_area: Rect,
+    /// The user configuration
+    pub config: Config,
+    /// Relative paths are resolved against the root of the project
+    pub root: PathBuf,
+    /// Invert the buffer until the next tick
+    pub flashing: bool,
+    /// The messages of the last edit
+    last_edit: Vec<Message>,
+    /// If the last edit is still being recorded
+    editing: bool,
+    /// If the last edit is being repeated
+    repeating: bool,
+    /// The changes made by repeating the last edit, undone together
+    repeated: Vec<Change>,
+    /// When the wheel last scrolled and how many ticks followed in quick succession
+    last_scroll: Option<(Instant, usize)>,
+    /// The files of recently closed buffers and where their cursor was, latest last
+    closed: Vec<(String, usize)>,
+    /// The interactive replace going on
+    replacing: Option<Replacing>,
+    pub clipboard: Clipboard,
+    /// Enables developer features (always on in debug builds)
+    pub debug: bool,
+}
+
+/// An interactive replace going through the search results
+struct Replacing {
+    replacement: String,
+    /// How many search results were replaced so far
+    replaced: usize,
 }
 
 impl Model {
-    pub fn new<'a>(mut buffers: Vec<Buffer>, theme_set: ThemeSet, viewport: Size) -> Model {
+    pub fn new<'a>(mut buffers: Vec<Buffer>, theme_set: ThemeSet, viewport: Size, config: Config) -> Model {
         let parse_caches = (|| {
             let mut map = HashMap::new();
             for buf in &buffers {
@@ -43,9 +111,9 @@ impl Model {
 
         let syntax_set = SyntaxSet::load_defaults_newlines();
         for buffer in &mut buffers {
-            buffer.find_syntax(&syntax_set);
+            prepare_opened(buffer, &syntax_set, &config);
         }
-        Model {
+        let mut model = Model {
             buffers: buffers,
             selected: 0,
             running: true,
@@ -57,15 +125,46 @@ impl Model {
             theme: "dracula".to_owned(),
             viewport,
             notification: None,
-            show_whitespace: false,
-        }
+            show_whitespace: config.whitespace,
+            relative_numbers: config.relative_numbers,
+            root: std::env::current_dir().unwrap_or_default(),
+            show_indent_guides: config.indent_guides,
+            show_crosshair: config.crosshair,
+            show_rainbow_brackets: config.rainbow_brackets,
+            wrap: config.wrap,
+            bidi: config.bidi,
+            reading: false,
+            shell_in_buffer_dir: config.shell_in_buffer_dir,
+            shell_output_in_buffer: config.shell_output_buffer,
+            case: if config.smartcase { buffer::Case::Smart } else { buffer::Case::Sensitive },
+            key_prefix: String::new(),
+            busy: None,
+            replacing: None,
+            utility_area: Rect::default(),
+            config,
+            debug: cfg!(debug_assertions),
+            clipboard: Clipboard::default(),
+            flashing: false,
+            last_edit: vec![],
+            editing: false,
+            repeating: false,
+            repeated: vec![],
+            last_scroll: None,
+            closed: vec![],
+        };
+        model.notice_rtl();
+        model
     }
 
     #[tracing::instrument(skip(self), level="debug")]
     pub fn update(&mut self, msg: Message) -> Option<Message> {
+        // a sequence of keys ends with any other key
+        if !matches!(msg, Message::KeyPrefix(_) | Message::Tick) {
+            self.key_prefix.clear();
+        }
+
         // remove notification if elapsed
-        // (the handling of this makes it so that if the user does not somehow create a message)
-        // the notification will hang around
+        // (while a notification is shown ticks are sent so this runs)
         if let Some(notification) = &self.notification {
             if notification.expired() {
                 self.notification = None;
@@ -78,6 +177,10 @@ impl Model {
             Some(UtilityWindow::Confirm(confirm)) => confirm.update(msg),
             Some(UtilityWindow::Developer(developer)) => developer.update(msg),
             Some(UtilityWindow::Shell(shell)) => shell.update(msg),
+            Some(UtilityWindow::Command(command)) => command.update(msg),
+            Some(UtilityWindow::Entry(entry)) => entry.update(msg),
+            Some(UtilityWindow::UndoTree(undo_tree)) => undo_tree.update(msg),
+            Some(UtilityWindow::Completion(completion)) => completion.update(msg),
             None => Some(msg),
         };
 
@@ -87,104 +190,602 @@ impl Model {
 
         let msg = new_msg.unwrap();
 
+        if msg.moves_cursor() {
+            self.current_buffer_mut().selection = None;
+        }
+
+        self.record_edit(&msg);
+
+        let edit = msg.is_edit();
+        // the content before the change, to find the change for undo
+        let before = msg.diffs_content().then(|| self.current_buffer().content.clone());
+        let selected = self.selected;
+        let position = self.current_buffer().position;
+        let next = self.handle(msg);
+        // recorded in the buffer that was edited even if another one was selected since
+        let changes = self.buffers.get_mut(selected).map(|buffer| std::mem::take(&mut buffer.changes)).unwrap_or_default();
+        match before {
+            Some(content) => self.record_undo(selected, &content, position),
+            None => self.record_changes(selected, changes, position),
+        }
+        // coming back to a buffer doesn't continue the changes made before leaving it
+        if self.selected != selected {
+            if let Some(buffer) = self.buffers.get_mut(selected) {
+                buffer.undo.seal();
+            }
+            self.notice_rtl();
+        }
+        // the search results moved with the text
+        if edit {
+            self.current_buffer_mut().refresh_highlights();
+        }
+        next
+    }
+
+    fn handle(&mut self, msg: Message) -> Option<Message> {
         match msg {
             Message::NoMessage => {},
             Message::NextBuffer => self.selected = (self.selected + 1) % self.buffers.len(),
             Message::PreviousBuffer => self.selected = (self.selected + self.buffers.len() - 1) % self.buffers.len(),
             Message::QuitNoSave => self.running = false,
             Message::Quit => {
-                match self.current_buffer().dirty() {
-                    Ok(true) => {
+                let dirty = self.dirty_buffers();
+                match (dirty.len(), self.config.quit_prompt) {
+                    (0, _) => self.running = false,
+                    (1, _) | (_, QuitPrompt::Each) => return Some(Message::QuitBuffers(dirty)),
+                    (n, QuitPrompt::All) => {
                         self.utility = Some(UtilityWindow::Confirm(
                             utilities::confirm::ConfirmModel::new(
-                                String::from("There are unsaved changes. Do you want to save?"),
+                                format!("{n} buffers have unsaved changes. Save all (y), discard all (n) or cancel (c)?"),
                                 vec![
-                                    ('y', Message::Double(Box::new(Message::Save), Box::new(Message::Quit))),
+                                    ('y', Message::Double(Box::new(Message::SaveAll), Box::new(Message::Quit))),
                                     ('n', Message::QuitNoSave),
+                                    ('c', Message::NoMessage),
                                 ]
-                        )));
-                    },
-                    Ok(false) => self.running = false,
-                    Err(err) => {
-                        error!("{err:?}");
-                        self.running = false;
+                            ).with_cancel(Message::NoMessage)
+                        ));
                     },
                 }
             },
-            Message::ScrollDown => {
-                if (self.current_buffer().content.lines().count() - self.viewport.height as usize) > self.current_buffer_mut().top {
-                 self.current_buffer_mut().top += 1;
+            Message::QuitBuffers(indices) => {
+                // buffers that are saved or discarded are left out of the next check
+                let Some(&i) = indices.iter().find(|i| self.buffers[**i].dirty().unwrap_or(false)) else {
+                    self.running = false;
+                    return None;
+                };
+                self.selected = i;
+                let rest: Vec<usize> = indices.iter().copied().filter(|j| *j != i).collect();
+                self.utility = Some(UtilityWindow::Confirm(
+                    utilities::confirm::ConfirmModel::new(
+                        format!("{} has unsaved changes. Do you want to save?", self.buffers[i].name),
+                        vec![
+                            ('y', Message::Double(Box::new(Message::Save), Box::new(Message::QuitBuffers(indices)))),
+                            ('n', Message::QuitBuffers(rest)),
+                            ('c', Message::NoMessage),
+                        ]
+                    ).with_cancel(Message::NoMessage)
+                ));
+            },
+            Message::SaveAll => {
+                let mut failed = vec![];
+                for i in self.dirty_buffers() {
+                    if self.formats_on_save(i) {
+                        if let Err(e) = self.format_buffer(i) {
+                            failed.push(format!("{}: {e}", self.buffers[i].name));
+                            continue;
+                        }
+                    }
+                    if let Err(e) = self.busy(&format!("Saving {}…", self.buffers[i].name), |m| m.buffers[i].save()) {
+                        tracing::warn!("Failed to save {}: {e:?}", self.buffers[i].name);
+                        failed.push(format!("{}: {e}", self.buffers[i].name));
+                    }
+                }
+                if !failed.is_empty() {
+                    self.flash();
+                    return Some(Message::Notification(
+                        format!("Error writing files:\n{}", failed.join("\n")),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
                 }
             },
-            Message::ScrollUp => self.current_buffer_mut().top = self.current_buffer_mut().top.checked_sub(1).unwrap_or_default(),
+            Message::ScrollDown => {
+                let lines = self.scroll_lines();
+                let bottom = self.current_buffer().content.lines().count().saturating_sub(self.text_height());
+                let top = self.current_buffer().top;
+                self.current_buffer_mut().top = cmp::max(top, cmp::min(top + lines, bottom));
+            },
+            Message::ScrollUp => {
+                let lines = self.scroll_lines();
+                self.current_buffer_mut().top = self.current_buffer().top.saturating_sub(lines);
+            },
+            Message::ScrollLeft => self.current_buffer_mut().left = self.current_buffer_mut().left.saturating_sub(1),
+            Message::ScrollRight => self.current_buffer_mut().left += 1,
             Message::OpenHelp => self.utility = Some(UtilityWindow::Help(utilities::help::HelpModel())),
             Message::OpenFind => self.utility = Some(UtilityWindow::Find(utilities::find::FindModel::new())),
             Message::Escape => return Some(Message::CloseUtility),
             Message::CloseUtility => self.utility = None,
             Message::InsertChar(chr) => {
+                if self.current_buffer().selection.is_some() {
+                    if let Some(close) = closing_pair(chr) {
+                        self.current_buffer_mut().surround_selection(chr, close);
+                        return None;
+                    }
+                }
+                self.current_buffer_mut().delete_selection();
+                if self.config.auto_indent && matches!(chr, '}' | ')' | ']') {
+                    self.current_buffer_mut().dedent_closing_bracket(chr);
+                }
                 self.current_buffer_mut().insert(chr);
                 self.may_scroll = true;
+                let completing = matches!(self.utility, Some(UtilityWindow::Completion(_)));
+                if completing || (self.config.auto_complete && self.utility.is_none() && buffer::is_word_char(chr, &self.config.word_chars)) {
+                    return Some(Message::UpdateCompletion);
+                }
             },
             Message::MoveLeft => {
-                self.current_buffer_mut().move_left();
+                let wrap = self.config.wrap_cursor;
+                self.current_buffer_mut().move_left(wrap);
                 self.may_scroll = true;
             },
             Message::MoveRight => {
-                self.current_buffer_mut().move_right();
+                let wrap = self.config.wrap_cursor;
+                self.current_buffer_mut().move_right(wrap);
                 self.may_scroll = true;
             },
             Message::MoveUp => {
-                self.current_buffer_mut().move_up();
+                match self.wrap {
+                    true => {
+                        let width = self.text_width();
+                        self.current_buffer_mut().move_up_wrapped(width);
+                    },
+                    false => self.current_buffer_mut().move_up(),
+                }
                 self.may_scroll = true;
             },
             Message::MoveDown => {
-                self.current_buffer_mut().move_down();
+                match self.wrap {
+                    true => {
+                        let width = self.text_width();
+                        self.current_buffer_mut().move_down_wrapped(width);
+                    },
+                    false => self.current_buffer_mut().move_down(),
+                }
                 self.may_scroll = true;
             },
             Message::PageUp => {
-                let height = self.viewport.height as usize;
-                self.current_buffer_mut().page_up(height);
+                let height = self.text_height();
+                let overlap = self.config.page_overlap;
+                self.current_buffer_mut().page_up(height, overlap);
                 // self.may_scroll = true;
             },
             Message::PageDown => {
-                let height = self.viewport.height as usize;
-                self.current_buffer_mut().page_down(height);
+                let height = self.text_height();
+                let overlap = self.config.page_overlap;
+                self.current_buffer_mut().page_down(height, overlap);
                 // self.may_scroll = true;
             },
             Message::Backspace => {
                 let cur = self.current_buffer_mut();
-                if cur.position > 0 {
-                    cur.content.remove(cur.position-1);
-                    return Some(Message::MoveLeft);
+                if !cur.delete_selection() {
+                    cur.backspace();
+                }
+                self.may_scroll = true;
+                if matches!(self.utility, Some(UtilityWindow::Completion(_))) {
+                    return Some(Message::UpdateCompletion);
                 }
             },
             Message::Delete => {
                 let cur = self.current_buffer_mut();
-                if cur.position < cur.content.len() {
-                    cur.content.remove(cur.position);
+                if !cur.delete_selection() {
+                    cur.delete();
                 }
+                self.may_scroll = true;
             },
             Message::JumpWordLeft => {
-                self.current_buffer_mut().move_word_left();
+                let word_chars = self.config.word_chars.clone();
+                self.current_buffer_mut().move_word_left(&word_chars);
                 self.may_scroll = true;
             },
             Message::JumpWordRight => {
-                self.current_buffer_mut().move_word_right();
+                let word_chars = self.config.word_chars.clone();
+                self.current_buffer_mut().move_word_right(&word_chars);
                 self.may_scroll = true;
             },
-            Message::GotoStartOfLine => self.current_buffer_mut().goto_start_of_line(),
+            Message::GotoStartOfLine => if self.config.smart_home {
+                self.current_buffer_mut().goto_first_non_whitespace();
+            } else {
+                self.current_buffer_mut().goto_start_of_line();
+            },
             Message::GotoEndOfLine => self.current_buffer_mut().goto_end_of_line(),
-            Message::Enter => return Some(Message::InsertChar('\n')),
+            Message::Enter => {
+                let buffer = self.current_buffer_mut();
+                buffer.delete_selection();
+                if self.config.auto_indent {
+                    self.current_buffer_mut().insert_newline_indented();
+                } else {
+                    self.current_buffer_mut().insert('\n');
+                }
+                self.may_scroll = true;
+            },
+            Message::OpenLineBelow => {
+                let indent = self.config.auto_indent;
+                self.current_buffer_mut().open_line_below(indent);
+                self.may_scroll = true;
+            },
+            Message::OpenLineAbove => {
+                let indent = self.config.auto_indent;
+                self.current_buffer_mut().open_line_above(indent);
+                self.may_scroll = true;
+            },
             Message::Find(query) => {
-                self.current_buffer_mut().find(query);
+                let empty = query.is_empty();
+                let case = self.case;
+                self.current_buffer_mut().find(query, case);
+                if !empty && self.current_buffer().highlights.is_empty() {
+                    self.flash();
+                }
+                self.may_scroll = true;
+            },
+            Message::JumpNextHighlight | Message::JumpPreviousHighlight => {
+                if self.current_buffer().highlights.is_empty() {
+                    self.flash();
+                    return None;
+                }
+                match msg {
+                    Message::JumpNextHighlight => self.current_buffer_mut().jump_next_highlight(),
+                    _ => self.current_buffer_mut().jump_previous_highlight(),
+                }
+                self.may_scroll = true;
+            },
+            Message::Tick => {
+                self.flashing = false;
+                for (i, buffer) in self.buffers.iter_mut().enumerate().filter(|(_, b)| b.follow) {
+                    match buffer.read_appended() {
+                        Ok(true) if i == self.selected => self.may_scroll = true,
+                        Ok(_) => {},
+                        Err(e) => {
+                            error!("Failed to follow {}: {e:?}", buffer.name);
+                            buffer.follow = false;
+                        },
+                    }
+                }
+            },
+            Message::OpenInsertFile => self.utility = Some(UtilityWindow::Entry(
+                utilities::entry::EntryModel::new("Insert file", Message::InsertFile).with_path_completion(self.root.clone())
+            )),
+            Message::InsertFile(path) => {
+                let full_path = self.root.join(&path);
+                let content = self.busy("Reading…", |_| std::fs::read(full_path)).map_err(|e| e.to_string()).and_then(|bytes| {
+                    match String::from_utf8(bytes) {
+                        Ok(content) if !content.contains('\0') => Ok(content),
+                        _ => Err(String::from("not a UTF-8 text file")),
+                    }
+                });
+                match content {
+                    Ok(content) => return Some(Message::Paste(content)),
+                    Err(e) => {
+                        error!("Failed to insert {path}: {e}");
+                        self.flash();
+                        return Some(Message::Notification(
+                            format!("Failed to insert {path}: {e}"),
+                            Style::new().bg(Color::Red).fg(Color::White)
+                        ));
+                    },
+                }
+            },
+            Message::Complete => {
+                let completions = self.completions();
+                if completions.is_empty() {
+                    return Some(Message::Notification(
+                        String::from("No completions"),
+                        Style::new().bg(Color::White).fg(Color::Black)
+                    ));
+                }
+                self.utility = Some(UtilityWindow::Completion(CompletionModel::new(completions)));
+            },
+            Message::UpdateCompletion => {
+                let buffer = self.current_buffer();
+                let start = complete::prefix_start(&buffer.content, buffer.position, &self.config.word_chars);
+                let completions = match buffer.position - start >= complete::AUTO_COMPLETE_PREFIX {
+                    true => self.completions(),
+                    false => vec![],
+                };
+                self.utility = match completions.is_empty() {
+                    true => None,
+                    false => Some(UtilityWindow::Completion(CompletionModel::new(completions))),
+                };
+            },
+            Message::InsertCompletion(word) => {
+                let word_chars = self.config.word_chars.clone();
+                let buffer = self.current_buffer_mut();
+                let start = complete::prefix_start(&buffer.content, buffer.position, &word_chars);
+                if word.starts_with(&buffer.content[start..buffer.position]) {
+                    let rest = &word[buffer.position - start..];
+                    buffer.paste(rest);
+                }
+                self.may_scroll = true;
+            },
+            Message::OpenInsertUnicode => self.utility = Some(UtilityWindow::Entry(
+                utilities::entry::EntryModel::new("Insert code point (U+…) or digraph", Message::InsertUnicode)
+            )),
+            Message::InsertUnicode(input) => match digraph::parse(&input) {
+                Ok(c) => return Some(Message::InsertChar(c)),
+                Err(e) => {
+                    self.flash();
+                    return Some(Message::Notification(e, Style::new().bg(Color::Red).fg(Color::White)));
+                },
+            },
+            Message::OpenReplace => {
+                let count = self.current_buffer().highlights.len();
+                if count == 0 {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("No matches to replace"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                }
+                self.utility = Some(UtilityWindow::Entry(
+                    utilities::entry::EntryModel::new(&format!("Replace {count} matches with"), Message::ReplaceAll)
+                ));
+            },
+            Message::ReplaceAll(replacement) => {
+                let count = self.current_buffer().highlights.len();
+                if self.config.confirm_replace_all && count > 0 {
+                    let query = self.current_buffer().query.clone().unwrap_or_default();
+                    self.utility = Some(UtilityWindow::Confirm(
+                        utilities::confirm::ConfirmModel::new(
+                            format!("Replace {count} occurrences of {query:?} with {replacement:?}?"),
+                            vec![
+                                ('y', Message::ReplaceAllConfirmed(replacement)),
+                                ('n', Message::NoMessage)
+                            ]
+                        ).with_cancel(Message::NoMessage)
+                    ));
+                    return None;
+                }
+                return Some(Message::ReplaceAllConfirmed(replacement));
+            },
+            Message::ReplaceAllConfirmed(replacement) => {
+                let count = self.current_buffer_mut().replace_highlights(.., &replacement);
+                self.may_scroll = true;
+                if count == 0 {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("No matches to replace"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                }
+                return Some(Message::Notification(
+                    format!("Replaced {count} occurrence{}", if count == 1 { "" } else { "s" }),
+                    Style::new().bg(Color::Green).fg(Color::Black)
+                ));
+            },
+            Message::OpenReplaceEach => {
+                if self.current_buffer().highlights.is_empty() {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("No matches to replace"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                }
+                self.utility = Some(UtilityWindow::Entry(
+                    utilities::entry::EntryModel::new("Replace each match with", Message::ReplaceEach)
+                ));
+            },
+            Message::ReplaceEach(replacement) => {
+                self.replacing = Some(Replacing { replacement, replaced: 0 });
+                self.current_buffer_mut().position = 0;
+                return Some(Message::ReplacePrompt);
+            },
+            // ask about the next search result after the cursor, or finish
+            Message::ReplacePrompt => {
+                let position = self.current_buffer().position;
+                let next = self.current_buffer().highlights.iter().find(|(start, _)| *start >= position).copied();
+                let Some((start, end)) = next else { return Some(Message::ReplaceQuit) };
+                let buffer = self.current_buffer_mut();
+                buffer.position = start;
+                buffer.selection = Some((start, end));
                 self.may_scroll = true;
+                self.utility = Some(UtilityWindow::Confirm(
+                    utilities::confirm::ConfirmModel::new(
+                        String::from("Replace this match?"),
+                        vec![
+                            ('y', Message::ReplaceMatch(true)),
+                            ('n', Message::ReplaceMatch(false)),
+                            ('a', Message::ReplaceRest),
+                            ('q', Message::ReplaceQuit)
+                        ]
+                    ).with_cancel(Message::ReplaceQuit)
+                ));
+            },
+            Message::ReplaceMatch(replace) => {
+                let Some(replacing) = &mut self.replacing else { return None };
+                let buffer = &mut self.buffers[self.selected];
+                let position = buffer.position;
+                buffer.selection = None;
+                if replace {
+                    // every replacement is undone on its own
+                    buffer.undo.seal();
+                    buffer.replace_highlights(position..=position, &replacing.replacement);
+                    buffer.position = position + replacing.replacement.len();
+                    replacing.replaced += 1;
+                } else {
+                    buffer.position = buffer.highlights.iter().find(|(start, _)| *start == position).map_or(position + 1, |(_, end)| *end);
+                }
+                return Some(Message::ReplacePrompt);
+            },
+            Message::ReplaceRest => {
+                let Some(replacing) = &mut self.replacing else { return None };
+                let buffer = &mut self.buffers[self.selected];
+                let position = buffer.position;
+                buffer.selection = None;
+                buffer.undo.seal();
+                replacing.replaced += buffer.replace_highlights(position.., &replacing.replacement);
+                return Some(Message::ReplaceQuit);
+            },
+            Message::ReplaceQuit => {
+                let Some(replacing) = self.replacing.take() else { return None };
+                self.current_buffer_mut().selection = None;
+                let count = replacing.replaced;
+                return Some(Message::Notification(
+                    format!("Replaced {count} occurrence{}", if count == 1 { "" } else { "s" }),
+                    Style::new().bg(Color::Green).fg(Color::Black)
+                ));
+            },
+            Message::OpenWriteSelection => {
+                if self.current_buffer().selection.is_none() {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("Nothing is selected"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                }
+                self.utility = Some(UtilityWindow::Entry(
+                    utilities::entry::EntryModel::new("Write selection to", Message::WriteSelection).with_path_completion(self.root.clone())
+                ));
+            },
+            Message::WriteSelection(path) => {
+                let full_path = self.root.join(&path);
+                match self.busy("Writing…", |m| m.current_buffer().write_selection(&full_path)) {
+                    Ok(n) => return Some(Message::Notification(
+                        format!("Wrote {n} bytes to {path}"),
+                        Style::new().bg(Color::Green).fg(Color::Black)
+                    )),
+                    Err(e) => {
+                        tracing::warn!("{:?}", e);
+                        self.flash();
+                        return Some(Message::Notification(
+                            format!("Error writing {path}: {e}"),
+                            Style::new().bg(Color::Red).fg(Color::White)
+                        ));
+                    },
+                }
+            },
+            Message::OpenLog => {
+                let Some(path) = logging::log_path() else {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("There is no log file"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                };
+                let name = path.to_string_lossy().into_owned();
+                if let Some(i) = self.find_buffer(path) {
+                    self.selected = i;
+                    return Some(Message::Notification(
+                        format!("{name} is already open"),
+                        Style::new().bg(Color::White).fg(Color::Black)
+                    ));
+                }
+                let buffer = std::fs::File::open(path)
+                    .and_then(|file| Buffer::new(name.clone(), file, true, None));
+                match buffer {
+                    Ok(mut buffer) => {
+                        buffer.set_readonly(true);
+                        buffer.follow = true;
+                        buffer.position = buffer.content.len();
+                        self.parse_caches.insert(name, Rc::new(RefCell::new(ParseCache::new())));
+                        self.buffers.push(buffer);
+                        self.selected = self.buffers.len() - 1;
+                        self.may_scroll = true;
+                    },
+                    Err(e) => {
+                        error!("Failed to open the log: {e:?}");
+                        self.flash();
+                        return Some(Message::Notification(
+                            format!("Failed to open the log: {e}"),
+                            Style::new().bg(Color::Red).fg(Color::White)
+                        ));
+                    },
+                }
+            },
+            Message::ReopenClosed => {
+                let Some((name, position)) = self.closed.pop() else {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("No closed buffers to reopen"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                };
+                if let Some(i) = self.find_buffer(Path::new(&name)) {
+                    self.selected = i;
+                    return Some(Message::Notification(
+                        format!("{name} is already open"),
+                        Style::new().bg(Color::White).fg(Color::Black)
+                    ));
+                }
+                let buffer = std::fs::File::options().read(true).write(true).open(&name).map(|file| (file, false))
+                    .or_else(|_| std::fs::File::open(&name).map(|file| (file, true)))
+                    .and_then(|(file, readonly)| Buffer::new(name.clone(), file, readonly, None));
+                match buffer {
+                    Ok(mut buffer) => {
+                        prepare_opened(&mut buffer, &self.syntax_set, &self.config);
+                        // the file may have changed since
+                        buffer.position = (0..=cmp::min(position, buffer.content.len())).rev()
+                            .find(|i| buffer.content.is_char_boundary(*i))
+                            .unwrap_or(0);
+                        self.parse_caches.insert(name, Rc::new(RefCell::new(ParseCache::new())));
+                        self.buffers.push(buffer);
+                        self.selected = self.buffers.len() - 1;
+                        self.may_scroll = true;
+                    },
+                    Err(e) => {
+                        error!("Failed to reopen {name}: {e:?}");
+                        self.flash();
+                        return Some(Message::Notification(
+                            format!("Failed to reopen {name}: {e}"),
+                            Style::new().bg(Color::Red).fg(Color::White)
+                        ));
+                    },
+                }
+            },
+            Message::RepeatLastEdit => {
+                let edit = std::mem::take(&mut self.last_edit);
+                self.repeating = true;
+                for msg in edit.iter().cloned() {
+                    let mut next = Some(msg);
+                    while let Some(msg) = next {
+                        next = self.update(msg);
+                    }
+                }
+                self.repeating = false;
+                self.last_edit = edit;
+                let changes = std::mem::take(&mut self.repeated);
+                let undo = &mut self.current_buffer_mut().undo;
+                undo.seal();
+                undo.record_all(changes);
+                undo.seal();
             },
             Message::Save => {
-                if let Err(e) =  self.current_buffer_mut().save() {
+                // a file that fails to format isn't saved half way
+                if self.formats_on_save(self.selected) {
+                    if let Err(e) = self.format_buffer(self.selected) {
+                        self.flash();
+                        return Some(Message::Notification(
+                            format!("Not saved, formatting failed: {e}"),
+                            Style::new().bg(Color::Red).fg(Color::White)
+                        ));
+                    }
+                }
+                let conflicts = self.conflicting_buffers(self.selected);
+                if let Err(e) = self.busy("Saving…", |m| m.current_buffer_mut().save()) {
                     tracing::warn!("{:?}", e);
-                    return Some(Message::Notification(
+                    self.flash();
+                    let notification = Message::Notification(
                         format!("Error writing file: {e}"),
                         Style::new().bg(Color::Red).fg(Color::White)
+                    );
+                    // offer to write it with more privileges instead
+                    if e.kind() == io::ErrorKind::PermissionDenied {
+                        return Some(Message::Double(Box::new(notification), Box::new(Message::SaveAsRootConfirmation)));
+                    }
+                    return Some(notification);
+                } else if !conflicts.is_empty() {
+                    let names: Vec<String> = conflicts.iter().map(|i| format!("{}", i + 1)).collect();
+                    tracing::warn!("Saved {} over the changes of buffers {names:?}", self.current_buffer().name);
+                    return Some(Message::Notification(
+                        format!("SAVED, but buffer {} of this file has other changes", names.join(", ")),
+                        Style::new().bg(Color::Yellow).fg(Color::Black)
                     ));
                 } else {
                     return Some(Message::Notification(
@@ -196,6 +797,7 @@ impl Model {
             Message::SaveAsRoot => {
                 if let Err(e) = self.current_buffer_mut().save_as_root() {
                     tracing::error!("Error saving as root: {e:?}");
+                    self.flash();
                     return Some(Message::Notification(
                         format!("Error saving as root: {e}"),
                         Style::new().bg(Color::Red).fg(Color::White)
@@ -209,18 +811,488 @@ impl Model {
             },
             Message::Resize(x, y) => {
                 self.viewport = (x,y).into();
+                // keep the cursor in view of the new size
+                let height = self.text_height();
+                let scrolloff = self.config.scrolloff;
+                for buffer in &mut self.buffers {
+                    let line_count = buffer.content.split('\n').count();
+                    buffer.top = cmp::min(buffer.top, line_count.saturating_sub(1));
+                    buffer.scroll_view(height, scrolloff);
+                }
             },
             Message::MouseLeft(x, y) => {
-                self.current_buffer_mut().set_viewport_cursor_pos(x, y);
+                // clicks on a utility don't reach the buffer behind it
+                if let Some(utility) = &mut self.utility {
+                    if self.utility_area.contains(Position::new(x, y)) {
+                        return utility.click(x, y, self.utility_area);
+                    }
+                }
+                if y < self.tab_bar_height() {
+                    let tab = self.tabs(self.viewport.width).into_iter()
+                        .find(|(_, start, label)| (*start..start + label.chars().count() as u16).contains(&x));
+                    if let Some((i, _, _)) = tab {
+                        self.selected = i;
+                    }
+                    return None;
+                }
+                let x = x.saturating_sub(self.reading_margin() + self.gutter_width()) + self.current_buffer().left as u16;
+                let y = y - self.tab_bar_height();
+                let height = self.text_height();
+                let width = self.text_width();
+                let wrap = self.wrap;
+                let buffer = self.current_buffer_mut();
+                if wrap {
+                    let rows = wrap::screen_rows(&buffer.content, buffer.top, height, width);
+                    if let Some(row) = rows.get(y as usize).or(rows.last()) {
+                        buffer.position = wrap::byte_in_row(&buffer.content, *row, x as usize);
+                        buffer.prefered_col = None;
+                    }
+                    return None;
+                }
+                match buffer.visible_columns(height) {
+                    Some(columns) => {
+                        // map the clicked column back to a byte in the line
+                        let line_start = buffer.content.split_inclusive('\n').take(buffer.top + y as usize).map(str::len).sum::<usize>();
+                        let line = buffer.content[line_start.min(buffer.content.len())..].lines().next().unwrap_or_default();
+                        buffer.position = line_start.min(buffer.content.len()) + columns.byte_at(line, x as usize);
+                        buffer.prefered_col = None;
+                    },
+                    None => buffer.set_viewport_cursor_pos(x, y),
+                }
+            },
+            Message::KeyPrefix(prefix) => self.key_prefix = prefix,
+            Message::NewScratchBuffer => {
+                let mut buffer = Buffer::empty();
+                buffer.scratch = true;
+                self.parse_caches.entry(buffer.name.clone()).or_insert_with(|| Rc::new(RefCell::new(ParseCache::new())));
+                self.buffers.push(buffer);
+                self.selected = self.buffers.len() - 1;
+            },
+            Message::ToggleRelativeNumbers => self.relative_numbers = !self.relative_numbers,
+            Message::ToggleIndentGuides => self.show_indent_guides = !self.show_indent_guides,
+            Message::ToggleCrosshair => self.show_crosshair = !self.show_crosshair,
+            Message::ToggleRainbowBrackets => self.show_rainbow_brackets = !self.show_rainbow_brackets,
+            Message::ToggleWrap => {
+                self.wrap = !self.wrap;
+                self.may_scroll = true;
+            },
+            Message::ToggleCase => {
+                let (case, description) = match self.case {
+                    buffer::Case::Smart => (buffer::Case::Insensitive, "ignores case"),
+                    buffer::Case::Insensitive => (buffer::Case::Sensitive, "matches case"),
+                    buffer::Case::Sensitive => (buffer::Case::Smart, "matches case if the query has upper case"),
+                };
+                self.case = case;
+                self.current_buffer_mut().case = case;
+                self.current_buffer_mut().refresh_highlights();
+                return Some(Message::Notification(
+                    format!("Search {description}"),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
+            },
+            Message::ToggleReading => {
+                self.reading = !self.reading;
+                self.may_scroll = true;
+            },
+            Message::ToggleBidi => {
+                self.bidi = !self.bidi;
+                return Some(Message::Notification(
+                    format!("Right-to-left lines are displayed {}", if self.bidi { "right to left" } else { "left to right" }),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
+            },
+            Message::DuplicateBuffer => {
+                let buffer = self.current_buffer().clone();
+                self.buffers.insert(self.selected + 1, buffer);
+                self.selected += 1;
+            },
+            Message::CloseBuffer => {
+                if self.buffers.len() == 1 {
+                    return Some(Message::Quit);
+                }
+                return Some(Message::CloseBuffers(vec![self.selected], 0));
+            },
+            Message::CloseOtherBuffers => {
+                let others = (0..self.buffers.len()).filter(|i| *i != self.selected).collect();
+                return Some(Message::CloseBuffers(others, 0));
+            },
+            Message::CloseBuffersToTheRight => {
+                return Some(Message::CloseBuffers((self.selected + 1..self.buffers.len()).collect(), 0));
+            },
+            Message::CloseBuffers(mut indices, closed) => {
+                // the last index is closed first so the others stay valid
+                let Some(&i) = indices.last() else {
+                    return Some(Message::Notification(
+                        format!("Closed {closed} buffer{}", if closed == 1 { "" } else { "s" }),
+                        Style::new().bg(Color::White).fg(Color::Black)
+                    ));
+                };
+                match self.buffers[i].dirty() {
+                    Ok(true) => {
+                        self.selected = i;
+                        let rest = {
+                            let mut rest = indices.clone();
+                            rest.pop();
+                            rest
+                        };
+                        self.utility = Some(UtilityWindow::Confirm(
+                            utilities::confirm::ConfirmModel::new(
+                                format!("{} has unsaved changes. Do you want to save?", self.buffers[i].name),
+                                vec![
+                                    ('y', Message::Double(Box::new(Message::Save), Box::new(Message::CloseBuffers(indices, closed)))),
+                                    ('n', Message::Double(Box::new(Message::RemoveBuffer(i)), Box::new(Message::CloseBuffers(rest, closed + 1)))),
+                                    ('c', Message::CloseBuffers(vec![], closed)),
+                                ]
+                            ).with_cancel(Message::CloseBuffers(vec![], closed))
+                        ));
+                    },
+                    Ok(false) => {
+                        self.update(Message::RemoveBuffer(i));
+                        indices.pop();
+                        return Some(Message::CloseBuffers(indices, closed + 1));
+                    },
+                    Err(err) => {
+                        error!("{err:?}");
+                        self.flash();
+                        return Some(Message::Notification(
+                            format!("Error closing {}: {err}", self.buffers[i].name),
+                            Style::new().bg(Color::Red).fg(Color::White)
+                        ));
+                    },
+                }
+            },
+            Message::RemoveBuffer(i) => {
+                // there is always a buffer
+                if self.buffers.len() > 1 {
+                    let buffer = self.buffers.remove(i);
+                    if buffer.file.is_some() && !buffer.follow {
+                        self.closed.retain(|(name, _)| *name != buffer.name);
+                        self.closed.push((buffer.name.clone(), buffer.position));
+                        if self.closed.len() > CLOSED_BUFFERS {
+                            self.closed.remove(0);
+                        }
+                    }
+                    if !self.buffers.iter().any(|b| b.name == buffer.name) {
+                        self.parse_caches.remove(&buffer.name);
+                    }
+                    if self.selected > i {
+                        self.selected -= 1;
+                    }
+                    self.selected = cmp::min(self.selected, self.buffers.len() - 1);
+                }
+            },
+            Message::ToggleComment => {
+                let syntax = self.current_buffer().syntax.as_ref().map(|syntax| syntax.name.clone()).unwrap_or_default();
+                match buffer::comment_markers(&syntax) {
+                    (Some(marker), _) => self.current_buffer_mut().toggle_line_comment(marker),
+                    (None, Some((open, close))) => self.current_buffer_mut().toggle_block_comment(open, close),
+                    (None, None) => {
+                        self.flash();
+                        return Some(Message::Notification(
+                            format!("No comments known for {}", if syntax.is_empty() { "plain text" } else { &syntax }),
+                            Style::new().bg(Color::Red).fg(Color::White)
+                        ));
+                    },
+                }
+                self.may_scroll = true;
+            },
+            Message::JoinLines => {
+                self.current_buffer_mut().join_lines();
+                self.may_scroll = true;
+            },
+            Message::Transform(name) => {
+                let Some(text) = self.current_buffer().selected_text() else {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("Nothing is selected"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                };
+                match transform::transform(&name, text) {
+                    Ok(transformed) => {
+                        self.current_buffer_mut().transform_selection(|_| transformed.clone());
+                        self.may_scroll = true;
+                    },
+                    Err(e) => {
+                        self.flash();
+                        return Some(Message::Notification(e, Style::new().bg(Color::Red).fg(Color::White)));
+                    },
+                }
+            },
+            Message::AlignOn(target) => {
+                let changed = self.current_buffer_mut().align_selection_on(&target);
+                self.may_scroll = true;
+                return Some(Message::Notification(
+                    format!("Aligned {changed} lines on {target:?}"),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
+            },
+            Message::Format => match self.format_buffer(self.selected) {
+                Ok(true) => {},
+                Ok(false) => {
+                    self.flash();
+                    return Some(Message::Notification(
+                        format!("No formatter is configured for {}", self.current_buffer().syntax_name()),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                },
+                Err(e) => {
+                    self.flash();
+                    return Some(Message::Notification(e, Style::new().bg(Color::Red).fg(Color::White)));
+                },
+            },
+            Message::StripAnsi => {
+                let removed = self.current_buffer_mut().strip_ansi();
+                self.may_scroll = true;
+                return Some(Message::Notification(
+                    format!("Removed {removed} bytes of escape sequences"),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
+            },
+            Message::Retab(from, to) => {
+                let changed = self.current_buffer_mut().retab(from, to);
+                self.may_scroll = true;
+                return Some(Message::Notification(
+                    format!("Retabbed {changed} lines"),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
+            },
+            Message::ToggleAlignedColumns => {
+                self.current_buffer_mut().toggle_aligned_columns();
+                self.may_scroll = true;
             },
             Message::Notification(content, style) => {
                 self.notification = Some(Notification::new(content, style));
             },
             Message::DeveloperKey => {
-                self.utility = Some(UtilityWindow::Developer(DeveloperModel()));
+                if self.debug {
+                    self.utility = Some(UtilityWindow::Developer(DeveloperModel()));
+                } else {
+                    debug!("Ignoring developer key, start with --debug to enable it");
+                }
+            },
+            Message::SetLogLevel(level) => {
+                if let Err(e) = logging::set_log_level(level) {
+                    error!("Failed to set log level: {e:?}");
+                    self.flash();
+                    return Some(Message::Notification(
+                        format!("Failed to set log level: {e}"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                }
+                return Some(Message::Notification(
+                    format!("Log level set to {level}"),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
+            },
+            // pasted text is inserted as is, auto-indent only applies to typing
+            Message::Paste(paste) => {
+                if self.config.confirm_large_paste && paste.len() > self.config.large_paste {
+                    self.utility = Some(UtilityWindow::Confirm(
+                        utilities::confirm::ConfirmModel::new(
+                            format!("Paste {} KB of text?", paste.len() / 1024),
+                            vec![
+                                ('y', Message::PasteLarge(paste)),
+                                ('n', Message::NoMessage)
+                            ]
+                        ).with_cancel(Message::NoMessage)
+                    ));
+                    return None;
+                }
+                return Some(Message::PasteLarge(paste));
+            },
+            Message::PasteLarge(paste) => {
+                self.current_buffer_mut().delete_selection();
+                let paste = match self.config.reindent_paste && paste.contains('\n') {
+                    true => self.current_buffer().reindent_paste(&paste),
+                    false => paste,
+                };
+                self.current_buffer_mut().paste(&paste);
+                self.may_scroll = true;
+            },
+            Message::DeleteToEndOfLine => {
+                if let Some(killed) = self.current_buffer_mut().delete_to_end_of_line() {
+                    self.clipboard.set(killed);
+                }
+            },
+            Message::DeleteToStartOfLine => {
+                if let Some(killed) = self.current_buffer_mut().delete_to_start_of_line() {
+                    self.clipboard.set(killed);
+                    self.may_scroll = true;
+                }
+            },
+            Message::TransposeGraphemes => {
+                self.current_buffer_mut().transpose_graphemes();
+                self.may_scroll = true;
+            },
+            Message::TransposeWords => {
+                let word_chars = self.config.word_chars.clone();
+                self.current_buffer_mut().transpose_words(&word_chars);
+                self.may_scroll = true;
+            },
+            Message::Yank => return Some(Message::Paste(self.clipboard.get().to_owned())),
+            Message::Undo => {
+                let keep_cursor = self.config.undo_keeps_cursor.then(|| self.text_height());
+                if !self.current_buffer_mut().undo(keep_cursor) {
+                    return Some(Message::Notification(String::from("Nothing to undo"), Style::new().bg(Color::White).fg(Color::Black)));
+                }
+                self.may_scroll = true;
+            },
+            Message::Redo => {
+                let keep_cursor = self.config.undo_keeps_cursor.then(|| self.text_height());
+                if !self.current_buffer_mut().redo(keep_cursor) {
+                    return Some(Message::Notification(String::from("Nothing to redo"), Style::new().bg(Color::White).fg(Color::Black)));
+                }
+                self.may_scroll = true;
+            },
+            Message::NextUndoBranch | Message::PreviousUndoBranch => {
+                let forward = matches!(msg, Message::NextUndoBranch);
+                let content = match self.current_buffer_mut().undo.switch_branch(forward) {
+                    Some((branch, count)) => format!("Redo follows branch {} of {count}", branch + 1),
+                    None => String::from("No other branches to redo"),
+                };
+                return Some(Message::Notification(content, Style::new().bg(Color::White).fg(Color::Black)));
+            },
+            Message::OpenUndoTree => self.utility = Some(UtilityWindow::UndoTree(UndoTreeModel())),
+            Message::SelectAll => {
+                self.current_buffer_mut().select_all();
+                self.may_scroll = true;
+            },
+            Message::OpenCommand => {
+                let syntaxes = self.syntax_set.syntaxes().iter().map(|syntax| syntax.name.clone()).collect();
+                self.utility = Some(UtilityWindow::Command(utilities::command::CommandModel::new(syntaxes)));
+            },
+            Message::RunCommand(command) => {
+                self.utility = None;
+                match utilities::command::parse(&command) {
+                    Ok(msg) => return Some(msg),
+                    Err(e) => {
+                        self.flash();
+                        return Some(Message::Notification(e, Style::new().bg(Color::Red).fg(Color::White)));
+                    },
+                }
+            },
+            Message::SetSyntax(name) => {
+                let syntax = self.syntax_set.find_syntax_by_name(&name)
+                    .or_else(|| self.syntax_set.syntaxes().iter().find(|syntax| syntax.name.eq_ignore_ascii_case(&name)))
+                    .or_else(|| self.syntax_set.find_syntax_by_token(&name))
+                    .cloned();
+                match syntax {
+                    Some(syntax) => {
+                        info!("Setting syntax of {} to {}", self.current_buffer().name, syntax.name);
+                        let buffer = self.current_buffer_mut();
+                        buffer.syntax = Some(syntax);
+                        buffer.parse_cache.clear();
+                        let name = buffer.name.clone();
+                        if let Some(cache) = self.parse_caches.get(&name) {
+                            cache.borrow_mut().clear();
+                        }
+                    },
+                    None => {
+                        let matches = utilities::command::close_matches(&name, self.syntax_set.syntaxes().iter().map(|syntax| syntax.name.as_str()));
+                        let content = if matches.is_empty() {
+                            format!("Unknown syntax {name:?}")
+                        } else {
+                            format!("Unknown syntax {name:?}, did you mean: {}", matches.join(", "))
+                        };
+                        self.flash();
+                        return Some(Message::Notification(content, Style::new().bg(Color::Red).fg(Color::White)));
+                    },
+                }
+            },
+            Message::OpenShell => {
+                let dir = self.shell_dir();
+                self.utility = Some(utilities::UtilityWindow::Shell(utilities::shell::ShellModel::new(dir)));
+            },
+            Message::CommandOutput(command, output, success) => {
+                let style = match success {
+                    true => Style::new().bg(Color::White).fg(Color::Black),
+                    false => Style::new().bg(Color::Red).fg(Color::White),
+                };
+                if !self.shell_output_in_buffer {
+                    return Some(Message::Notification(output, style));
+                }
+                let i = match self.buffers.iter().position(|buffer| buffer.scratch && buffer.name == COMMAND_OUTPUT) {
+                    Some(i) => i,
+                    None => {
+                        let mut buffer = Buffer::empty();
+                        buffer.name = String::from(COMMAND_OUTPUT);
+                        buffer.scratch = true;
+                        buffer.set_readonly(true);
+                        self.buffers.push(buffer);
+                        self.buffers.len() - 1
+                    },
+                };
+                let buffer = &mut self.buffers[i];
+                buffer.content = format!("$ {command}\n{output}\n");
+                buffer.position = 0;
+                buffer.top = 0;
+                buffer.selection = None;
+                buffer.undo = UndoState::default();
+                buffer.parse_cache.clear();
+                self.parse_caches.insert(String::from(COMMAND_OUTPUT), Rc::new(RefCell::new(ParseCache::new())));
+                self.selected = i;
+                self.may_scroll = true;
+                if !success {
+                    self.flash();
+                    return Some(Message::Notification(format!("{command} failed"), style));
+                }
+            },
+            Message::ToggleShellOutput => {
+                self.shell_output_in_buffer = !self.shell_output_in_buffer;
+                return Some(Message::Notification(
+                    format!("Command output goes to {}", if self.shell_output_in_buffer { "a buffer" } else { "a notification" }),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
+            },
+            Message::ToggleMouseCapture => {
+                let capture = !crate::tui::mouse_capture();
+                crate::tui::set_mouse_capture(capture);
+                return Some(Message::Notification(
+                    String::from(if capture { "Mouse captured" } else { "Mouse left to the terminal" }),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
+            },
+            Message::CopyPath | Message::CopyLocation => {
+                let style = if self.config.copy_relative_path { PathStyle::Relative } else { PathStyle::Absolute };
+                let Some(mut path) = self.buffer_path(style) else {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("This buffer has no path"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                };
+                if let Message::CopyLocation = msg {
+                    let (col, line) = self.current_buffer().cursor_pos();
+                    path = format!("{path}:{}:{}", line + 1, col + 1);
+                }
+                self.clipboard.set(path.clone());
+                return Some(Message::Notification(
+                    format!("Copied {path}"),
+                    Style::new().bg(Color::Green).fg(Color::Black)
+                ));
+            },
+            Message::InsertPath(style) => {
+                let Some(path) = self.buffer_path(style.unwrap_or(self.config.inserted_path)) else {
+                    self.flash();
+                    return Some(Message::Notification(
+                        String::from("This buffer has no path"),
+                        Style::new().bg(Color::Red).fg(Color::White)
+                    ));
+                };
+                return Some(Message::Paste(path));
+            },
+            Message::ToggleShellDir => {
+                self.shell_in_buffer_dir = !self.shell_in_buffer_dir;
+                let dir = match self.shell_dir() {
+                    Some(dir) => dir.display().to_string(),
+                    None => String::from("the working directory"),
+                };
+                return Some(Message::Notification(
+                    format!("Shell commands run in {dir}"),
+                    Style::new().bg(Color::White).fg(Color::Black)
+                ));
             },
-            Message::Paste(paste) => self.current_buffer_mut().paste(&paste),
-            Message::OpenShell => self.utility = Some(utilities::UtilityWindow::Shell(utilities::shell::ShellModel::new())),
             Message::Double(first, second) => {
                 self.update(*first);
                 return Some(*second);
@@ -233,7 +1305,8 @@ impl Model {
                             ('y', Message::SaveAsRoot),
                             ('n', Message::NoMessage)
                         ]
-                )));
+                    ).with_cancel(Message::NoMessage)
+                ));
             },
             Message::ToBottom => {
                 self.current_buffer_mut().to_bottom();
@@ -244,17 +1317,293 @@ impl Model {
                 self.may_scroll = true;
             },
             Message::Tab => {
-                self.current_buffer_mut().insert('\t');
+                self.current_buffer_mut().delete_selection();
+                self.current_buffer_mut().insert_indent();
                 self.may_scroll = true;
             },
         }
         None
     }
 
+    /// The height available to the buffer (without the tab and status bar)
+    pub fn text_height(&self) -> usize {
+        self.viewport.height.saturating_sub(self.tab_bar_height() + 1) as usize
+    }
+
+    /// The width available to the text of the buffer (without the gutter, scrollbar and reading margins)
+    pub fn text_width(&self) -> usize {
+        self.viewport.width.saturating_sub(self.gutter_width() + self.scrollbar_width() + 2 * self.reading_margin()) as usize
+    }
+
+    fn scrollbar_width(&self) -> u16 {
+        let lines = self.current_buffer().content.matches('\n').count();
+        if lines >= self.text_height() { 1 } else { 0 }
+    }
+
+    /// The empty space left and right of the text in the reading layout
+    pub fn reading_margin(&self) -> u16 {
+        if !self.reading {
+            return 0;
+        }
+        let width = self.viewport.width.saturating_sub(self.scrollbar_width());
+        width.saturating_sub(self.gutter_width() + self.config.reading_width) / 2
+    }
+
+    /// The tab bar is hidden when there is only one buffer
+    pub fn tab_bar_height(&self) -> u16 {
+        if self.buffers.len() > 1 { 1 } else { 0 }
+    }
+
+    /// The tabs that fit in the tab bar as (buffer index, x, label).
+    /// Long names are elided and tabs far from the selected one are left out.
+    pub fn tabs(&self, width: u16) -> Vec<(usize, u16, String)> {
+        let max_label = cmp::max(width as usize / self.buffers.len(), 12);
+        let labels: Vec<String> = self.buffers.iter().map(|buffer| {
+            let name = buffer.label().rsplit('/').next().unwrap_or_default();
+            let dirty = if buffer.unsaved() { "+" } else { "" };
+            let label = format!(" {name}{dirty} ");
+            if label.chars().count() > max_label {
+                let elided: String = label.chars().take(max_label - 2).collect();
+                format!("{elided}… ")
+            } else {
+                label
+            }
+        }).collect();
+
+        // grow outwards from the selected tab until the bar is full
+        let len = |label: &String| label.chars().count();
+        let (mut start, mut end) = (self.selected, self.selected + 1);
+        let mut used = len(&labels[self.selected]);
+        loop {
+            if end < labels.len() && used + len(&labels[end]) <= width as usize {
+                used += len(&labels[end]);
+                end += 1;
+            } else if start > 0 && used + len(&labels[start - 1]) <= width as usize {
+                start -= 1;
+                used += len(&labels[start]);
+            } else {
+                break;
+            }
+        }
+
+        let mut x = 0;
+        labels.into_iter().enumerate().skip(start).take(end - start).map(|(i, label)| {
+            let tab = (i, x, label);
+            x += len(&tab.2) as u16;
+            tab
+        }).collect()
+    }
+
+    /// The width of the line number gutter (including its padding)
+    pub fn gutter_width(&self) -> u16 {
+        if !self.config.line_numbers && !self.relative_numbers {
+            // room for the wrap indicator
+            return if self.wrap { 2 } else { 0 };
+        }
+        let lines = self.current_buffer().content.split('\n').count();
+        lines.to_string().len() as u16 + 1
+    }
+
+    /// Remember the messages making up the last edit so it can be repeated.
+    /// Consecutive edits form one group, which ends on any other action.
+    fn record_edit(&mut self, msg: &Message) {
+        if self.repeating {
+            return;
+        }
+        if msg.is_edit() {
+            if !self.editing {
+                self.last_edit.clear();
+                self.editing = true;
+            }
+            self.last_edit.push(msg.clone());
+        } else if !matches!(msg,
+            // these don't end an edit (or are replaced by messages that do)
+            Message::Yank | Message::Double(..) | Message::RepeatLastEdit
+            | Message::Tick | Message::Notification(..) | Message::Resize(..)
+            | Message::KeyPrefix(_) | Message::UpdateCompletion | Message::Paste(_)
+            | Message::ReplaceAll(_)
+        ) {
+            self.editing = false;
+        }
+    }
+
+    /// Where shell commands run: the directory of the current file if `shell_in_buffer_dir` is set
+    fn shell_dir(&self) -> Option<PathBuf> {
+        let buffer = self.current_buffer();
+        if !self.shell_in_buffer_dir || buffer.file.is_none() {
+            return None;
+        }
+        Path::new(&buffer.name).parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+    }
+
+    /// Words from the buffer that complete the word before the cursor
+    fn completions(&self) -> Vec<String> {
+        let buffer = self.current_buffer();
+        let start = complete::prefix_start(&buffer.content, buffer.position, &self.config.word_chars);
+        if start == buffer.position {
+            return vec![];
+        }
+        complete::words(&buffer.content, &buffer.content[start..buffer.position], buffer.position, &self.config.word_chars)
+    }
+
+    /// Show `what` is happening in the status bar while running a slow operation,
+    /// as nothing gets drawn until it is done
+    fn busy<T>(&mut self, what: &str, operation: impl FnOnce(&mut Self) -> T) -> T {
+        self.busy = Some(what.to_owned());
+        if let Some(terminal) = crate::TERMINAL.get() {
+            if let Err(e) = terminal.lock().unwrap().draw(|frame| self.view(frame)) {
+                error!("Failed to draw: {e:?}");
+            }
+        }
+        let result = operation(self);
+        self.busy = None;
+        result
+    }
+
+    /// Tell the first time a buffer is shown with right-to-left lines
+    /// that they are displayed mirrored
+    fn notice_rtl(&mut self) {
+        let bidi = self.bidi;
+        let buffer = self.current_buffer_mut();
+        if !bidi || buffer.rtl_noticed || !bidi::has_rtl(&buffer.content) {
+            return;
+        }
+        buffer.rtl_noticed = true;
+        self.notification = Some(Notification::new(
+            String::from("Right-to-left mode: RTL lines are displayed right to left (\"bidi\" command to toggle)"),
+            Style::new().bg(Color::White).fg(Color::Black)
+        ));
+    }
+
+    /// Remember the changes an edit recorded as it made them, undone together
+    fn record_changes(&mut self, index: usize, mut changes: Vec<Change>, position: usize) {
+        let Some(buffer) = self.buffers.get_mut(index) else { return };
+        for change in &mut changes {
+            change.cursor_before = position;
+            change.cursor_after = buffer.position;
+        }
+        match self.repeating {
+            true => self.repeated.extend(changes),
+            false => buffer.undo.record_all(changes),
+        }
+    }
+
+    /// Remember how a buffer changed so it can be undone
+    fn record_undo(&mut self, index: usize, before: &str, position: usize) {
+        let Some(buffer) = self.buffers.get_mut(index) else { return };
+        if let Some(change) = Change::between(before, &buffer.content, position, buffer.position) {
+            match self.repeating {
+                true => self.repeated.push(change),
+                false => buffer.undo.record(change),
+            }
+        }
+    }
+
+    /// Pipe a buffer through the formatter configured for its syntax, undone as one change.
+    /// Returns if there is a formatter, a failing formatter leaves the buffer alone.
+    fn format_buffer(&mut self, index: usize) -> Result<bool, String> {
+        let buffer = &self.buffers[index];
+        let Some(formatter) = self.config.formatters.get(buffer.syntax_name()).cloned() else {
+            return Ok(false);
+        };
+        let (content, position) = (buffer.content.clone(), buffer.position);
+        let formatted = self.busy("Formatting…", |_| format::run(&formatter, &content)).inspect_err(|e| error!("{e}"))?;
+        if formatted != content {
+            self.buffers[index].replace_content(formatted);
+            self.record_undo(index, &content, position);
+            self.may_scroll = true;
+        }
+        Ok(true)
+    }
+
+    /// If a buffer is formatted before it is saved
+    fn formats_on_save(&self, index: usize) -> bool {
+        let syntax = self.buffers[index].syntax_name();
+        self.config.format_on_save || self.config.format_on_save_syntaxes.iter().any(|s| s == syntax)
+    }
+
+    /// Highlight `pattern` and move to its first match, like when started with `--search`
+    pub fn search(&mut self, pattern: String) {
+        self.update(Message::Find(pattern.clone()));
+        if self.current_buffer().highlights.is_empty() {
+            self.notification = Some(Notification::new(
+                format!("No match for {pattern:?}"),
+                Style::new().bg(Color::Yellow).fg(Color::Black)
+            ));
+        }
+    }
+
+    /// The path of the current buffer in `style`, `None` if it has no file
+    fn buffer_path(&self, style: PathStyle) -> Option<String> {
+        let buffer = self.current_buffer();
+        if buffer.scratch || buffer.name.is_empty() {
+            return None;
+        }
+        match style {
+            PathStyle::Name => Path::new(&buffer.name).file_name().map(|name| name.to_string_lossy().into_owned()),
+            PathStyle::Relative => Some(project::relative_name(&buffer.name, &self.root)),
+            PathStyle::Absolute => std::path::absolute(&buffer.name).ok().map(|path| path.display().to_string()),
+        }
+    }
+
+    /// Flash the screen to signal a failure
+    fn flash(&mut self) {
+        if self.config.flash {
+            self.flashing = true;
+        }
+    }
+
     pub fn current_buffer_mut(&mut self) -> &mut Buffer {
         return &mut self.buffers[self.selected];
     }
 
+    /// How many lines a tick of the wheel scrolls.
+    /// With acceleration, ticks in quick succession scroll up to four times as far.
+    fn scroll_lines(&mut self) -> usize {
+        let lines = self.config.scroll_lines;
+        if !self.config.scroll_acceleration {
+            return lines;
+        }
+        let now = Instant::now();
+        let streak = match self.last_scroll {
+            Some((last, streak)) if now.duration_since(last) < Duration::from_millis(50) => streak + 1,
+            _ => 0,
+        };
+        self.last_scroll = Some((now, streak));
+        lines * cmp::min(1 + streak / 4, 4)
+    }
+
+    /// The indices of the buffers with unsaved changes
+    fn dirty_buffers(&self) -> Vec<usize> {
+        (0..self.buffers.len()).filter(|i| {
+            self.buffers[*i].dirty().unwrap_or_else(|e| {
+                error!("Failed to check if {} has changes: {e:?}", self.buffers[*i].name);
+                false
+            })
+        }).collect()
+    }
+
+    /// The buffer that has the file at `path` open
+    fn find_buffer(&self, path: &Path) -> Option<usize> {
+        let canonical = std::fs::canonicalize(path).ok()?;
+        self.buffers.iter().position(|buffer| buffer.canonical.as_ref() == Some(&canonical))
+    }
+
+    /// The other buffers of the same file whose content differs from buffer `index`
+    fn conflicting_buffers(&self, index: usize) -> Vec<usize> {
+        let buffer = &self.buffers[index];
+        self.buffers.iter().enumerate()
+            .filter(|(i, other)| *i != index && other.content != buffer.content)
+            .filter(|(_, other)| match (&buffer.file, &other.file) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                _ => false,
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
     pub fn current_buffer(&self) -> &Buffer {
         return &self.buffers[self.selected];
     }
@@ -264,6 +1613,31 @@ impl Model {
     }
 }
 
+/// How many closed buffers can be reopened
+const CLOSED_BUFFERS: usize = 10;
+
+/// The name of the buffer showing the output of the last shell command
+const COMMAND_OUTPUT: &str = "[Command output]";
+
+/// Find the syntax of an opened buffer and, if configured, how it is indented
+fn prepare_opened(buffer: &mut Buffer, syntax_set: &SyntaxSet, config: &Config) {
+    buffer.find_syntax(syntax_set);
+    if config.detect_indentation {
+        buffer.indent = buffer::detect_indentation(&buffer.content);
+    }
+}
+
+/// The closing character for characters that can surround a selection
+fn closing_pair(open: char) -> Option<char> {
+    match open {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' | '\'' | '`' => Some(open),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     NextBuffer,
@@ -272,9 +1646,14 @@ pub enum Message {
     Quit,
     ScrollDown,
     ScrollUp,
+    ScrollLeft,
+    ScrollRight,
     OpenHelp,
     OpenFind,
     Find(String),
+    /// Move to the next (or previous) search result
+    JumpNextHighlight,
+    JumpPreviousHighlight,
     Escape,
     InsertChar(char),
     MoveLeft,
@@ -295,10 +1674,18 @@ pub enum Message {
     MouseLeft(u16, u16),
     Notification(String, Style),
     DeveloperKey,
+    SetLogLevel(LevelFilter),
     CloseUtility,
     /// Quit immediately
     QuitNoSave,
+    /// Ask about each of these buffers that has unsaved changes, then quit
+    QuitBuffers(Vec<usize>),
+    /// Save every buffer with unsaved changes
+    SaveAll,
+    /// Insert text verbatim (asking first if there is a lot of it)
     Paste(String),
+    /// Insert text verbatim without asking
+    PasteLarge(String),
     OpenShell,
     /// Two messages
     Double(Box<Message>, Box<Message>),
@@ -309,4 +1696,734 @@ pub enum Message {
     ToTop,
     ToBottom,
     Tab,
+    SelectAll,
+    /// Delete to the end of the line and put it in the clipboard
+    DeleteToEndOfLine,
+    /// Delete to the start of the line and put it in the clipboard
+    DeleteToStartOfLine,
+    /// Paste the clipboard
+    Yank,
+    TransposeGraphemes,
+    TransposeWords,
+    /// Sent periodically while something is waiting on time to pass
+    Tick,
+    /// Apply the last edit again at the cursor
+    RepeatLastEdit,
+    /// Open the log file in a buffer that follows it
+    OpenLog,
+    OpenInsertFile,
+    /// Insert the contents of a file at the cursor
+    InsertFile(String),
+    OpenInsertUnicode,
+    /// Insert a character by its code point (like `U+2603`) or digraph
+    InsertUnicode(String),
+    OpenReplace,
+    /// Replace all search results with this (after asking if `confirm_replace_all` is set)
+    ReplaceAll(String),
+    ReplaceAllConfirmed(String),
+    OpenReplaceEach,
+    /// Go through the search results from the top, asking about replacing each with this
+    ReplaceEach(String),
+    ReplacePrompt,
+    /// Replace the search result at the cursor (or skip it) and ask about the next
+    ReplaceMatch(bool),
+    /// Replace the search results from the cursor on without asking
+    ReplaceRest,
+    ReplaceQuit,
+    OpenWriteSelection,
+    /// Write the selection to a new file
+    WriteSelection(String),
+    ToggleRelativeNumbers,
+    ToggleIndentGuides,
+    /// Highlight the line and column of the cursor (or stop)
+    ToggleCrosshair,
+    ToggleRainbowBrackets,
+    ToggleWrap,
+    /// Switch between displaying right-to-left lines right to left or as stored
+    ToggleBidi,
+    /// Center the text in a column of `reading_width`
+    ToggleReading,
+    /// Switch searching between smartcase, ignoring case and matching case
+    ToggleCase,
+    /// Switch between running shell commands in the directory of the current file or the working directory
+    ToggleShellDir,
+    /// A shell command finished with this output (and succeeded or not)
+    CommandOutput(String, String, bool),
+    /// Switch between showing command output in a notification or a buffer
+    ToggleShellOutput,
+    /// Switch between capturing the mouse and leaving it to the terminal
+    ToggleMouseCapture,
+    /// Copy the path of the buffer (as set by `copy_relative_path`)
+    CopyPath,
+    /// Copy the path of the buffer with the line and column of the cursor (`path:line:col`)
+    CopyLocation,
+    /// Insert the path of the buffer, in the style given or configured by `inserted_path`
+    InsertPath(Option<PathStyle>),
+    /// Replace the selection with a transformation of it (like rot13)
+    Transform(String),
+    /// Remove ANSI escape sequences from the selection (or buffer)
+    StripAnsi,
+    /// Pipe the buffer through the formatter configured for its syntax
+    Format,
+    /// Show words from the buffer that complete the word before the cursor
+    Complete,
+    /// Show the completions for the word typed so far (or hide them if there are none)
+    UpdateCompletion,
+    /// Complete the word before the cursor to this word
+    InsertCompletion(String),
+    /// Join the line (or selected lines) with the next
+    JoinLines,
+    /// Start a new line after the current one
+    OpenLineBelow,
+    /// Start a new line before the current one
+    OpenLineAbove,
+    /// Comment or uncomment the selected lines
+    ToggleComment,
+    /// Pad the selected lines so the text lines up in the same column
+    AlignOn(String),
+    /// Open the current buffer again, sharing its file
+    DuplicateBuffer,
+    /// Open a throwaway buffer that is never saved
+    NewScratchBuffer,
+    /// The keys typed so far of a sequence (empty when it ended)
+    KeyPrefix(String),
+    CloseBuffer,
+    CloseOtherBuffers,
+    CloseBuffersToTheRight,
+    /// Close the buffers at these indices (asking to save dirty ones)
+    /// and how many have been closed so far
+    CloseBuffers(Vec<usize>, usize),
+    /// Close the buffer at an index without asking
+    RemoveBuffer(usize),
+    /// Open the file of the last closed buffer again
+    ReopenClosed,
+    /// Change the indentation width from the first to the second amount of spaces
+    Retab(usize, usize),
+    /// Display delimited data (like CSV) as aligned columns
+    ToggleAlignedColumns,
+    OpenCommand,
+    /// Run a command from the command palette
+    RunCommand(String),
+    /// Set the syntax of the current buffer by name
+    SetSyntax(String),
+    /// Revert the last group of changes
+    Undo,
+    /// Apply the last undone group of changes again
+    Redo,
+    /// Make redo follow the next branch of the undo tree
+    NextUndoBranch,
+    /// Make redo follow the previous branch of the undo tree
+    PreviousUndoBranch,
+    OpenUndoTree,
+}
+
+impl Message {
+    /// If this message edits the buffer
+    pub fn is_edit(&self) -> bool {
+        matches!(self,
+            Message::InsertChar(_) | Message::Enter | Message::Backspace | Message::Delete | Message::Tab
+            | Message::PasteLarge(_) | Message::DeleteToEndOfLine | Message::DeleteToStartOfLine
+            | Message::TransposeGraphemes | Message::TransposeWords | Message::JoinLines
+            | Message::ToggleComment | Message::Transform(_)
+            | Message::OpenLineBelow | Message::OpenLineAbove | Message::AlignOn(_)
+            | Message::StripAnsi | Message::InsertCompletion(_) | Message::ReplaceAllConfirmed(_)
+            | Message::ReplaceMatch(_) | Message::ReplaceRest
+        )
+    }
+
+    /// If this message may change the content (and can be undone)
+    pub fn changes_content(&self) -> bool {
+        self.is_edit() || matches!(self, Message::InsertFile(_) | Message::Retab(..))
+    }
+
+    /// If the change of this message is found by comparing the content before and after it.
+    /// Typing records its changes as it makes them, so it doesn't copy the whole buffer.
+    pub fn diffs_content(&self) -> bool {
+        self.changes_content() && !matches!(self,
+            Message::InsertChar(_) | Message::Enter | Message::Backspace | Message::Delete | Message::Tab
+            | Message::PasteLarge(_)
+        )
+    }
+
+    /// If this message moves the cursor (which drops the selection)
+    pub fn moves_cursor(&self) -> bool {
+        matches!(self,
+            Message::MoveLeft | Message::MoveRight | Message::MoveUp | Message::MoveDown
+            | Message::PageUp | Message::PageDown
+            | Message::JumpWordLeft | Message::JumpWordRight
+            | Message::GotoStartOfLine | Message::GotoEndOfLine
+            | Message::ToTop | Message::ToBottom
+            | Message::MouseLeft(..) | Message::Find(_)
+            | Message::JumpNextHighlight | Message::JumpPreviousHighlight
+            | Message::OpenLineBelow | Message::OpenLineAbove
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(contents: &[&str]) -> Model {
+        let buffers = contents.iter().enumerate().map(|(i, content)| {
+            let mut buffer = Buffer::empty();
+            buffer.name = format!("buffer{i}");
+            buffer.content = content.to_string();
+            buffer
+        }).collect();
+        Model::new(buffers, ThemeSet::new(), Size::new(80, 40), Config::default())
+    }
+
+    /// Process a message and the messages that follow from it
+    fn process(model: &mut Model, msg: Message) {
+        let mut next = Some(msg);
+        while let Some(msg) = next {
+            next = model.update(msg);
+        }
+    }
+
+    #[test]
+    fn close_buffers() {
+        // don't save buffers that have changes
+        fn close(model: &mut Model, msg: Message) {
+            process(model, msg);
+            while let Some(UtilityWindow::Confirm(_)) = model.utility {
+                process(model, Message::InsertChar('n'));
+            }
+        }
+        let mut model = model(&["", "a", "", "b"]);
+        model.selected = 1;
+        close(&mut model, Message::CloseBuffersToTheRight);
+        assert_eq!(model.buffers.len(), 2);
+        assert_eq!(model.selected, 1);
+        close(&mut model, Message::CloseOtherBuffers);
+        assert_eq!(model.buffers.len(), 1);
+        assert_eq!(model.current_buffer().name, "buffer1");
+    }
+
+    #[test]
+    fn undo_reverts_edits() {
+        let mut model = model(&["x"]);
+        process(&mut model, Message::InsertChar('a'));
+        process(&mut model, Message::InsertChar('b'));
+        process(&mut model, Message::Retab(4, 2));
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "x");
+        assert_eq!(model.current_buffer().position, 0);
+        process(&mut model, Message::Redo);
+        assert_eq!(model.current_buffer().content, "abx");
+        assert_eq!(model.current_buffer().position, 2);
+        process(&mut model, Message::Redo);
+        assert!(model.notification.is_some());
+    }
+
+    #[test]
+    fn typing_is_undone_by_edit() {
+        let mut model = model(&["x"]);
+        model.current_buffer_mut().selection = Some((0, 1));
+        process(&mut model, Message::InsertChar('('));
+        assert_eq!(model.current_buffer().content, "(x)");
+        assert!(model.current_buffer().changes.is_empty());
+        model.current_buffer_mut().undo.seal();
+        model.current_buffer_mut().position = 3;
+        process(&mut model, Message::Backspace);
+        process(&mut model, Message::Backspace);
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "(x)");
+        assert_eq!(model.current_buffer().position, 3);
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "x");
+    }
+
+    #[test]
+    fn undo_can_keep_the_cursor() {
+        let content = "a\n".repeat(100);
+        let mut model = model(&[&content]);
+        model.config.undo_keeps_cursor = true;
+        process(&mut model, Message::InsertChar('b'));
+        model.current_buffer_mut().position = 6;
+        process(&mut model, Message::Undo);
+        // the change is in view
+        assert_eq!(model.current_buffer().position, 5);
+        process(&mut model, Message::Redo);
+        assert_eq!(model.current_buffer().position, 6);
+        model.current_buffer_mut().position = 150;
+        model.current_buffer_mut().top = 60;
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().position, 0);
+    }
+
+    #[test]
+    fn undo_stays_in_its_buffer() {
+        let mut model = model(&["", ""]);
+        process(&mut model, Message::InsertChar('a'));
+        process(&mut model, Message::NextBuffer);
+        process(&mut model, Message::InsertChar('b'));
+        process(&mut model, Message::PreviousBuffer);
+        process(&mut model, Message::InsertChar('c'));
+        process(&mut model, Message::Undo);
+        assert_eq!(model.buffers[0].content, "a");
+        assert_eq!(model.buffers[1].content, "b");
+        process(&mut model, Message::NextBuffer);
+        process(&mut model, Message::Undo);
+        assert_eq!(model.buffers[0].content, "a");
+        assert_eq!(model.buffers[1].content, "");
+        process(&mut model, Message::Undo);
+        assert!(model.notification.is_some());
+        process(&mut model, Message::PreviousBuffer);
+        process(&mut model, Message::Undo);
+        assert_eq!(model.buffers[0].content, "");
+        process(&mut model, Message::Redo);
+        process(&mut model, Message::Redo);
+        assert_eq!(model.buffers[0].content, "ac");
+        assert_eq!(model.buffers[1].content, "");
+    }
+
+    #[test]
+    fn tells_about_rtl_mode_once() {
+        let mut model = model(&["plain", "שלום\n"]);
+        model.bidi = true;
+        assert!(model.notification.is_none());
+        process(&mut model, Message::NextBuffer);
+        assert!(matches!(&model.notification, Some(notification) if notification.content.starts_with("Right-to-left mode")));
+        model.notification = None;
+        process(&mut model, Message::PreviousBuffer);
+        process(&mut model, Message::NextBuffer);
+        assert!(model.notification.is_none());
+    }
+
+    #[test]
+    fn paste_is_not_indented() {
+        let mut model = model(&["    "]);
+        assert!(model.config.auto_indent);
+        model.current_buffer_mut().position = 4;
+        let code = "if x {\n    y(\n\tz]\n}\n";
+        process(&mut model, Message::Paste(code.to_owned()));
+        assert_eq!(model.current_buffer().content, format!("    {code}"));
+    }
+
+    #[test]
+    fn large_paste_asks_first() {
+        let mut model = model(&[""]);
+        model.config.large_paste = 4;
+        process(&mut model, Message::Paste(String::from("abcd")));
+        assert_eq!(model.current_buffer().content, "abcd");
+        process(&mut model, Message::Paste(String::from("efghi")));
+        assert!(matches!(model.utility, Some(UtilityWindow::Confirm(_))));
+        assert_eq!(model.current_buffer().content, "abcd");
+        process(&mut model, Message::InsertChar('y'));
+        assert_eq!(model.current_buffer().content, "abcdefghi");
+    }
+
+    #[test]
+    fn repeats_a_paste_once() {
+        let mut model = model(&[""]);
+        process(&mut model, Message::Paste(String::from("ab")));
+        process(&mut model, Message::MoveLeft);
+        process(&mut model, Message::RepeatLastEdit);
+        assert_eq!(model.current_buffer().content, "aabb");
+    }
+
+    #[test]
+    fn repeated_edit_is_undone_at_once() {
+        let mut typed = model(&[""]);
+        for c in ['a', 'b'] {
+            process(&mut typed, Message::InsertChar(c));
+        }
+        process(&mut typed, Message::RepeatLastEdit);
+        assert_eq!(typed.current_buffer().content, "abab");
+        process(&mut typed, Message::Undo);
+        assert_eq!(typed.current_buffer().content, "ab");
+
+        // a deletion and an insertion are one group too
+        let mut model = model(&["xy"]);
+        model.current_buffer_mut().position = 1;
+        process(&mut model, Message::Backspace);
+        process(&mut model, Message::InsertChar('z'));
+        process(&mut model, Message::MoveRight);
+        process(&mut model, Message::RepeatLastEdit);
+        assert_eq!(model.current_buffer().content, "zz");
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "zy");
+    }
+
+    #[test]
+    fn paste_can_be_reindented() {
+        let mut model = model(&["    "]);
+        model.config.reindent_paste = true;
+        model.current_buffer_mut().position = 4;
+        process(&mut model, Message::Paste(String::from("\tif x {\n\t\ty\n\t}")));
+        assert_eq!(model.current_buffer().content, "    if x {\n    \ty\n    }");
+    }
+
+    #[test]
+    fn highlights_follow_edits() {
+        let mut model = model(&["a foo b foo"]);
+        process(&mut model, Message::Find(String::from("foo")));
+        model.current_buffer_mut().position = 0;
+        process(&mut model, Message::InsertChar('x'));
+        process(&mut model, Message::InsertChar('y'));
+        let buffer = model.current_buffer();
+        assert_eq!(buffer.highlights.len(), 2);
+        for (start, end) in &buffer.highlights {
+            assert_eq!(&buffer.content[*start..*end], "foo");
+        }
+    }
+
+    #[test]
+    fn searches_on_startup() {
+        let mut model = model(&["a\nb foo\nfoo"]);
+        model.search(String::from("foo"));
+        assert_eq!(model.current_buffer().position, 4);
+        assert!(model.notification.is_none());
+        model.search(String::from("bar"));
+        assert!(model.notification.is_some());
+    }
+
+    #[test]
+    fn resize_keeps_cursor_in_view() {
+        let mut buffer = Buffer::empty();
+        buffer.content = "line\n".repeat(50);
+        buffer.position = buffer.content.len() - 2;
+        let mut model = Model::new(vec![buffer], ThemeSet::new(), Size::new(80, 40), Config::default());
+        let height = model.text_height();
+        model.current_buffer_mut().scroll_view(height, 0);
+        model.update(Message::Resize(80, 5));
+        let (_, cursor_y) = model.current_buffer().cursor_pos();
+        let top = model.current_buffer().top;
+        assert!((top..top + model.text_height()).contains(&(cursor_y as usize)), "cursor {cursor_y} top {top}");
+    }
+
+    #[test]
+    fn duplicate_buffer_warns_on_conflicting_save() {
+        let (buffer, path) = buffer::temp_buffer("duplicate", "one\n", false);
+        let mut model = Model::new(vec![buffer], ThemeSet::new(), Size::new(80, 40), Config::default());
+        process(&mut model, Message::DuplicateBuffer);
+        assert_eq!(model.buffers.len(), 2);
+        assert_eq!(model.selected, 1);
+
+        // the same changes don't conflict
+        process(&mut model, Message::InsertChar('a'));
+        model.buffers[0].content = model.buffers[1].content.clone();
+        assert!(matches!(model.update(Message::Save), Some(Message::Notification(text, _)) if text == "SAVED"));
+
+        process(&mut model, Message::InsertChar('b'));
+        let notification = model.update(Message::Save);
+        assert!(matches!(notification, Some(Message::Notification(text, _)) if text.contains("buffer 1")));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), model.buffers[1].content);
+    }
+
+    #[test]
+    fn wheel_scrolls_configured_lines() {
+        // a short buffer doesn't scroll
+        let mut short = model(&["short"]);
+        process(&mut short, Message::ScrollDown);
+        assert_eq!(short.current_buffer().top, 0);
+
+        let content = "line\n".repeat(100);
+        let mut model = model(&[&content]);
+        model.config.scroll_lines = 5;
+        process(&mut model, Message::ScrollDown);
+        assert_eq!(model.current_buffer().top, 5);
+        process(&mut model, Message::ScrollUp);
+        process(&mut model, Message::ScrollUp);
+        assert_eq!(model.current_buffer().top, 0);
+        // stops at the bottom
+        for _ in 0..30 {
+            process(&mut model, Message::ScrollDown);
+        }
+        assert_eq!(model.current_buffer().top, 100 - model.text_height());
+    }
+
+    #[test]
+    fn escape_cancels_quitting() {
+        let (mut buffer, path) = buffer::temp_buffer("quit", "saved", false);
+        buffer.content = String::from("unsaved");
+        let mut model = Model::new(vec![buffer], ThemeSet::new(), Size::new(80, 40), Config::default());
+        process(&mut model, Message::Quit);
+        assert!(matches!(model.utility, Some(UtilityWindow::Confirm(_))));
+        process(&mut model, Message::Escape);
+        assert!(model.utility.is_none());
+        assert!(model.running);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "saved");
+    }
+
+    #[test]
+    fn finds_open_files_by_canonical_path() {
+        let dir = buffer::TempPath::new("open");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let buffer = buffer::file_buffer(&dir.join("file"), "", false);
+        let model = Model::new(vec![buffer], ThemeSet::new(), Size::new(80, 40), Config::default());
+        assert_eq!(model.find_buffer(&dir.join("sub/../file")), Some(0));
+        assert_eq!(model.find_buffer(&dir.join("sub")), None);
+    }
+
+    #[test]
+    fn detects_indentation_when_configured() {
+        let (buffer, _path) = buffer::temp_buffer("indentation", "a\n  b\n", false);
+        let model = Model::new(vec![buffer.clone()], ThemeSet::new(), Size::new(80, 40), Config::default());
+        assert_eq!(model.current_buffer().indent, Some(buffer::Indent::Spaces(2)));
+        let config = Config { detect_indentation: false, ..Config::default() };
+        let model = Model::new(vec![buffer], ThemeSet::new(), Size::new(80, 40), config);
+        assert_eq!(model.current_buffer().indent, None);
+    }
+
+    #[test]
+    fn reopen_closed_buffer() {
+        let (mut buffer, _path) = buffer::temp_buffer("reopen", "some text\n", false);
+        let name = buffer.name.clone();
+        buffer.position = 5;
+        let mut model = Model::new(vec![Buffer::empty(), buffer], ThemeSet::new(), Size::new(80, 40), Config::default());
+        model.selected = 1;
+        process(&mut model, Message::CloseBuffer);
+        assert_eq!(model.buffers.len(), 1);
+        process(&mut model, Message::ReopenClosed);
+        assert_eq!(model.buffers.len(), 2);
+        assert_eq!(model.selected, 1);
+        assert_eq!(model.current_buffer().name, name);
+        assert_eq!(model.current_buffer().position, 5);
+        process(&mut model, Message::ReopenClosed);
+        assert_eq!(model.buffers.len(), 2);
+    }
+
+    #[test]
+    fn formats_on_save() {
+        let (buffer, path) = buffer::temp_buffer("format", "", false);
+        let mut config = Config::default();
+        config.format_on_save_syntaxes = vec![String::from("Plain Text")];
+        config.formatters.insert(String::from("Plain Text"), String::from("tr a-z A-Z"));
+        let mut model = Model::new(vec![buffer], ThemeSet::new(), Size::new(80, 40), config);
+        process(&mut model, Message::Paste(String::from("text\n")));
+        process(&mut model, Message::Save);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "TEXT\n");
+        // nothing is written when formatting fails
+        model.config.formatters.insert(String::from("Plain Text"), String::from("exit 1"));
+        process(&mut model, Message::Paste(String::from("more\n")));
+        process(&mut model, Message::Save);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "TEXT\n");
+        assert!(model.current_buffer().dirty().unwrap());
+    }
+
+    #[test]
+    fn offers_saving_as_root_without_permission() {
+        let (buffer, _path) = buffer::temp_buffer("permission", "", true);
+        let mut model = Model::new(vec![buffer], ThemeSet::new(), Size::new(80, 40), Config::default());
+        process(&mut model, Message::Save);
+        assert!(matches!(&model.utility, Some(UtilityWindow::Confirm(confirm)) if confirm.msg.contains(buffer::PRIVESC_CMD)));
+        assert!(model.notification.is_some());
+        process(&mut model, Message::InsertChar('n'));
+        assert!(model.utility.is_none());
+    }
+
+    #[test]
+    fn copies_paths() {
+        let mut model = model(&["one\ntwo"]);
+        model.root = std::env::current_dir().unwrap();
+        model.current_buffer_mut().name = String::from("src/main.rs");
+        model.current_buffer_mut().position = 6;
+        process(&mut model, Message::CopyLocation);
+        assert_eq!(model.clipboard.get(), "src/main.rs:2:3");
+        model.config.copy_relative_path = false;
+        process(&mut model, Message::CopyPath);
+        assert_eq!(model.clipboard.get(), model.root.join("src/main.rs").display().to_string());
+        model.current_buffer_mut().name = String::new();
+        process(&mut model, Message::CopyPath);
+        assert!(model.notification.as_ref().is_some_and(|n| n.content.contains("no path")));
+    }
+
+    #[test]
+    fn replaces_all_matches() {
+        let mut model = model(&["a foo b foo"]);
+        process(&mut model, Message::ReplaceAll(String::from("x")));
+        assert!(model.notification.as_ref().is_some_and(|n| n.content == "No matches to replace"));
+        process(&mut model, Message::Find(String::from("foo")));
+        model.config.confirm_replace_all = true;
+        process(&mut model, Message::ReplaceAll(String::from("bar")));
+        assert!(matches!(model.utility, Some(UtilityWindow::Confirm(_))));
+        process(&mut model, Message::InsertChar('y'));
+        assert_eq!(model.current_buffer().content, "a bar b bar");
+        assert!(model.notification.as_ref().is_some_and(|n| n.content == "Replaced 2 occurrences"));
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "a foo b foo");
+    }
+
+    #[test]
+    fn repeats_a_replace_once() {
+        let mut model = model(&["a x a"]);
+        process(&mut model, Message::Find(String::from("a")));
+        process(&mut model, Message::ReplaceAll(String::from("ab")));
+        assert_eq!(model.current_buffer().content, "ab x ab");
+        process(&mut model, Message::RepeatLastEdit);
+        assert_eq!(model.current_buffer().content, "abb x abb");
+    }
+
+    #[test]
+    fn replaces_interactively() {
+        let mut model = model(&["foo foo foo foo"]);
+        process(&mut model, Message::Find(String::from("foo")));
+        process(&mut model, Message::ReplaceEach(String::from("x")));
+        for answer in ['y', 'n', 'a'] {
+            assert!(matches!(model.utility, Some(UtilityWindow::Confirm(_))));
+            process(&mut model, Message::InsertChar(answer));
+        }
+        assert!(model.utility.is_none());
+        assert_eq!(model.current_buffer().content, "x foo x x");
+        assert!(model.notification.as_ref().is_some_and(|n| n.content == "Replaced 3 occurrences"));
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "x foo foo foo");
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "foo foo foo foo");
+    }
+
+    #[test]
+    fn inserts_paths() {
+        let mut model = model(&[""]);
+        model.root = std::env::current_dir().unwrap();
+        model.current_buffer_mut().name = String::from("src/main.rs");
+        process(&mut model, Message::InsertPath(None));
+        process(&mut model, Message::InsertChar(' '));
+        process(&mut model, Message::InsertPath(Some(PathStyle::Name)));
+        assert_eq!(model.current_buffer().content, "src/main.rs main.rs");
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "");
+    }
+
+    #[test]
+    fn shell_runs_in_the_directory_of_the_file() {
+        let dir = buffer::TempPath::new("shell-dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        let buffer = buffer::file_buffer(&dir.join("file"), "", false);
+        let mut model = Model::new(vec![buffer, Buffer::empty()], ThemeSet::new(), Size::new(80, 40), Config::default());
+        assert_eq!(model.shell_dir(), Some(dir.to_path_buf()));
+        // unnamed buffers run in the working directory
+        model.selected = 1;
+        assert_eq!(model.shell_dir(), None);
+        model.selected = 0;
+        process(&mut model, Message::ToggleShellDir);
+        assert_eq!(model.shell_dir(), None);
+    }
+
+    #[test]
+    fn command_output_can_go_to_a_buffer() {
+        let mut model = model(&[""]);
+        process(&mut model, Message::CommandOutput(String::from("ls"), String::from("a\nb"), true));
+        assert_eq!(model.buffers.len(), 1);
+        assert_eq!(model.notification.as_ref().map(|n| n.content.as_str()), Some("a\nb"));
+        process(&mut model, Message::ToggleShellOutput);
+        process(&mut model, Message::CommandOutput(String::from("ls"), String::from("a\nb"), true));
+        process(&mut model, Message::CommandOutput(String::from("ls -a"), String::from(".\na\nb"), true));
+        assert_eq!(model.buffers.len(), 2);
+        assert_eq!(model.selected, 1);
+        assert_eq!(model.current_buffer().content, "$ ls -a\n.\na\nb\n");
+        assert!(model.current_buffer().readonly);
+        process(&mut model, Message::Quit);
+        assert!(!model.running);
+    }
+
+    #[test]
+    fn quit_asks_about_every_dirty_buffer() {
+        // buffers without a file are unsaved once they have text
+        let mut model = model(&["typed", "", "typed"]);
+        model.selected = 1;
+        process(&mut model, Message::Quit);
+        assert_eq!(model.selected, 0);
+        process(&mut model, Message::InsertChar('n'));
+        assert_eq!(model.selected, 2);
+        assert!(model.running);
+        process(&mut model, Message::InsertChar('c'));
+        assert!(model.running);
+        assert!(model.utility.is_none());
+
+        process(&mut model, Message::Quit);
+        process(&mut model, Message::InsertChar('n'));
+        process(&mut model, Message::InsertChar('n'));
+        assert!(!model.running);
+    }
+
+    #[test]
+    fn scratch_buffers_are_not_saved() {
+        let mut model = model(&[""]);
+        process(&mut model, Message::NewScratchBuffer);
+        assert_eq!(model.selected, 1);
+        process(&mut model, Message::InsertChar('a'));
+        process(&mut model, Message::Save);
+        assert!(matches!(&model.notification, Some(notification) if notification.content.contains("Scratch")));
+        process(&mut model, Message::Quit);
+        assert!(!model.running);
+    }
+
+    #[test]
+    fn inserts_unicode() {
+        let mut model = model(&[""]);
+        process(&mut model, Message::InsertUnicode(String::from("U+2603")));
+        assert_eq!(model.current_buffer().content, "☃");
+        process(&mut model, Message::InsertUnicode(String::from("U+D800")));
+        assert_eq!(model.current_buffer().content, "☃");
+        assert!(model.notification.is_some());
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "");
+    }
+
+    #[test]
+    fn reading_layout_centers_the_text() {
+        let mut model = model(&["hello world"]);
+        model.config.reading_width = 60;
+        process(&mut model, Message::ToggleReading);
+        assert_eq!(model.reading_margin(), 10);
+        assert_eq!(model.text_width(), 60);
+        // clicks left of the text land at the start of the line
+        process(&mut model, Message::MouseLeft(16, 0));
+        assert_eq!(model.current_buffer().position, 6);
+        process(&mut model, Message::MouseLeft(2, 0));
+        assert_eq!(model.current_buffer().position, 0);
+        model.viewport.width = 50;
+        assert_eq!(model.reading_margin(), 0);
+    }
+
+    #[test]
+    fn completes_words() {
+        let mut model = model(&["value valid\n"]);
+        model.current_buffer_mut().position = 12;
+        model.config.auto_complete = true;
+        process(&mut model, Message::InsertChar('v'));
+        assert!(model.utility.is_none());
+        process(&mut model, Message::InsertChar('a'));
+        assert!(matches!(&model.utility, Some(UtilityWindow::Completion(completion)) if completion.completions == ["valid", "value"]));
+        process(&mut model, Message::InsertChar('l'));
+        process(&mut model, Message::MoveDown);
+        process(&mut model, Message::Tab);
+        assert!(model.utility.is_none());
+        assert_eq!(model.current_buffer().content, "value valid\nvalue");
+        // undone together with the typing it completes
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "value valid\n");
+    }
+
+    #[test]
+    fn formats_with_the_configured_formatter() {
+        let mut model = model(&["one\ntwo\n"]);
+        process(&mut model, Message::Format);
+        assert!(matches!(&model.notification, Some(notification) if notification.content.contains("No formatter")));
+        model.config.formatters.insert(String::from("Plain Text"), String::from("tr a-z A-Z"));
+        model.current_buffer_mut().position = 6;
+        process(&mut model, Message::Format);
+        assert_eq!(model.current_buffer().content, "ONE\nTWO\n");
+        assert_eq!(model.current_buffer().position, 6);
+        // a failing formatter leaves the buffer alone
+        model.config.formatters.insert(String::from("Plain Text"), String::from("false"));
+        process(&mut model, Message::Format);
+        assert_eq!(model.current_buffer().content, "ONE\nTWO\n");
+        process(&mut model, Message::Undo);
+        assert_eq!(model.current_buffer().content, "one\ntwo\n");
+    }
+
+    #[test]
+    fn quit_can_ask_once() {
+        let mut model = model(&["typed", "", "typed"]);
+        model.config.quit_prompt = QuitPrompt::All;
+        process(&mut model, Message::Quit);
+        assert!(matches!(&model.utility, Some(UtilityWindow::Confirm(confirm)) if confirm.msg.starts_with("2 buffers")));
+        process(&mut model, Message::InsertChar('n'));
+        assert!(!model.running);
+    }
 }
@@ -1,14 +1,21 @@
 //! For all your parsing and highlighting needs
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 
-use ratatui::style::Stylize;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use ratatui::style::{Color, Stylize};
 use ratatui::text::{Span, Line};
-use syntect::parsing::{ParseState, SyntaxReference, ScopeStack, SyntaxSet};
+use syntect::highlighting::ScopeSelectors;
+use syntect::parsing::{ParseState, SyntaxReference, ScopeStack, ScopeStackOp, SyntaxSet};
 use syntect::highlighting::{HighlightState, Highlighter, HighlightIterator};
 use syntect::util::LinesWithEndings;
 use tracing::debug;
 use crate::syntect_tui::{self, SyntectTuiError};
+use crate::columns::Columns;
+use crate::wrap;
 
 const CACHE_FREQUENCY: usize = 10;
 
@@ -42,8 +49,23 @@ impl ParseCacheTrait for ParseCache {
     }
 }
 
+/// How the parsed lines are displayed
+#[derive(Clone, Copy, Default)]
+pub struct DisplayOptions<'a> {
+    /// Show whitespace as symbols
+    pub show_whitespace: bool,
+    /// Align the fields of the lines as columns
+    pub columns: Option<&'a Columns>,
+    /// Color brackets by their depth with these colors
+    pub brackets: Option<&'a [Color]>,
+    /// Wrap the lines at this width
+    pub wrap: Option<usize>,
+    /// The rows on screen when wrapping
+    pub rows: Option<&'a [wrap::Row]>,
+}
+
 #[tracing::instrument(skip_all, level="trace", fields(start, limit = limit, from = from, n))]
-pub fn parse_from<'a>(from: usize, lines: LinesWithEndings<'a>, limit: usize, cache: &mut HashMap<usize, CachedParseState>, highlighter: &Highlighter, syntax: &SyntaxReference, syntax_set: &SyntaxSet, show_whitespace: bool) 
+pub fn parse_from<'a>(from: usize, lines: LinesWithEndings<'a>, limit: usize, cache: &mut HashMap<usize, CachedParseState>, highlighter: &Highlighter, syntax: &SyntaxReference, syntax_set: &SyntaxSet, options: &DisplayOptions) 
 -> anyhow::Result<Vec<Line<'a>>> {
     let (start, mut state) = match cache.closest_state(from) {
         Some((i, state)) => (i, state.clone()),
@@ -64,17 +86,19 @@ pub fn parse_from<'a>(from: usize, lines: LinesWithEndings<'a>, limit: usize, ca
         }
 
         let ops = state.ps.parse_line(line, syntax_set)?;
+        // the depth is tracked for every line so the cached states stay correct
+        let bracket_depths = bracket_depths(line, &ops, state.hs.path.clone(), &mut state.depth);
         let iter = HighlightIterator::new(&mut state.hs, &ops, line, highlighter);
         
         let spans: Result<Vec<Span>, SyntectTuiError> = iter.map(|t| syntect_tui::into_span(t)).collect();
         
         if line_no >= from {
             // Remove background color and handle whitespace chars
-            let spans: Vec<Span> = spans?.into_iter().map(|mut s| {
+            let fix = |mut s: Span<'a>| {
                 // not all parsers create separate spans for the whitespace
                 // I have to figure out a method to insert spans
                 // otherwise I cannot color the whitespace appropiately
-                match show_whitespace {
+                match options.show_whitespace {
                     true => {
                         let content = s.content
                         .replace("\t", &"↦".repeat(whitespace::TABSIZE))
@@ -93,9 +117,21 @@ pub fn parse_from<'a>(from: usize, lines: LinesWithEndings<'a>, limit: usize, ca
                     s = s.fg(ratatui::style::Color::Reset);
                 }
                 s.bg(ratatui::style::Color::Reset)
-            }).collect();
-
-            lexemes.push(Line::from(spans));
+            };
+            let spans = match options.brackets {
+                Some(palette) if !palette.is_empty() => color_brackets(spans?, &bracket_depths, palette),
+                _ => spans?,
+            };
+            match (options.wrap, options.columns) {
+                (Some(width), _) => {
+                    let breaks = wrap::get_linebreak_locations(line, width);
+                    for row in split_rows(spans, &breaks) {
+                        lexemes.push(Line::from(row.into_iter().map(fix).collect::<Vec<Span>>()));
+                    }
+                },
+                (None, Some(columns)) => lexemes.push(Line::from(pad_columns(spans, &columns.padding(line), fix))),
+                (None, None) => lexemes.push(Line::from(spans.into_iter().map(fix).collect::<Vec<Span>>())),
+            }
         }
 
         if line_no > from+limit {
@@ -106,11 +142,134 @@ pub fn parse_from<'a>(from: usize, lines: LinesWithEndings<'a>, limit: usize, ca
     return Ok(lexemes);
 }
 
+/// Insert padding spans at byte offsets into the line,
+/// `fix` is applied to the pieces of the line but not the padding
+fn pad_columns<'a>(spans: Vec<Span<'a>>, padding: &[(usize, usize)], fix: impl Fn(Span<'a>) -> Span<'a>) -> Vec<Span<'a>> {
+    let mut padding = padding.iter().peekable();
+    let mut padded = vec![];
+    let mut offset = 0;
+    for span in spans {
+        let len = span.content.len();
+        let mut rest = span.content;
+        let mut rest_start = offset;
+        while let Some(&&(at, width)) = padding.peek() {
+            if at >= offset + len {
+                break;
+            }
+            let (before, after) = match rest {
+                Cow::Borrowed(s) => {
+                    let (before, after) = s.split_at(at - rest_start);
+                    (Cow::Borrowed(before), Cow::Borrowed(after))
+                },
+                Cow::Owned(s) => {
+                    let (before, after) = s.split_at(at - rest_start);
+                    (Cow::Owned(before.to_owned()), Cow::Owned(after.to_owned()))
+                },
+            };
+            if !before.is_empty() {
+                padded.push(fix(Span::styled(before, span.style)));
+            }
+            padded.push(Span::raw(" ".repeat(width)));
+            rest = after;
+            rest_start = at;
+            padding.next();
+        }
+        if !rest.is_empty() {
+            padded.push(fix(Span::styled(rest, span.style)));
+        }
+        offset += len;
+    }
+    padded
+}
+
+/// The byte offsets of the brackets in a line with their nesting depth.
+/// Brackets in strings and comments are skipped.
+/// `path` is the scope stack at the start of the line.
+fn bracket_depths(line: &str, ops: &[(usize, ScopeStackOp)], mut path: ScopeStack, depth: &mut usize) -> Vec<(usize, usize)> {
+    static IGNORED: OnceLock<ScopeSelectors> = OnceLock::new();
+    let ignored = IGNORED.get_or_init(|| ScopeSelectors::from_str("string, comment").unwrap());
+    let mut ops = ops.iter().peekable();
+    let mut brackets = vec![];
+    for (i, c) in line.char_indices() {
+        while let Some((_, op)) = ops.next_if(|(at, _)| *at <= i) {
+            if let Err(e) = path.apply(op) {
+                debug!("Failed to apply a scope operation: {e:?}");
+            }
+        }
+        if !matches!(c, '(' | '[' | '{' | ')' | ']' | '}') || ignored.does_match(path.as_slice()).is_some() {
+            continue;
+        }
+        if matches!(c, '(' | '[' | '{') {
+            brackets.push((i, *depth));
+            *depth += 1;
+        } else {
+            *depth = depth.saturating_sub(1);
+            brackets.push((i, *depth));
+        }
+    }
+    brackets
+}
+
+/// Split the spans of a line into rows starting at the byte offsets `breaks`
+fn split_rows<'a>(spans: Vec<Span<'a>>, breaks: &[usize]) -> Vec<Vec<Span<'a>>> {
+    let mut breaks = breaks.iter().peekable();
+    let mut rows = vec![vec![]];
+    let mut offset = 0;
+    for span in spans {
+        let len = span.content.len();
+        let mut rest = span.content.into_owned();
+        let mut rest_start = offset;
+        while let Some(&at) = breaks.next_if(|at| **at < offset + len) {
+            let after = rest.split_off(at - rest_start);
+            if !rest.is_empty() {
+                rows.last_mut().unwrap().push(Span::styled(rest, span.style));
+            }
+            rows.push(vec![]);
+            rest = after;
+            rest_start = at;
+        }
+        if !rest.is_empty() {
+            rows.last_mut().unwrap().push(Span::styled(rest, span.style));
+        }
+        offset += len;
+    }
+    rows
+}
+
+/// Color the brackets at byte offsets into the line by their depth
+fn color_brackets<'a>(spans: Vec<Span<'a>>, brackets: &[(usize, usize)], palette: &[Color]) -> Vec<Span<'a>> {
+    let mut brackets = brackets.iter().peekable();
+    let mut colored = vec![];
+    let mut offset = 0;
+    for span in spans {
+        let len = span.content.len();
+        let mut rest = span.content.into_owned();
+        let mut rest_start = offset;
+        while let Some(&(at, depth)) = brackets.next_if(|(at, _)| *at < offset + len) {
+            let after = rest.split_off(at + 1 - rest_start);
+            let bracket = rest.split_off(at - rest_start);
+            if !rest.is_empty() {
+                colored.push(Span::styled(rest, span.style));
+            }
+            colored.push(Span::styled(bracket, span.style.fg(palette[depth % palette.len()])));
+            rest = after;
+            rest_start = at + 1;
+        }
+        if !rest.is_empty() {
+            colored.push(Span::styled(rest, span.style));
+        }
+        offset += len;
+    }
+    colored
+}
+
 // Parse
 #[derive(Clone, Debug)]
 pub struct CachedParseState {
     pub ps: ParseState,
     pub hs: HighlightState,
+    /// How deep the brackets are nested
+    pub depth: usize,
 }
 
 impl CachedParseState {
@@ -118,7 +277,51 @@ impl CachedParseState {
         CachedParseState {
             ps: ParseState::new(syntax),
             hs: HighlightState::new(highlighter, ScopeStack::new()),
+            depth: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bracket_depth_crosses_lines() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
+        let mut ps = ParseState::new(syntax);
+        let mut depth = 0;
+        let mut depths = vec![];
+        for line in LinesWithEndings::from("fn f() {\n    g(\"(\", [1]); // )\n}\n") {
+            let ops = ps.parse_line(line, &syntax_set).unwrap();
+            depths.push(bracket_depths(line, &ops, ScopeStack::new(), &mut depth));
         }
+        assert_eq!(depths[0], vec![(4, 0), (5, 0), (7, 0)]);
+        // the brackets in the string and comment don't count
+        assert_eq!(depths[1], vec![(5, 1), (11, 2), (13, 2), (14, 1)]);
+        assert_eq!(depths[2], vec![(0, 0)]);
+        assert_eq!(depth, 0);
+    }
+
+    #[test]
+    fn splits_rows() {
+        let spans = vec![Span::raw("the "), Span::raw("quick brown")];
+        let rows = split_rows(spans, &[4, 10]);
+        let contents: Vec<Vec<&str>> = rows.iter().map(|row| row.iter().map(|span| span.content.as_ref()).collect()).collect();
+        assert_eq!(contents, vec![vec!["the "], vec!["quick "], vec!["brown"]]);
+    }
+
+    #[test]
+    fn colors_brackets() {
+        let palette = [Color::Red, Color::Blue];
+        let spans = vec![Span::raw("f(a"), Span::raw("[b])")];
+        let colored = color_brackets(spans, &[(1, 0), (3, 1), (5, 1), (6, 0)], &palette);
+        let contents: Vec<&str> = colored.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(contents, vec!["f", "(", "a", "[", "b", "]", ")"]);
+        assert_eq!(colored[1].style.fg, Some(Color::Red));
+        assert_eq!(colored[3].style.fg, Some(Color::Blue));
+        assert_eq!(colored[2].style.fg, None);
     }
 }
 
@@ -0,0 +1,46 @@
+//! Finding the root of the project that is being edited
+
+use std::path::{Path, PathBuf};
+
+/// The nearest ancestor of `start` (or `start` itself) containing one of the markers
+pub fn find_root(start: &Path, markers: &[String]) -> Option<PathBuf> {
+    start.ancestors().find(|dir| markers.iter().any(|marker| dir.join(marker).exists())).map(Path::to_path_buf)
+}
+
+/// Where to look for the root of the project of the files that are opened
+pub fn start_dir(files: &[String]) -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    match files.first() {
+        Some(file) => {
+            let path = cwd.join(file);
+            path.parent().map(Path::to_path_buf).unwrap_or(cwd)
+        },
+        None => cwd,
+    }
+}
+
+/// Show a path relative to the root if it is inside of it
+pub fn relative_name(name: &str, root: &Path) -> String {
+    let path = std::env::current_dir().unwrap_or_default().join(name);
+    match path.strip_prefix(root) {
+        Ok(relative) if !name.is_empty() => relative.to_string_lossy().into_owned(),
+        _ => name.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::TempPath;
+
+    #[test]
+    fn finds_nearest_root() {
+        let dir = TempPath::new("root");
+        let nested = dir.join("src").join("deep");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "").unwrap();
+        let markers = vec![String::from(".git"), String::from("Cargo.toml")];
+        assert_eq!(find_root(&nested, &markers), Some(dir.to_path_buf()));
+        assert_eq!(relative_name(&nested.join("main.rs").to_string_lossy(), &dir), "src/deep/main.rs");
+    }
+}
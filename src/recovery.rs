@@ -0,0 +1,101 @@
+//! Recovering unsaved work after a crash
+
+use std::{fs, io::{self, Write}, path::{Path, PathBuf}, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::buffer::Buffer;
+
+/// The last known state of each buffer.
+/// This lives outside the model so the panic hook can reach it.
+static SNAPSHOTS: Mutex<Vec<Snapshot>> = Mutex::new(vec![]);
+
+struct Snapshot {
+    name: String,
+    /// The undo generation the buffer was at
+    generation: usize,
+    /// `None` if the buffer had no unsaved changes
+    content: Option<String>,
+}
+
+/// Remember the content of the unsaved buffers so it can be recovered after a panic.
+/// Buffers that didn't change since the last snapshot aren't looked at again.
+pub fn snapshot(buffers: &[Buffer]) {
+    let Ok(mut snapshots) = SNAPSHOTS.lock() else { return };
+    let mut previous = std::mem::take(&mut *snapshots);
+    snapshots.extend(buffers.iter().map(|b| {
+        let generation = b.undo.generation();
+        match previous.iter().position(|s| s.name == b.name && s.generation == generation) {
+            Some(i) => previous.swap_remove(i),
+            None => Snapshot {
+                name: b.name.clone(),
+                generation,
+                content: b.unsaved().then(|| b.content.clone()),
+            },
+        }
+    }));
+}
+
+/// The directory recovery files are written to
+fn recovery_dir() -> io::Result<PathBuf> {
+    Ok(dirs::cache_dir()
+        .ok_or_else(|| io::Error::other("failed to find cache dir"))?
+        .join("atto")
+        .join("recovery"))
+}
+
+/// Write every buffer that differs from its file to the recovery directory.
+/// Returns the paths of the written files.
+pub fn dump() -> io::Result<Vec<PathBuf>> {
+    // the panic may have happened while the lock was held
+    let snapshots = SNAPSHOTS.try_lock().map_err(|_| io::Error::other("snapshots are locked"))?;
+    write(&snapshots, &recovery_dir()?)
+}
+
+/// Write the snapshots that differ from their file to `dir`.
+/// The index of the buffer keeps buffers with the same file name apart.
+fn write(snapshots: &[Snapshot], dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let mut paths = vec![];
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let Some(content) = &snapshot.content else { continue };
+        // it may have been saved since
+        let dirty = match fs::read_to_string(&snapshot.name) {
+            Ok(on_disk) => on_disk != *content,
+            Err(_) => !content.is_empty(),
+        };
+        if !dirty {
+            continue;
+        }
+        let basename = Path::new(&snapshot.name).file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or(String::from("unnamed"));
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{basename}.{timestamp}.{i}"));
+        // an earlier recovery is never overwritten
+        fs::File::options().write(true).create_new(true).open(&path)?.write_all(content.as_bytes())?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::TempPath;
+
+    #[test]
+    fn keeps_buffers_with_the_same_name_apart() {
+        let dir = TempPath::new("recovery");
+        let snapshot = |name: String, content: &str| Snapshot { name, generation: 1, content: Some(content.to_owned()) };
+        let in_dir = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let snapshots = [
+            snapshot(in_dir("src/main.rs"), "one"),
+            snapshot(in_dir("tests/main.rs"), "two"),
+            // unnamed buffers
+            snapshot(String::new(), "three"),
+            snapshot(String::new(), "four"),
+        ];
+        let paths = write(&snapshots, &dir.join("recovered")).unwrap();
+        let recovered: Vec<String> = paths.iter().map(|path| fs::read_to_string(path).unwrap()).collect();
+        assert_eq!(recovered, ["one", "two", "three", "four"]);
+    }
+}
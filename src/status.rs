@@ -0,0 +1,90 @@
+//! The format of the right side of the status bar
+
+/// The placeholders a status format can use
+pub const PLACEHOLDERS: &[(&str, &str)] = &[
+    ("position", "line and column as set by column_indicator"),
+    ("line", "line of the cursor"),
+    ("col", "column of the cursor"),
+    ("encoding", "encoding followed by a space, unless it is UTF-8"),
+    ("name", "name of the buffer relative to the project root"),
+    ("symlink", "where the file links to if it is a symlink"),
+    ("dirty", "+ if there are unsaved changes"),
+    ("readonly", "[RO] after a space if the buffer can't be changed"),
+    ("syntax", "name of the syntax"),
+    ("buffer", "number of the buffer"),
+    ("buffers", "how many buffers are open"),
+];
+
+pub const DEFAULT: &str = "{position} {encoding}{name}{symlink}{dirty}{readonly} {buffer}/{buffers}";
+
+/// A piece of a status format
+#[derive(Debug, PartialEq)]
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a format into text and placeholders, `{{` and `}}` are literal braces
+fn pieces(format: &str) -> Result<Vec<Piece<'_>>, String> {
+    let mut pieces = vec![];
+    let mut rest = format;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("{{") {
+            pieces.push(Piece::Text("{"));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("}}") {
+            pieces.push(Piece::Text("}"));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = after.find('}').ok_or_else(|| String::from("unclosed {"))?;
+            let name = &after[..end];
+            if !PLACEHOLDERS.iter().any(|(placeholder, _)| *placeholder == name) {
+                return Err(format!("unknown placeholder {{{name}}}"));
+            }
+            pieces.push(Piece::Placeholder(name));
+            rest = &after[end + 1..];
+        } else if rest.starts_with('}') {
+            return Err(String::from("unmatched }"));
+        } else {
+            let end = rest.find(['{', '}']).unwrap_or(rest.len());
+            pieces.push(Piece::Text(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    Ok(pieces)
+}
+
+/// Check that a format only uses known placeholders
+pub fn validate(format: &str) -> Result<(), String> {
+    pieces(format).map(|_| ())
+}
+
+/// Fill in the placeholders of a format with `value`.
+/// Invalid formats use the default.
+pub fn render(format: &str, value: impl Fn(&str) -> String) -> String {
+    let pieces = pieces(format).or_else(|_| pieces(DEFAULT)).unwrap_or_default();
+    pieces.iter().map(|piece| match piece {
+        Piece::Text(text) => text.to_string(),
+        Piece::Placeholder(name) => value(name),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_placeholders() {
+        let status = render("{line}:{col} {{{name}}}", |name| name.to_uppercase());
+        assert_eq!(status, "LINE:COL {NAME}");
+        assert_eq!(render("{nope}", |name| name.to_string()), "position encodingnamesymlinkdirtyreadonly buffer/buffers");
+    }
+
+    #[test]
+    fn validates() {
+        assert!(validate(DEFAULT).is_ok());
+        assert_eq!(validate("{line"), Err(String::from("unclosed {")));
+        assert_eq!(validate("line}"), Err(String::from("unmatched }")));
+        assert_eq!(validate("{lines}"), Err(String::from("unknown placeholder {lines}")));
+    }
+}
@@ -0,0 +1,180 @@
+//! Transformations of selected text
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The names of the transformations
+pub const TRANSFORMS: &[&str] = &[
+    "reverse",
+    "rot13",
+    "base64-encode",
+    "base64-decode",
+    "url-encode",
+    "url-decode",
+];
+
+/// Transform text with the transformation called `name`
+pub fn transform(name: &str, text: &str) -> Result<String, String> {
+    match name {
+        "reverse" => Ok(text.graphemes(true).rev().collect()),
+        "rot13" => Ok(rot13(text)),
+        "base64-encode" => Ok(base64_encode(text.as_bytes())),
+        "base64-decode" => base64_decode(text)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(|| String::from("Invalid base64")),
+        "url-encode" => Ok(url_encode(text)),
+        "url-decode" => url_decode(text).ok_or_else(|| String::from("Invalid URL encoding")),
+        name => Err(format!("Unknown transformation {name:?}")),
+    }
+}
+
+fn rot13(text: &str) -> String {
+    text.chars().map(|c| match c {
+        'a'..='z' => (b'a' + (c as u8 - b'a' + 13) % 26) as char,
+        'A'..='Z' => (b'A' + (c as u8 - b'A' + 13) % 26) as char,
+        c => c,
+    }).collect()
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+/// Whitespace is ignored so wrapped base64 can be decoded
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if text.len() % 4 != 0 {
+        return None;
+    }
+    let mut bytes = vec![];
+    for (c, chunk) in text.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|b| **b == b'=').count();
+        if padding > 2 || (padding > 0 && (c + 1) * 4 != text.len()) {
+            return None;
+        }
+        let mut n = 0u32;
+        for (i, b) in chunk[..4 - padding].iter().enumerate() {
+            let value = BASE64.iter().position(|c| c == b)? as u32;
+            n |= value << (18 - 6 * i);
+        }
+        bytes.extend(n.to_be_bytes()[1..4 - padding].iter());
+    }
+    Some(bytes)
+}
+
+fn url_encode(text: &str) -> String {
+    text.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+        b => format!("%{b:02X}"),
+    }).collect()
+}
+
+fn url_decode(text: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut iter = text.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            },
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Remove ANSI escape sequences (colors, cursor movement, titles) like in captured terminal output.
+/// Only complete sequences are removed, a lone escape or an unterminated one is kept.
+pub fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('\x1b') {
+        stripped.push_str(&rest[..i]);
+        let sequence = &rest[i..];
+        match escape_len(sequence.as_bytes()) {
+            Some(len) => rest = &sequence[len..],
+            None => {
+                stripped.push('\x1b');
+                rest = &sequence[1..];
+            },
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// The length of the escape sequence at the start of `bytes`, if it is a complete one
+fn escape_len(bytes: &[u8]) -> Option<usize> {
+    match *bytes.get(1)? {
+        // CSI: parameters, intermediates and a final byte
+        b'[' => {
+            let parameters = bytes[2..].iter().take_while(|b| (0x30..=0x3f).contains(*b)).count();
+            let intermediates = bytes[2 + parameters..].iter().take_while(|b| (0x20..=0x2f).contains(*b)).count();
+            let end = 2 + parameters + intermediates;
+            (0x40..=0x7e).contains(bytes.get(end)?).then_some(end + 1)
+        },
+        // OSC: ended by BEL or ST (ESC \\)
+        b']' => {
+            let end = bytes[2..].iter().position(|b| *b == 0x07 || *b == 0x1b)? + 2;
+            match bytes[end] {
+                0x07 => Some(end + 1),
+                _ => (bytes.get(end + 1) == Some(&b'\\')).then_some(end + 2),
+            }
+        },
+        // character set designations like ESC ( B
+        b'(' | b')' => bytes.get(2).filter(|b| b.is_ascii_alphanumeric()).map(|_| 3),
+        // two byte sequences like ESC M or keypad modes
+        b'@'..=b'Z' | b'\\' | b'^' | b'_' | b'=' | b'>' | b'7' | b'8' => Some(2),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms() {
+        assert_eq!(transform("reverse", "abé"), Ok(String::from("éba")));
+        assert_eq!(transform("rot13", "Hello, World!"), Ok(String::from("Uryyb, Jbeyq!")));
+        assert_eq!(transform("url-encode", "a b&ü"), Ok(String::from("a%20b%26%C3%BC")));
+        assert_eq!(transform("url-decode", "a%20b%26%C3%BC"), Ok(String::from("a b&ü")));
+        assert!(transform("url-decode", "100%").is_err());
+    }
+
+    #[test]
+    fn strips_ansi() {
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m: oops"), "error: oops");
+        assert_eq!(strip_ansi("\x1b]0;title\x07$ ls\x1b]8;;http://a\x1b\\link"), "$ lslink");
+        assert_eq!(strip_ansi("\x1b(B\x1b[?25lhi\x1bM"), "hi");
+        // incomplete sequences are kept
+        assert_eq!(strip_ansi("a\x1b"), "a\x1b");
+        assert_eq!(strip_ansi("\x1b[31"), "\x1b[31");
+        assert_eq!(strip_ansi("\x1b]0;title"), "\x1b]0;title");
+        assert_eq!(strip_ansi("\x1bé"), "\x1bé");
+    }
+
+    #[test]
+    fn base64() {
+        for (text, encoded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foobar", "Zm9vYmFy")] {
+            assert_eq!(transform("base64-encode", text), Ok(String::from(encoded)));
+            assert_eq!(transform("base64-decode", encoded), Ok(String::from(text)));
+        }
+        assert_eq!(transform("base64-decode", "Zm9v\nYmFy"), Ok(String::from("foobar")));
+        assert!(transform("base64-decode", "Zm9").is_err());
+        assert!(transform("base64-decode", "Zg==Zm9v").is_err());
+        assert!(transform("base64-decode", "Zm9*").is_err());
+    }
+}
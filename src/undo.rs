@@ -0,0 +1,351 @@
+//! Undo history as a tree, so undone changes are kept as branches
+
+use std::{cmp, time::{Duration, Instant}};
+
+/// Changes closer together than this are undone together
+const MERGE_TIME: Duration = Duration::from_millis(500);
+
+/// `removed` at byte `at` was replaced by `inserted`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub at: usize,
+    pub removed: String,
+    pub inserted: String,
+    /// Where the cursor was before the change
+    pub cursor_before: usize,
+    /// Where the cursor was after the change
+    pub cursor_after: usize,
+}
+
+impl Change {
+    /// The change that turns `before` into `after`, if they differ
+    pub fn between(before: &str, after: &str, cursor_before: usize, cursor_after: usize) -> Option<Self> {
+        if before == after {
+            return None;
+        }
+        let mut prefix = before.bytes().zip(after.bytes()).take_while(|(a, b)| a == b).count();
+        while !before.is_char_boundary(prefix) || !after.is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+        let max_suffix = cmp::min(before.len(), after.len()) - prefix;
+        let mut suffix = before.bytes().rev().zip(after.bytes().rev())
+            .take(max_suffix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !before.is_char_boundary(before.len() - suffix) || !after.is_char_boundary(after.len() - suffix) {
+            suffix -= 1;
+        }
+        Some(Self {
+            at: prefix,
+            removed: before[prefix..before.len() - suffix].to_string(),
+            inserted: after[prefix..after.len() - suffix].to_string(),
+            cursor_before,
+            cursor_after,
+        })
+    }
+
+    /// If this change continues `previous` so they can be undone together:
+    /// typing on after it, or deleting on from the same point
+    fn continues(&self, previous: &Change) -> bool {
+        match (self.removed.is_empty(), self.inserted.is_empty(), previous.removed.is_empty(), previous.inserted.is_empty()) {
+            // insertions
+            (true, false, true, false) => self.at == previous.at + previous.inserted.len(),
+            // deletions, backwards like backspace or forwards like delete
+            (false, true, false, true) => self.at + self.removed.len() == previous.at || self.at == previous.at,
+            _ => false,
+        }
+    }
+
+    /// Where a byte offset of the content before the change ends up after it
+    pub fn shift(&self, position: usize) -> usize {
+        if position <= self.at {
+            position
+        } else if position >= self.at + self.removed.len() {
+            position - self.removed.len() + self.inserted.len()
+        } else {
+            self.at + self.inserted.len()
+        }
+    }
+
+    fn apply(&self, content: &mut String) {
+        content.replace_range(self.at..self.at + self.removed.len(), &self.inserted);
+    }
+
+    fn revert(&self, content: &mut String) {
+        content.replace_range(self.at..self.at + self.inserted.len(), &self.removed);
+    }
+
+    /// A short description for the undo tree
+    fn summary(&self) -> String {
+        let (sign, text) = match self.inserted.is_empty() {
+            true => ('-', &self.removed),
+            false => ('+', &self.inserted),
+        };
+        let text = text.replace('\n', "↵").replace('\t', "→");
+        match text.chars().count() > 20 {
+            true => format!("{sign}{}…", text.chars().take(20).collect::<String>()),
+            false => format!("{sign}{text}"),
+        }
+    }
+}
+
+/// A group of changes that are undone together
+#[derive(Debug, Clone)]
+struct Node {
+    parent: usize,
+    children: Vec<usize>,
+    /// The child that redo goes to
+    branch: usize,
+    changes: Vec<Change>,
+    time: Instant,
+}
+
+/// The undo history of a buffer.
+/// Making a change after undoing starts a new branch instead of discarding the undone changes.
+#[derive(Debug, Clone)]
+pub struct UndoState {
+    /// The first node is the unchanged buffer
+    nodes: Vec<Node>,
+    current: usize,
+    /// The next change starts a new group
+    sealed: bool,
+    /// Counts the changes recorded, undone and redone, to tell if the content changed since
+    generation: usize,
+}
+
+impl Default for UndoState {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node { parent: 0, children: vec![], branch: 0, changes: vec![], time: Instant::now() }],
+            current: 0,
+            sealed: false,
+            generation: 0,
+        }
+    }
+}
+
+impl UndoState {
+    /// Remember a change.
+    /// Quick successive changes of the same kind in the same place are grouped.
+    pub fn record(&mut self, change: Change) {
+        self.generation += 1;
+        let now = Instant::now();
+        let index = self.nodes.len();
+        let node = &mut self.nodes[self.current];
+        let continues = node.changes.last().is_some_and(|last| change.continues(last));
+        let sealed = std::mem::take(&mut self.sealed);
+        if self.current != 0 && node.children.is_empty() && continues && !sealed && now.duration_since(node.time) < MERGE_TIME {
+            node.changes.push(change);
+            node.time = now;
+            return;
+        }
+        node.branch = node.children.len();
+        node.children.push(index);
+        self.nodes.push(Node { parent: self.current, children: vec![], branch: 0, changes: vec![change], time: now });
+        self.current = index;
+    }
+
+    /// Remember the changes of one edit, they are undone together
+    pub fn record_all(&mut self, changes: Vec<Change>) {
+        let mut changes = changes.into_iter();
+        let Some(first) = changes.next() else { return };
+        self.record(first);
+        self.nodes[self.current].changes.extend(changes);
+    }
+
+    /// Changes whenever the content is changed through the history
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Don't group the next change with the ones before it
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    /// Revert the current group of changes.
+    /// Returns where the cursor was before them.
+    pub fn undo(&mut self, content: &mut String) -> Option<usize> {
+        if self.current == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.current];
+        for change in node.changes.iter().rev() {
+            change.revert(content);
+        }
+        let cursor = node.changes.first().map(|change| change.cursor_before);
+        let (parent, undone) = (node.parent, self.current);
+        self.nodes[parent].branch = self.nodes[parent].children.iter().position(|&child| child == undone).unwrap_or(0);
+        self.current = parent;
+        self.generation += 1;
+        cursor
+    }
+
+    /// Apply the group of changes on the current branch again.
+    /// Returns where the cursor was after them.
+    pub fn redo(&mut self, content: &mut String) -> Option<usize> {
+        let node = &self.nodes[self.current];
+        let child = *node.children.get(node.branch)?;
+        for change in &self.nodes[child].changes {
+            change.apply(content);
+        }
+        self.current = child;
+        self.generation += 1;
+        self.nodes[child].changes.last().map(|change| change.cursor_after)
+    }
+
+    /// Choose the next (or previous) branch for redo to follow.
+    /// Returns the chosen branch and how many there are.
+    pub fn switch_branch(&mut self, forward: bool) -> Option<(usize, usize)> {
+        let node = &mut self.nodes[self.current];
+        let count = node.children.len();
+        if count < 2 {
+            return None;
+        }
+        node.branch = match forward {
+            true => (node.branch + 1) % count,
+            false => (node.branch + count - 1) % count,
+        };
+        Some((node.branch, count))
+    }
+
+    /// Draw the tree, one group of changes per line.
+    /// Branches are indented below the group they split off from,
+    /// the current group is marked with a `*` and redo follows the `>`s.
+    pub fn lines(&self) -> Vec<String> {
+        // the groups from the start through the current one and on along the branches
+        let mut path = vec![false; self.nodes.len()];
+        let mut node = self.current;
+        while node != 0 {
+            path[node] = true;
+            node = self.nodes[node].parent;
+        }
+        path[0] = true;
+        let mut node = &self.nodes[self.current];
+        while let Some(&child) = node.children.get(node.branch) {
+            path[child] = true;
+            node = &self.nodes[child];
+        }
+
+        let mut lines = vec![];
+        let mut stack = vec![(0, 0)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            let marker = match (index == self.current, path[index]) {
+                (true, _) => '*',
+                (false, true) => '>',
+                (false, false) => ' ',
+            };
+            let summary = match node.changes.first() {
+                Some(change) => change.summary(),
+                None => String::from("(original)"),
+            };
+            let more = match node.changes.len() {
+                0 | 1 => String::new(),
+                n => format!(" (+{} more)", n - 1),
+            };
+            lines.push(format!("{}{marker} {index} {summary}{more}", "  ".repeat(depth)));
+            // the first child continues the line, the others are branches
+            for (i, &child) in node.children.iter().enumerate().rev() {
+                stack.push((child, if i == 0 { depth } else { depth + 1 }));
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(undo: &mut UndoState, content: &mut String, new: &str) {
+        let change = Change::between(content, new, 0, 0).unwrap();
+        undo.record(change);
+        *content = new.to_string();
+        // don't merge with the next edit
+        undo.nodes[undo.current].time -= MERGE_TIME;
+    }
+
+    #[test]
+    fn finds_the_change() {
+        let change = Change::between("hello world", "hello brave world", 0, 0).unwrap();
+        assert_eq!((change.at, change.removed.as_str(), change.inserted.as_str()), (6, "", "brave "));
+        let change = Change::between("aaa", "aa", 0, 0).unwrap();
+        assert_eq!((change.at, change.removed.as_str(), change.inserted.as_str()), (2, "a", ""));
+        let change = Change::between("é", "è", 0, 0).unwrap();
+        assert_eq!((change.at, change.removed.as_str(), change.inserted.as_str()), (0, "é", "è"));
+        assert!(Change::between("same", "same", 0, 0).is_none());
+        let change = Change::between("abcdef", "aXf", 0, 0).unwrap();
+        assert_eq!((change.shift(1), change.shift(3), change.shift(6)), (1, 2, 3));
+    }
+
+    #[test]
+    fn undoes_and_redoes() {
+        let mut undo = UndoState::default();
+        let mut content = String::from("a");
+        edit(&mut undo, &mut content, "ab");
+        edit(&mut undo, &mut content, "abc");
+        assert!(undo.undo(&mut content).is_some());
+        assert_eq!(content, "ab");
+        assert!(undo.undo(&mut content).is_some());
+        assert_eq!(content, "a");
+        assert!(undo.undo(&mut content).is_none());
+        assert!(undo.redo(&mut content).is_some());
+        assert!(undo.redo(&mut content).is_some());
+        assert_eq!(content, "abc");
+        assert!(undo.redo(&mut content).is_none());
+        // two edits, two undos and two redos
+        assert_eq!(undo.generation(), 6);
+    }
+
+    #[test]
+    fn keeps_undone_changes_as_a_branch() {
+        let mut undo = UndoState::default();
+        let mut content = String::from("a");
+        edit(&mut undo, &mut content, "ab");
+        undo.undo(&mut content);
+        edit(&mut undo, &mut content, "ac");
+        undo.undo(&mut content);
+        // redo follows the newest branch
+        undo.redo(&mut content);
+        assert_eq!(content, "ac");
+        undo.undo(&mut content);
+        assert_eq!(undo.switch_branch(true), Some((0, 2)));
+        undo.redo(&mut content);
+        assert_eq!(content, "ab");
+        assert_eq!(undo.lines(), vec!["> 0 (original)", "* 1 +b", "    2 +c"]);
+    }
+
+    /// Make quick changes, then undo once
+    fn undo_quick_changes(content: &str, changes: &[&str]) -> String {
+        let mut undo = UndoState::default();
+        let mut content = content.to_string();
+        for new in changes {
+            undo.record(Change::between(&content, new, 0, 0).unwrap());
+            content = new.to_string();
+        }
+        undo.undo(&mut content);
+        content
+    }
+
+    #[test]
+    fn groups_quick_changes() {
+        assert_eq!(undo_quick_changes("", &["a", "ab", "abc"]), "");
+        // backspace
+        assert_eq!(undo_quick_changes("abc", &["ab", "a", ""]), "abc");
+        // delete
+        assert_eq!(undo_quick_changes("abc", &["bc", "c"]), "abc");
+    }
+
+    #[test]
+    fn groups_only_changes_of_the_same_kind() {
+        // typing, fixing a typo and typing on
+        assert_eq!(undo_quick_changes("", &["a", "ab", "abx", "ab", "abc", "abcd"]), "ab");
+        assert_eq!(undo_quick_changes("", &["a", "ab", "abx", "ab"]), "abx");
+        assert_eq!(undo_quick_changes("", &["a", "ab", "abx"]), "");
+        // typing somewhere else
+        assert_eq!(undo_quick_changes("--", &["a--", "ab--", "ab--c"]), "ab--");
+        // deleting somewhere else
+        assert_eq!(undo_quick_changes("abcd", &["abc", "bc"]), "abc");
+    }
+}
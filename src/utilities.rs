@@ -3,10 +3,16 @@ pub mod find;
 pub mod confirm;
 pub mod developer;
 pub mod shell;
+pub mod command;
+pub mod entry;
+pub mod undo_tree;
+pub mod completion;
 
-use ratatui::{layout::Rect, style::{Style, Stylize}, widgets::{Block, Borders, Padding}, Frame};
+use std::path::Path;
 
-use crate::model::{Message, Model};
+use ratatui::{layout::Rect, style::{Color, Style, Stylize}, widgets::{Block, Borders, Padding}, Frame};
+
+use crate::{complete, model::{Message, Model}};
 
 /// All utilities must implement this trait
 pub trait Utility {
@@ -19,6 +25,14 @@ pub trait Utility {
     /// Given the maximum allowed area size
     /// the widget can draw itself
     fn view(&self, m: &Model, f: &mut Frame, area: Rect);
+    /// A click at `x` and `y` on screen within the area of the utility
+    fn click(&mut self, _x: u16, _y: u16, _area: Rect) -> Option<Message> {
+        None
+    }
+    /// The keys this utility responds to, shown in the status bar
+    fn hint(&self) -> Option<String> {
+        None
+    }
 }
 
 /// Utilitis are encouraged to render themselves in this block
@@ -45,6 +59,21 @@ pub fn default_view(title: &str, content: &str, f: &mut Frame, area: Rect) {
     f.render_widget(Paragraph::new(widget_content).block(block), area);
 }
 
+/// Complete the path `entry` ends with (starting at `start`) relative to `dir`.
+/// If there is nothing to complete the matching files are shown instead.
+pub fn complete_path(entry: &mut String, start: usize, dir: &Path) -> Option<Message> {
+    let (completed, candidates) = complete::path(&entry[start..], dir);
+    if completed.len() > entry.len() - start {
+        entry.replace_range(start.., &completed);
+        return None;
+    }
+    match candidates.len() {
+        0 => Some(Message::Notification(String::from("No matching files"), Style::new().bg(Color::White).fg(Color::Black))),
+        1 => None,
+        _ => Some(Message::Notification(candidates.join("  "), Style::new().bg(Color::White).fg(Color::Black))),
+    }
+}
+
 /// The top right window
 pub enum UtilityWindow {
     Help(help::HelpModel),
@@ -52,4 +81,38 @@ pub enum UtilityWindow {
     Confirm(confirm::ConfirmModel),
     Developer(developer::DeveloperModel),
     Shell(shell::ShellModel),
+    Command(command::CommandModel),
+    Entry(entry::EntryModel),
+    UndoTree(undo_tree::UndoTreeModel),
+    Completion(completion::CompletionModel),
+}
+
+impl UtilityWindow {
+    pub fn click(&mut self, x: u16, y: u16, area: Rect) -> Option<Message> {
+        match self {
+            UtilityWindow::Help(help) => help.click(x, y, area),
+            UtilityWindow::Find(find) => find.click(x, y, area),
+            UtilityWindow::Confirm(confirm) => confirm.click(x, y, area),
+            UtilityWindow::Developer(developer) => developer.click(x, y, area),
+            UtilityWindow::Shell(shell) => shell.click(x, y, area),
+            UtilityWindow::Command(command) => command.click(x, y, area),
+            UtilityWindow::Entry(entry) => entry.click(x, y, area),
+            UtilityWindow::UndoTree(undo_tree) => undo_tree.click(x, y, area),
+            UtilityWindow::Completion(completion) => completion.click(x, y, area),
+        }
+    }
+
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            UtilityWindow::Help(help) => help.hint(),
+            UtilityWindow::Find(find) => find.hint(),
+            UtilityWindow::Confirm(confirm) => confirm.hint(),
+            UtilityWindow::Developer(developer) => developer.hint(),
+            UtilityWindow::Shell(shell) => shell.hint(),
+            UtilityWindow::Command(command) => command.hint(),
+            UtilityWindow::Entry(entry) => entry.hint(),
+            UtilityWindow::UndoTree(undo_tree) => undo_tree.hint(),
+            UtilityWindow::Completion(completion) => completion.hint(),
+        }
+    }
 }
@@ -0,0 +1,294 @@
+//! The command palette
+
+use ratatui::{layout::{Constraint, Layout, Rect}, style::{Modifier, Style, Stylize}, text::{Line, Span}, widgets::{Clear, Paragraph}, Frame};
+
+use crate::{config::PathStyle, model::{Message, Model}, transform::TRANSFORMS, utilities};
+
+/// The commands that can be run from the palette
+pub const COMMANDS: &[&str] = &[
+    "set-syntax",
+    "align-columns",
+    "align",
+    "retab",
+    "format",
+    "log",
+    "read",
+    "unicode",
+    "write-selection",
+    "join",
+    "open-below",
+    "open-above",
+    "close",
+    "close-others",
+    "close-right",
+    "reopen",
+    "duplicate",
+    "scratch",
+    "indent-guides",
+    "crosshair",
+    "rainbow-brackets",
+    "wrap",
+    "bidi",
+    "reading",
+    "case",
+    "shell-dir",
+    "shell-output",
+    "mouse",
+    "copy-path",
+    "copy-location",
+    "insert-path",
+    "replace",
+    "replace-each",
+    "transform",
+    "strip-ansi",
+    "comment",
+    "complete",
+    "undo",
+    "redo",
+    "undo-tree",
+];
+
+pub struct CommandModel {
+    pub entry: String,
+    /// The names of the available syntaxes (for completion)
+    syntaxes: Vec<String>,
+}
+
+impl CommandModel {
+    pub fn new(syntaxes: Vec<String>) -> Self {
+        Self { entry: String::new(), syntaxes }
+    }
+
+    /// The entries that the current entry could complete to
+    pub fn candidates(&self) -> Vec<String> {
+        match self.entry.split_once(' ') {
+            Some((command, arg)) => {
+                let args: Vec<&str> = match command {
+                    "set-syntax" => self.syntaxes.iter().map(String::as_str).collect(),
+                    "transform" => TRANSFORMS.to_vec(),
+                    "insert-path" => vec!["name", "relative", "absolute"],
+                    _ => vec![],
+                };
+                args.iter()
+                    .filter(|candidate| candidate.to_lowercase().starts_with(&arg.to_lowercase()))
+                    .map(|candidate| format!("{command} {candidate}"))
+                    .collect()
+            },
+            None => COMMANDS.iter()
+                .filter(|command| command.starts_with(&self.entry))
+                .map(|command| command.to_string())
+                .collect(),
+        }
+    }
+
+    /// Extend the entry as far as all candidates agree
+    fn complete(&mut self) {
+        let candidates = self.candidates();
+        let Some(first) = candidates.first() else {
+            return;
+        };
+        let mut common = first.as_str();
+        for candidate in &candidates[1..] {
+            let len = common.char_indices().zip(candidate.chars())
+                .find(|((_, a), b)| !a.eq_ignore_ascii_case(b))
+                .map(|((i, _), _)| i)
+                .unwrap_or(common.len().min(candidate.len()));
+            common = &common[..len];
+        }
+        if common.len() >= self.entry.len() {
+            self.entry = common.to_owned();
+        }
+        // a complete command is followed by its argument
+        if candidates.len() == 1 && !self.entry.contains(' ') {
+            self.entry.push(' ');
+        }
+    }
+}
+
+/// Turn a command into the message that runs it
+pub fn parse(entry: &str) -> Result<Message, String> {
+    let (command, arg) = entry.trim().split_once(' ').unwrap_or((entry.trim(), ""));
+    let arg = arg.trim();
+    match command {
+        "set-syntax" if arg.is_empty() => Err(String::from("set-syntax needs the name of a syntax")),
+        "set-syntax" => Ok(Message::SetSyntax(arg.to_owned())),
+        "align-columns" => Ok(Message::ToggleAlignedColumns),
+        "align" if arg.is_empty() => Err(String::from("align needs the text to align on")),
+        "align" => Ok(Message::AlignOn(arg.to_owned())),
+        "unicode" if arg.is_empty() => Ok(Message::OpenInsertUnicode),
+        "unicode" => Ok(Message::InsertUnicode(arg.to_owned())),
+        "join" => Ok(Message::JoinLines),
+        "open-below" => Ok(Message::OpenLineBelow),
+        "open-above" => Ok(Message::OpenLineAbove),
+        "close" => Ok(Message::CloseBuffer),
+        "close-others" => Ok(Message::CloseOtherBuffers),
+        "close-right" => Ok(Message::CloseBuffersToTheRight),
+        "reopen" => Ok(Message::ReopenClosed),
+        "duplicate" => Ok(Message::DuplicateBuffer),
+        "scratch" => Ok(Message::NewScratchBuffer),
+        "indent-guides" => Ok(Message::ToggleIndentGuides),
+        "crosshair" => Ok(Message::ToggleCrosshair),
+        "rainbow-brackets" => Ok(Message::ToggleRainbowBrackets),
+        "wrap" => Ok(Message::ToggleWrap),
+        "bidi" => Ok(Message::ToggleBidi),
+        "strip-ansi" => Ok(Message::StripAnsi),
+        "format" => Ok(Message::Format),
+        "complete" => Ok(Message::Complete),
+        "reading" => Ok(Message::ToggleReading),
+        "case" => Ok(Message::ToggleCase),
+        "shell-dir" => Ok(Message::ToggleShellDir),
+        "shell-output" => Ok(Message::ToggleShellOutput),
+        "mouse" => Ok(Message::ToggleMouseCapture),
+        "copy-path" => Ok(Message::CopyPath),
+        "replace" if arg.is_empty() => Ok(Message::OpenReplace),
+        "replace" => Ok(Message::ReplaceAll(arg.to_owned())),
+        "replace-each" if arg.is_empty() => Ok(Message::OpenReplaceEach),
+        "replace-each" => Ok(Message::ReplaceEach(arg.to_owned())),
+        "copy-location" => Ok(Message::CopyLocation),
+        "insert-path" => match arg {
+            "" => Ok(Message::InsertPath(None)),
+            "name" => Ok(Message::InsertPath(Some(PathStyle::Name))),
+            "relative" => Ok(Message::InsertPath(Some(PathStyle::Relative))),
+            "absolute" => Ok(Message::InsertPath(Some(PathStyle::Absolute))),
+            _ => Err(String::from("insert-path takes name, relative or absolute")),
+        },
+        "transform" if !TRANSFORMS.contains(&arg) => Err(format!("transform needs one of {}", TRANSFORMS.join(", "))),
+        "transform" => Ok(Message::Transform(arg.to_owned())),
+        "comment" => Ok(Message::ToggleComment),
+        "undo" => Ok(Message::Undo),
+        "redo" => Ok(Message::Redo),
+        "undo-tree" => Ok(Message::OpenUndoTree),
+        "log" => Ok(Message::OpenLog),
+        "read" if arg.is_empty() => Ok(Message::OpenInsertFile),
+        "read" => Ok(Message::InsertFile(arg.to_owned())),
+        "write-selection" if arg.is_empty() => Ok(Message::OpenWriteSelection),
+        "write-selection" => Ok(Message::WriteSelection(arg.to_owned())),
+        "retab" => {
+            let widths: Vec<usize> = arg.split_whitespace().map(str::parse).collect::<Result<_, _>>()
+                .map_err(|_| String::from("retab widths must be numbers"))?;
+            match widths[..] {
+                [from, to] if from > 0 => Ok(Message::Retab(from, to)),
+                _ => Err(String::from("usage: retab <from> <to>")),
+            }
+        },
+        "" => Ok(Message::NoMessage),
+        command => Err(format!("Unknown command {command:?}")),
+    }
+}
+
+/// The candidates that are close to `name`, best first
+pub fn close_matches<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let mut matches: Vec<(usize, &str)> = candidates.filter_map(|candidate| {
+        let lower = candidate.to_lowercase();
+        let distance = if lower.contains(&name) { 0 } else { edit_distance(&name, &lower) };
+        (distance <= (name.chars().count() / 3).max(2)).then_some((distance, candidate))
+    }).collect();
+    matches.sort();
+    matches.into_iter().take(5).map(|(_, candidate)| candidate).collect()
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + (ca != *cb) as usize;
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+impl utilities::Utility for CommandModel {
+    fn view(&self, _m: &Model, f: &mut Frame, area: Rect) {
+        f.render_widget(Clear, area);
+
+        let block = utilities::default_block("Command");
+
+        let layout = Layout::new(ratatui::layout::Direction::Vertical, [
+            Constraint::Length(1),
+            Constraint::Length(1), // Padding
+            Constraint::Min(0),
+        ]).split(block.inner(area));
+
+        f.render_widget(block, area);
+
+        let underlined = Style::default().add_modifier(Modifier::UNDERLINED);
+        let entry = match self.entry.len() {
+            0 => Span::styled(" ", underlined.fg(ratatui::style::Color::Gray)),
+            _ => Span::styled(self.entry.clone(), underlined),
+        };
+        f.render_widget(Paragraph::new(entry), layout[0]);
+
+        let candidates: Vec<Line> = self.candidates().into_iter()
+            .take(layout[2].height as usize)
+            .map(|candidate| Line::raw(candidate).dark_gray())
+            .collect();
+        f.render_widget(Paragraph::new(candidates), layout[2]);
+    }
+
+    fn update(&mut self, msg: Message) -> Option<Message> {
+        match msg {
+            Message::InsertChar(c) => {
+                self.entry.push(c);
+                None
+            },
+            Message::Backspace => {
+                self.entry.pop();
+                None
+            },
+            Message::Tab => {
+                self.complete();
+                None
+            },
+            Message::Enter => Some(Message::RunCommand(self.entry.clone())),
+            msg => Some(msg),
+        }
+    }
+
+    fn hint(&self) -> Option<String> {
+        Some(String::from("Enter: run  Tab: complete  Esc: close"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_commands_and_syntaxes() {
+        let mut command = CommandModel::new(vec![String::from("Rust"), String::from("Ruby"), String::from("C")]);
+        command.entry = String::from("set-s");
+        command.complete();
+        assert_eq!(command.entry, "set-syntax ");
+        command.entry.push_str("ru");
+        command.complete();
+        assert_eq!(command.entry, "set-syntax Ru");
+        command.entry.push('s');
+        command.complete();
+        assert_eq!(command.entry, "set-syntax Rust");
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert!(matches!(parse("set-syntax Rust"), Ok(Message::SetSyntax(name)) if name == "Rust"));
+        assert!(parse("set-syntax").is_err());
+        assert!(parse("frobnicate").is_err());
+        assert!(matches!(parse("retab 2 4"), Ok(Message::Retab(2, 4))));
+        assert!(parse("retab 0 4").is_err());
+        assert!(matches!(parse("transform rot13"), Ok(Message::Transform(name)) if name == "rot13"));
+        assert!(parse("transform rot14").is_err());
+    }
+
+    #[test]
+    fn suggests_close_matches() {
+        let names = ["Rust", "Ruby", "Python", "JavaScript"];
+        assert_eq!(close_matches("rsut", names.into_iter()), vec!["Rust"]);
+        assert_eq!(close_matches("script", names.into_iter()), vec!["JavaScript"]);
+    }
+}
@@ -0,0 +1,71 @@
+//! A list of completions for the word before the cursor, shown below it
+
+use ratatui::{layout::Rect, style::{Style, Stylize}, text::Line, widgets::{Clear, Paragraph}, Frame};
+
+use crate::model::{Message, Model};
+
+/// How many completions are shown at once
+const HEIGHT: usize = 8;
+
+pub struct CompletionModel {
+    pub completions: Vec<String>,
+    pub selected: usize,
+}
+
+impl CompletionModel {
+    pub fn new(completions: Vec<String>) -> Self {
+        Self { completions, selected: 0 }
+    }
+
+    /// The size of the list
+    pub fn size(&self) -> (u16, u16) {
+        let width = self.completions.iter().map(|word| word.chars().count()).max().unwrap_or(0) + 2;
+        (width as u16, self.completions.len().min(HEIGHT) as u16)
+    }
+
+    fn accept(&self) -> Message {
+        Message::Double(
+            Box::new(Message::CloseUtility),
+            Box::new(Message::InsertCompletion(self.completions[self.selected].clone()))
+        )
+    }
+}
+
+impl super::Utility for CompletionModel {
+    fn update(&mut self, msg: Message) -> Option<Message> {
+        let count = self.completions.len();
+        match msg {
+            Message::MoveUp => {
+                self.selected = (self.selected + count - 1) % count;
+                None
+            },
+            Message::MoveDown => {
+                self.selected = (self.selected + 1) % count;
+                None
+            },
+            Message::Enter | Message::Tab => Some(self.accept()),
+            // typing on refines the completions
+            Message::InsertChar(_) | Message::Backspace => Some(msg),
+            msg if msg.moves_cursor() || msg.is_edit() => Some(Message::Double(Box::new(Message::CloseUtility), Box::new(msg))),
+            msg => Some(msg),
+        }
+    }
+
+    /// `area` is where the list fits below (or above) the cursor
+    fn view(&self, _m: &Model, f: &mut Frame, area: Rect) {
+        let skip = (self.selected + 1).saturating_sub(HEIGHT);
+        let lines: Vec<Line> = self.completions.iter().enumerate().skip(skip).take(HEIGHT).map(|(i, word)| {
+            let line = Line::from(format!(" {word} "));
+            match i == self.selected {
+                true => line.style(Style::new().black().on_white()),
+                false => line.style(Style::new().white().on_dark_gray()),
+            }
+        }).collect();
+        f.render_widget(Clear, area);
+        f.render_widget(Paragraph::new(lines).on_dark_gray(), area);
+    }
+
+    fn hint(&self) -> Option<String> {
+        Some(String::from("↑/↓: choose  Enter/Tab: accept  Esc: close"))
+    }
+}
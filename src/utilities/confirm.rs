@@ -1,40 +1,119 @@
-use ratatui::widgets::{Clear, Paragraph};
+use ratatui::{layout::Rect, style::{Color, Style}, widgets::{Clear, Paragraph}};
 
 use crate::model::Message;
 use indoc::indoc;
 
 pub struct ConfirmModel {
     pub msg: String,
-    pub choices: Vec<(char, Message)>
+    pub choices: Vec<(char, Message)>,
+    /// What happens when the dialog is cancelled with Escape
+    pub cancel: Option<Message>,
 }
 
 impl ConfirmModel {
     pub fn new(msg: String, choices: Vec<(char, Message)>) -> Self {
-        Self {msg, choices}
+        Self {msg, choices, cancel: None}
+    }
+
+    /// Send `msg` when the dialog is cancelled
+    pub fn with_cancel(mut self, msg: Message) -> Self {
+        self.cancel = Some(msg);
+        self
+    }
+
+    fn choices(&self) -> String {
+        self.choices.iter().map(|(c, _a)| c.to_string()).collect::<Vec<_>>().join("/")
+    }
+
+    /// The message answering the dialog with `c`
+    fn choose(&self, c: char) -> Option<Message> {
+        let (_, action) = self.choices.iter().find(|(choice, _)| *choice == c)?;
+        Some(Message::Double(Box::new(Message::CloseUtility), Box::new(action.clone())))
+    }
+
+    fn content(&self) -> String {
+        format!("{}: {}", self.msg, self.choices())
+    }
+
+    /// Remind which keys answer the dialog
+    fn reminder(&self) -> Message {
+        Message::Notification(
+            format!("Press {} (Esc to cancel)", self.choices().replace('/', ", ")),
+            Style::new().bg(Color::White).fg(Color::Black)
+        )
     }
 }
 
 impl super::Utility for ConfirmModel {
     fn update(&mut self, msg: Message) -> Option<Message> {
         match msg {
-            Message::InsertChar(c) => {
-                for (choice, action) in self.choices.iter() {
-                    if *choice == c {
-                        return Some(Message::Double(
-                            Box::new(Message::CloseUtility),
-                            Box::new(action.clone())
-                        ));
-                    }
-                }
-                return None
+            Message::InsertChar(c) => Some(self.choose(c).unwrap_or_else(|| self.reminder())),
+            Message::Escape => match self.cancel.take() {
+                Some(cancel) => Some(Message::Double(Box::new(Message::CloseUtility), Box::new(cancel))),
+                None => Some(Message::CloseUtility),
             },
+            // keys that would otherwise edit the buffer behind the dialog
+            Message::Tab | Message::Enter | Message::Backspace | Message::Delete | Message::Paste(_) => Some(self.reminder()),
             msg => Some(msg)
         }
     }
     
     fn view(&self, m: &crate::model::Model, f: &mut ratatui::Frame, area: ratatui::prelude::Rect) {
-        let choicesstring= self.choices.iter().map(|(c, _a)| c.to_string()).collect::<Vec<_>>().join("/");
-        let content =  format!("{}: {}", self.msg, choicesstring);
-        super::default_view("Confirm", &content, f, area);
+        super::default_view("Confirm", &self.content(), f, area);
+    }
+
+    /// Clicking one of the choices answers with it
+    fn click(&mut self, x: u16, y: u16, area: Rect) -> Option<Message> {
+        let inner = super::default_block("Confirm").inner(area);
+        let wrapped = textwrap::fill(&self.content(), inner.width as usize);
+        let last = wrapped.lines().last()?;
+        if y != inner.y + wrapped.lines().count() as u16 - 1 {
+            return None;
+        }
+        // the choices end the last line
+        let column = x.checked_sub(inner.x)? as usize;
+        let choices_start = last.chars().count().saturating_sub(self.choices().chars().count());
+        if column < choices_start {
+            return None;
+        }
+        self.choose(last.chars().nth(column)?)
+    }
+
+    fn hint(&self) -> Option<String> {
+        Some(format!("{}: answer  Esc: cancel", self.choices()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::Utility;
+
+    #[test]
+    fn other_keys_hint_at_the_choices() {
+        let mut confirm = ConfirmModel::new(String::from("Sure?"), vec![('y', Message::Quit), ('n', Message::NoMessage)]);
+        assert!(matches!(confirm.update(Message::InsertChar('x')), Some(Message::Notification(hint, _)) if hint.starts_with("Press y, n")));
+        assert!(matches!(confirm.update(Message::Tab), Some(Message::Notification(..))));
+        assert!(matches!(confirm.update(Message::Escape), Some(Message::CloseUtility)));
+        assert!(matches!(confirm.update(Message::InsertChar('y')), Some(Message::Double(..))));
+    }
+
+    #[test]
+    fn escape_sends_the_cancel_message() {
+        let mut confirm = ConfirmModel::new(String::from("Sure?"), vec![('y', Message::Quit)]).with_cancel(Message::OpenHelp);
+        assert!(matches!(confirm.update(Message::Escape), Some(Message::Double(close, cancel))
+            if matches!(*close, Message::CloseUtility) && matches!(*cancel, Message::OpenHelp)));
+    }
+
+    #[test]
+    fn clicking_a_choice_answers() {
+        let mut confirm = ConfirmModel::new(String::from("Sure?"), vec![('y', Message::Quit), ('n', Message::NoMessage)]);
+        // "Sure?: y/n" inside the border
+        let area = Rect::new(10, 0, 30, 10);
+        assert!(matches!(confirm.click(18, 1, area), Some(Message::Double(_, quit)) if matches!(*quit, Message::Quit)));
+        assert!(matches!(confirm.click(20, 1, area), Some(Message::Double(_, no)) if matches!(*no, Message::NoMessage)));
+        assert!(confirm.click(19, 1, area).is_none());
+        assert!(confirm.click(11, 1, area).is_none());
+        assert!(confirm.click(18, 2, area).is_none());
     }
 }
@@ -1,9 +1,10 @@
 //! A utility for testing
 
 use ratatui::{style::{Color, Style}, widgets::{Clear, Paragraph, Wrap}};
-use indoc::indoc;
+use indoc::{formatdoc, indoc};
+use tracing::level_filters::LevelFilter;
 
-use crate::{model::Message, notification::Notification};
+use crate::{logging, model::Message, notification::Notification};
 
 pub struct DeveloperModel();
 
@@ -12,7 +13,13 @@ impl super::Utility for DeveloperModel {
         match msg {
             Message::InsertChar(char) => {
                 match char {
-                    n => Some(Message::Notification(indoc!{"
+                    't' => Some(Message::SetLogLevel(LevelFilter::TRACE)),
+                    'd' => Some(Message::SetLogLevel(LevelFilter::DEBUG)),
+                    'i' => Some(Message::SetLogLevel(LevelFilter::INFO)),
+                    'w' => Some(Message::SetLogLevel(LevelFilter::WARN)),
+                    'e' => Some(Message::SetLogLevel(LevelFilter::ERROR)),
+                    'l' => Some(Message::Double(Box::new(Message::CloseUtility), Box::new(Message::OpenLog))),
+                    'n' => Some(Message::Notification(indoc!{"
                         warning: unused variable: `width`
                         --> src/view.rs:139:21
                             |
@@ -29,8 +36,16 @@ impl super::Utility for DeveloperModel {
         }
     }
     fn view(&self, m: &crate::model::Model, f: &mut ratatui::Frame, area: ratatui::prelude::Rect) {
-        super::default_view("brrrrr", indoc! {"
+        let level = logging::log_level().map(|l| l.to_string()).unwrap_or(String::from("none"));
+        super::default_view("brrrrr", &formatdoc! {"
         * n - create an error notification
+        * t/d/i/w/e - set log level
+        * l - open the log
+        log level: {level}
         "}, f, area);
     }
+
+    fn hint(&self) -> Option<String> {
+        Some(String::from("t/d/i/w/e: log level  l: log  Esc: close"))
+    }
 }
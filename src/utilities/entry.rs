@@ -0,0 +1,79 @@
+//! A prompt for a single line of text
+
+use std::path::PathBuf;
+
+use ratatui::{layout::{Constraint, Layout, Rect}, style::{Modifier, Style}, text::Span, widgets::{Clear, Paragraph}, Frame};
+
+use crate::{model::{Message, Model}, utilities};
+
+pub struct EntryModel {
+    pub title: String,
+    pub entry: String,
+    /// Creates the message that is sent when the entry is submitted
+    submit: fn(String) -> Message,
+    /// Tab completes paths relative to this directory
+    paths: Option<PathBuf>,
+}
+
+impl EntryModel {
+    pub fn new(title: &str, submit: fn(String) -> Message) -> Self {
+        Self { title: title.to_owned(), entry: String::new(), submit, paths: None }
+    }
+
+    /// The entry is a path, relative to `dir`
+    pub fn with_path_completion(mut self, dir: PathBuf) -> Self {
+        self.paths = Some(dir);
+        self
+    }
+}
+
+impl utilities::Utility for EntryModel {
+    fn view(&self, _m: &Model, f: &mut Frame, area: Rect) {
+        let block = utilities::default_block(&self.title);
+        let area = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).split(area)[0];
+        let inner = block.inner(area);
+
+        f.render_widget(Clear, area);
+        f.render_widget(block, area);
+
+        let underlined = Style::default().add_modifier(Modifier::UNDERLINED);
+        let entry = match self.entry.len() {
+            0 => Span::styled(" ", underlined.fg(ratatui::style::Color::Gray)),
+            _ => Span::styled(self.entry.clone(), underlined),
+        };
+        f.render_widget(Paragraph::new(entry), inner);
+    }
+
+    fn update(&mut self, msg: Message) -> Option<Message> {
+        match msg {
+            Message::InsertChar(c) => {
+                self.entry.push(c);
+                None
+            },
+            Message::Backspace => {
+                self.entry.pop();
+                None
+            },
+            Message::Paste(paste) => {
+                self.entry.push_str(paste.trim_end_matches('\n'));
+                None
+            },
+            Message::Tab => match &self.paths {
+                Some(dir) => utilities::complete_path(&mut self.entry, 0, dir),
+                None => None,
+            },
+            Message::Enter => Some(Message::Double(
+                Box::new(Message::CloseUtility),
+                Box::new((self.submit)(self.entry.clone()))
+            )),
+            msg => Some(msg),
+        }
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self.paths {
+            Some(_) => Some(String::from("Tab: complete  Enter: confirm  Esc: cancel")),
+            None => Some(String::from("Enter: confirm  Esc: cancel")),
+        }
+    }
+}
@@ -1,14 +1,82 @@
+use std::{fs, path::PathBuf, sync::{Mutex, OnceLock}};
+
 use ratatui::{layout::{Constraint, Layout, Rect}, style::{Modifier, Style, Stylize}, text::{Line, Span}, widgets::{Clear, Paragraph, Wrap}, Frame};
+use tracing::warn;
 
 use crate::{model::{Message, Model}, utilities};
 
+/// How many past searches are remembered
+const HISTORY_LENGTH: usize = 100;
+
+/// Past searches, oldest first (kept across sessions)
+static HISTORY: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+
+fn history_path() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("atto-find-history"))
+}
+
+fn history() -> &'static Mutex<Vec<String>> {
+    HISTORY.get_or_init(|| {
+        let history = history_path().and_then(|path| fs::read_to_string(path).ok()).unwrap_or_default();
+        Mutex::new(history.lines().map(str::to_owned).collect())
+    })
+}
+
+/// Add a search to the history, unless it repeats the last one
+fn push(history: &mut Vec<String>, query: &str) -> bool {
+    if query.is_empty() || query.contains('\n') || history.last().is_some_and(|last| last == query) {
+        return false;
+    }
+    history.push(query.to_owned());
+    if history.len() > HISTORY_LENGTH {
+        history.remove(0);
+    }
+    true
+}
+
+/// Remember a search for the next time
+fn remember(query: &str) {
+    let mut history = history().lock().unwrap();
+    if push(&mut history, query) {
+        if let Some(path) = history_path() {
+            if let Err(e) = fs::write(&path, history.join("\n")) {
+                warn!("Failed to write the find history to {path:?}: {e}");
+            }
+        }
+    }
+}
+
 pub struct FindModel {
     pub entry: String,
+    /// Enter searches towards the start of the buffer
+    pub backward: bool,
+    /// Which past search is shown (counting back from the newest)
+    history_index: Option<usize>,
 }
 
 impl FindModel {
     pub fn new() -> Self {
-        Self { entry: String::new() }
+        Self { entry: String::new(), backward: false, history_index: None }
+    }
+
+    /// Show an older (or newer) past search
+    fn browse_history(&mut self, older: bool) -> Option<Message> {
+        let history = history().lock().unwrap();
+        let index = match (self.history_index, older) {
+            (None, true) => 0,
+            (None, false) => return None,
+            (Some(i), true) => (i + 1).min(history.len().saturating_sub(1)),
+            (Some(0), false) => {
+                self.history_index = None;
+                self.entry.clear();
+                return Some(Message::Find(String::new()));
+            },
+            (Some(i), false) => i - 1,
+        };
+        let query = history.iter().rev().nth(index)?;
+        self.history_index = Some(index);
+        self.entry = query.clone();
+        Some(Message::Find(self.entry.clone()))
     }
 }
 
@@ -20,7 +88,8 @@ impl utilities::Utility for FindModel {
 
        f.render_widget(Clear, area);
 
-       let block = utilities::default_block("Find");
+       let title = if self.backward { "Find ↑" } else { "Find ↓" };
+       let block = utilities::default_block(title);
 
        let layout = Layout::new(ratatui::layout::Direction::Vertical, [
            Constraint::Length(3),
@@ -50,16 +119,58 @@ impl utilities::Utility for FindModel {
    fn update(&mut self, msg: Message) -> Option<Message> {
        match msg {
            Message::InsertChar(c) => {
+               self.history_index = None;
                self.entry.push(c);
                Some(Message::Find(self.entry.clone()))
            },
            Message::Backspace => {
+            self.history_index = None;
             self.entry.pop();
             Some(Message::Find(self.entry.clone()))
            },
-           // we could do a thing where if it receives an ambigious Message:Next
-           // it can choose to replace it with a Message:NextSelection or Message::NextHighlight
+           Message::Enter => {
+               remember(&self.entry);
+               match self.backward {
+                   true => Some(Message::JumpPreviousHighlight),
+                   false => Some(Message::JumpNextHighlight),
+               }
+           },
+           Message::Escape => {
+               remember(&self.entry);
+               Some(msg)
+           },
+           Message::MoveUp => self.browse_history(true),
+           Message::MoveDown => self.browse_history(false),
+           Message::Tab => {
+               self.backward = !self.backward;
+               None
+           },
            msg => Some(msg),
        }
    }
+
+    fn hint(&self) -> Option<String> {
+        Some(String::from("Type to search  Enter: next  Tab: direction  ↑/↓: history  Esc: close"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_skips_repeats() {
+        let mut history = vec![];
+        assert!(push(&mut history, "foo"));
+        assert!(!push(&mut history, "foo"));
+        assert!(!push(&mut history, ""));
+        assert!(push(&mut history, "bar"));
+        assert!(push(&mut history, "foo"));
+        assert_eq!(history, vec!["foo", "bar", "foo"]);
+        for i in 0..HISTORY_LENGTH {
+            push(&mut history, &i.to_string());
+        }
+        assert_eq!(history.len(), HISTORY_LENGTH);
+        assert_eq!(history[0], "0");
+    }
 }
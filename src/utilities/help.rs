@@ -11,18 +11,51 @@ impl Utility for HelpModel {
         Welcome to Atto!
         Here is a list of keybinds:
         C-c Copy
-        C-x Cut
         C-v Paste
         C-a Select All
+        C-k Delete to end of line
+        C-u Delete to start of line
+        C-y Paste deleted text
+        C-z Undo
+        C-Z Redo
+        A-[ A-] Switch redo branch
+        C-t Transpose characters
+        C-T Reopen closed buffer
+        C-n New scratch buffer
+        A-t Transpose words
+        A-. Repeat last edit
+        A-J Join lines
+        A-o A-O Open line below/above
         A-a Start
         A-e End
         A-j Right
         A-i Up
         A-f Left
         A-n Down
+        A-b Word left
+        A-w Word right
+        A-l Relative line numbers
+        A-d Duplicate buffer
+        A-z Wrap lines
+        A-U Insert code point (U+…) or digraph
+        A-/ Complete word
+        A-c Search case
+        A-r Replace search results
+        A-R Replace search results one by one
         C-f Find
         C-e Command
+        C-r Insert file
+        C-w Write selection to file
+        C-/ Toggle comment
         C-b Shell
+        C-x C-s Save
+        C-x C-c Quit
+        C-x k Close buffer
+        C-x u Undo
        "}, f, area);
     }
+
+    fn hint(&self) -> Option<String> {
+        Some(String::from("Esc: close"))
+    }
 }
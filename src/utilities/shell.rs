@@ -1,4 +1,4 @@
-use std::{env, io::{self, stdout, BufRead, BufReader, Read, Stdout, Write}, os::fd::{AsRawFd, BorrowedFd}, process::{self, Command, Stdio}};
+use std::{borrow::Cow, env, io::{self, stdout, BufRead, BufReader, Read, Stdout, Write}, os::fd::{AsRawFd, BorrowedFd}, path::PathBuf, process::{self, Command, ExitStatus, Stdio}};
 
 use crossterm::{event::{DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, KeyboardEnhancementFlags, PushKeyboardEnhancementFlags}, terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}, ExecutableCommand, QueueableCommand};
 use nix::{libc::POLLIN, poll::{poll, PollFd, PollFlags, PollTimeout}, sys::{select::FdSet, time::TimeVal}};
@@ -15,11 +15,13 @@ static UNIX_SHELL: &'static str = "fish";
 #[derive(Debug)]
 pub struct ShellModel {
     pub entry: String,
+    /// Where commands run, the editor's working directory if not set
+    pub dir: Option<PathBuf>,
 }
 
 impl ShellModel {
-    pub fn new() -> Self {
-        Self { entry: String::new() }
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { entry: String::new(), dir }
     }
 
     #[tracing::instrument(skip_all, level="info", fields(cmd=self.entry))]
@@ -38,105 +40,82 @@ impl ShellModel {
         terminal.set_cursor_position((0,0))?;
         crate::tui::restore()?;
         
+        if let Some(dir) = &self.dir {
+            cmd.current_dir(dir);
+        }
         let mut child = cmd.arg(&self.entry)
             .stdin(Stdio::inherit())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn().log()?;
 
-        self.entry.clear();
-
-        // we could potentially improve performance by using a bufreader
-        let mut stdout_pipe = child.stdout.take().unwrap();
-        let mut stderr_pipe = child.stderr.take().unwrap();
-
-        let mut stdout_buf = [0; 1024];
-        let mut stderr_buf = [0; 1024];
-
-        let mut stdout = vec![];
-        let mut stderr = vec![];
-
-        let mut pollfds = vec![
-            PollFd::new(unsafe { BorrowedFd::borrow_raw(stdout_pipe.as_raw_fd()) }, PollFlags::POLLIN),
-            PollFd::new(unsafe { BorrowedFd::borrow_raw(stderr_pipe.as_raw_fd()) }, PollFlags::POLLIN),
-        ];
-
-        loop {
-            match child.try_wait()? {
-                Some(status) => {
-                    terminal.clear()?;
-                    crate::tui::setup()?;
-                    debug!("Exited with status {:?}", status.code());
-                    stdout_pipe.read_to_end(&mut stdout)?;
-                    stderr_pipe.read_to_end(&mut stderr)?;
-                    debug!("Total {}b stdout, {}b stderr", stdout.len(), stderr.len());
-                    
-                    let stdout = String::from_utf8(stdout);
-                    let stderr = String::from_utf8(stderr);
-
-                    /*
-                    I really need to fix the output formatting here.
-                    If the stdout and stderr are empty user should still get feedback.
-                    The utf8 should never be invalid. I feel like that would be worthy of an error.
-                    When there is both stdout and stderr they should've preferable been mixed
-                    beforehand.
-                     */
-
-                    if stdout.is_err() || stderr.is_err() {
-                        warn!("Failed to utf8 parse command output");
-                        match status.success() {
-                            true => {
-                                return Ok(Message::Notification(
-                                    format!("Command executed succesfully, but output was not utf8"),
-                                    Style::new().bg(Color::Green).fg(Color::White)
-                                ));
-                            },
-                            false => {
-                                return Ok(Message::Notification(
-                                    format!("Command failed with code {:?}, output was not utf8", status.code()),
-                                    Style::new().bg(Color::Red).fg(Color::White)
-                                ));
-                            }
-                        }
-                    } else {
-                        let style = if status.success(){
-                            Style::new().bg(Color::White).fg(Color::Black)
-                        }
-                        else {
-                            Style::new().bg(Color::Red).fg(Color::White)
-                        };
-                        let stdout = stdout.unwrap();
-                        let stderr = stderr.unwrap();
-                        let display = if stderr.is_empty() || stdout.is_empty() {
-                            format!("{}{}", stdout.trim(), stderr.trim()) }
-                        else {
-                            format!("{}\n{}", stdout.trim(), stderr.trim())
-                        };
-                
-                        return Ok(Message::Notification(display, style))
-                    }
-                },
-                None => {
-                    pollfds[0].set_events(PollFlags::POLLIN);
-                    pollfds[1].set_events(PollFlags::POLLIN);
-                    if poll(&mut pollfds, Some(10_u8))? > 0 {
-                        if pollfds[0].any().unwrap() {
-                            let n = stdout_pipe.read(&mut stdout_buf)?;
-                            debug!("received {} bytes in stdout", n);
-                            stdout.extend_from_slice(&stdout_buf[..n]);
-                            io::stdout().write_all(&stdout_buf[..n])?;
-                        }
-                        if pollfds[1].any().unwrap() {
-                            let n = stderr_pipe.read(&mut stderr_buf)?;
-                            debug!("received {} bytes in stderr", n);
-                            stderr.extend_from_slice(&stderr_buf[..n]);
-                            io::stderr().write_all(&stderr_buf[..n])?;
-                        }
-                    }
-                },
+        let command = std::mem::take(&mut self.entry);
+
+        let (status, output) = collect(&mut child, true)?;
+        terminal.clear()?;
+        crate::tui::setup()?;
+        debug!("Exited with status {:?}, {}b of output", status.code(), output.len());
+        Ok(summary(command, &output, status))
+    }
+}
+
+/// Wait for a command to exit while reading what it writes,
+/// stdout and stderr together in the order they were written.
+/// With `echo` the output is also shown on the terminal as it comes in.
+fn collect(child: &mut process::Child, echo: bool) -> io::Result<(ExitStatus, Vec<u8>)> {
+    let mut stdout_pipe = child.stdout.take().unwrap();
+    let mut stderr_pipe = child.stderr.take().unwrap();
+
+    let mut buf = [0; 1024];
+    let mut output = vec![];
+
+    let mut pollfds = vec![
+        PollFd::new(unsafe { BorrowedFd::borrow_raw(stdout_pipe.as_raw_fd()) }, PollFlags::POLLIN),
+        PollFd::new(unsafe { BorrowedFd::borrow_raw(stderr_pipe.as_raw_fd()) }, PollFlags::POLLIN),
+    ];
+
+    loop {
+        // checked before reading so nothing written before exiting is missed
+        let exited = child.try_wait()?;
+        pollfds[0].set_events(PollFlags::POLLIN);
+        pollfds[1].set_events(PollFlags::POLLIN);
+        if poll(&mut pollfds, Some(10_u8))? > 0 {
+            if pollfds[0].any().unwrap() {
+                let n = stdout_pipe.read(&mut buf)?;
+                output.extend_from_slice(&buf[..n]);
+                if echo {
+                    io::stdout().write_all(&buf[..n])?;
+                }
+            }
+            if pollfds[1].any().unwrap() {
+                let n = stderr_pipe.read(&mut buf)?;
+                output.extend_from_slice(&buf[..n]);
+                if echo {
+                    io::stderr().write_all(&buf[..n])?;
+                }
             }
         }
+        if let Some(status) = exited {
+            stdout_pipe.read_to_end(&mut output)?;
+            stderr_pipe.read_to_end(&mut output)?;
+            return Ok((status, output));
+        }
+    }
+}
+
+/// What a finished command reports: its output, or that there was none.
+/// Output that isn't UTF-8 is shown as well as it can be.
+fn summary(command: String, output: &[u8], status: ExitStatus) -> Message {
+    let text = String::from_utf8_lossy(output);
+    if let Cow::Owned(_) = text {
+        warn!("Command output was not UTF-8");
     }
+    let display = match (text.trim(), status.success()) {
+        ("", true) => String::from("(no output)"),
+        ("", false) => format!("Command failed ({status})"),
+        (text, _) => text.to_owned(),
+    };
+    Message::CommandOutput(command, display, status.success())
 }
 
 impl super::Utility for ShellModel {
@@ -145,6 +124,11 @@ impl super::Utility for ShellModel {
             Message::InsertChar(c) => self.entry.push(*c),
             Message::Paste(paste) => self.entry.push_str(paste),
             Message::Backspace => { self.entry.pop(); },
+            Message::Tab => {
+                let start = self.entry.rfind(' ').map_or(0, |i| i + 1);
+                let dir = self.dir.clone().or_else(|| env::current_dir().ok()).unwrap_or_default();
+                return super::complete_path(&mut self.entry, start, &dir);
+            },
             Message::Enter => return match self.exec().log() {
                 Ok(m) => Some(m),
                 Err(e) => Some(Message::Notification(format!("{e:?}"), Style::new().bg(Color::Red)))
@@ -155,6 +139,48 @@ impl super::Utility for ShellModel {
     }
 
     fn view(&self, m: &Model, f: &mut Frame, area: Rect) {
-        super::default_view("Shell", &self.entry, f, area);
+        let title = match &self.dir {
+            Some(dir) => format!("Shell ({})", dir.display()),
+            None => String::from("Shell"),
+        };
+        super::default_view(&title, &self.entry, f, area);
+    }
+
+    fn hint(&self) -> Option<String> {
+        Some(String::from("Enter: run  Tab: complete path  Esc: close"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    fn output(output: &[u8], status: i32) -> String {
+        match summary(String::from("command"), output, ExitStatus::from_raw(status)) {
+            Message::CommandOutput(_, output, _) => output,
+            _ => panic!("not the output of a command"),
+        }
+    }
+
+    #[test]
+    fn reports_empty_output() {
+        assert_eq!(output(b"", 0), "(no output)");
+        assert_eq!(output(b"\n", 1 << 8), "Command failed (exit status: 1)");
+        assert_eq!(output(b"ok\xff\n", 0), "ok\u{fffd}");
+    }
+
+    #[test]
+    fn interleaves_stdout_and_stderr() {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("echo out; sleep 0.1; echo err >&2; sleep 0.1; echo out again")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn().unwrap();
+        let (status, output) = collect(&mut child, false).unwrap();
+        assert!(status.success());
+        assert_eq!(String::from_utf8(output).unwrap(), "out\nerr\nout again\n");
     }
 }
@@ -0,0 +1,40 @@
+//! Shows the undo tree of the current buffer
+
+use ratatui::{layout::{Constraint, Layout, Rect}, widgets::{Clear, Paragraph}, Frame};
+
+use crate::model::{Message, Model};
+
+pub struct UndoTreeModel();
+
+impl super::Utility for UndoTreeModel {
+    fn update(&mut self, msg: Message) -> Option<Message> {
+        match msg {
+            Message::MoveUp => Some(Message::Undo),
+            Message::MoveDown => Some(Message::Redo),
+            Message::MoveLeft => Some(Message::PreviousUndoBranch),
+            Message::MoveRight => Some(Message::NextUndoBranch),
+            msg => Some(msg),
+        }
+    }
+
+    fn view(&self, m: &Model, f: &mut Frame, area: Rect) {
+        let block = super::default_block("Undo tree");
+        let lines = m.current_buffer().undo.lines();
+        let height = block.inner(area).height as usize;
+        // keep the current group in view
+        let current = lines.iter().position(|line| line.trim_start().starts_with('*')).unwrap_or(0);
+        let skip = (current + 1).saturating_sub(height);
+        let content = lines[skip..].iter().take(height).cloned().collect::<Vec<_>>().join("\n");
+        let bordersandpadding = area.height - block.inner(area).height;
+        let area = Layout::vertical([
+            Constraint::Length(lines.len().min(height) as u16 + bordersandpadding),
+            Constraint::Min(0),
+        ]).split(area)[0];
+        f.render_widget(Clear, area);
+        f.render_widget(Paragraph::new(content).block(block), area);
+    }
+
+    fn hint(&self) -> Option<String> {
+        Some(String::from("↑/↓: undo/redo  ←/→: branch  Esc: close"))
+    }
+}
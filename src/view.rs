@@ -1,12 +1,17 @@
 //! For rendering the model
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, cmp, rc::Rc};
 
 use color_eyre::owo_colors::OwoColorize;
-use ratatui::{layout::{Alignment, Constraint, Direction, Layout}, style::{Style, Stylize}, text::{Line, Text}, widgets::{Clear, Paragraph, Scrollbar, ScrollbarState}, Frame};
+use ratatui::{layout::{Alignment, Constraint, Direction, Layout, Rect}, style::{Color, Style, Stylize}, text::{Line, Span, Text}, widgets::{Clear, Paragraph, Scrollbar, ScrollbarState}, Frame};
 use syntect::{util::LinesWithEndings, highlighting::{Highlighter, Theme}, parsing::SyntaxSet};
 
-use crate::{model::Model, parse::{parse_from, ParseCache}, utilities::{Utility}};
+use crate::{model::Model, parse::{parse_from, whitespace::TABSIZE, DisplayOptions, ParseCache}, syntect_tui, utilities::{Utility}};
 use crate::buffer::Buffer;
+use crate::config::ColumnIndicator;
+use crate::project;
+use crate::bidi;
+use crate::status;
+use crate::wrap;
 use crate::utilities::UtilityWindow;
 
 /// files over this size might be handled differently (like not having a scrollbar)
@@ -19,79 +24,189 @@ pub trait View {
 impl View for Model {
     #[tracing::instrument(skip_all, level="trace")]
     fn view(&mut self, f: &mut Frame) {
-        // split between status bar and rest
+        // split between tab bar, status bar and rest
         let main = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Min(0), Constraint::Length(1)])
+                .constraints([Constraint::Length(self.tab_bar_height()), Constraint::Min(0), Constraint::Length(1)])
                 .split(f.area());
 
+        if main[0].height > 0 {
+            let tabs: Vec<Span> = self.tabs(main[0].width).into_iter().map(|(i, _x, label)| {
+                if i == self.selected {
+                    Span::styled(label, Style::new().black().on_white().bold())
+                } else {
+                    Span::styled(label, Style::new().white().on_dark_gray())
+                }
+            }).collect();
+            f.render_widget(Paragraph::new(Line::from(tabs)).on_dark_gray(), main[0]);
+        }
+
         let large_file = self.current_buffer().content.len() > LARGE_FILE_LIMIT;
         let content_height = if large_file { usize::MAX } else { self.current_buffer().content.chars().filter(|c| *c == '\n').count() };
-        let scrollbar_width = if content_height as u16 > f.area().height {1} else {0};
+        let scrollbar_width = if content_height >= main[1].height as usize {1} else {0};
 
         let buffer_and_scrollbar = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Min(0), Constraint::Length(scrollbar_width)])
-            .split(main[0]);
+            .split(main[1]);
 
         let vertical_middle_split = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-            .split(f.area());
+            .split(main[1]);
 
         let utility_area  = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Min(0), Constraint::Max(30), Constraint::Length(scrollbar_width)])
             .split(vertical_middle_split[0])[1];
+        self.utility_area = utility_area;
+
+        // the reading layout centers the text
+        let margin = self.reading_margin();
+        let centered = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(margin), Constraint::Min(0), Constraint::Length(margin)])
+            .split(buffer_and_scrollbar[0])[1];
+
+        let gutter_and_text = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(self.gutter_width()), Constraint::Min(0)])
+            .split(centered);
+        let text_area = gutter_and_text[1];
 
         // Scroll the buffer if the cursor was moved out of view.
-        {
-            let may_scroll = self.may_scroll;
-            let current_buffer = self.current_buffer_mut();
-            let (_, cursor_y) = current_buffer.cursor_pos();
-            if may_scroll {
-                if cursor_y < current_buffer.top as u16 {
-                    current_buffer.top = cursor_y as usize;
-                } else if cursor_y >= current_buffer.top as u16 + buffer_and_scrollbar[0].height {
-                    let diff = cursor_y - (current_buffer.top as u16 + buffer_and_scrollbar[0].height);
-                    current_buffer.top += diff as usize + 1;
-                }
+        if self.may_scroll {
+            let scrolloff = self.config.scrolloff;
+            self.current_buffer_mut().scroll_view(text_area.height as usize, scrolloff);
+            match self.wrap {
+                true => self.current_buffer_mut().scroll_view_wrapped(text_area.height as usize, text_area.width as usize),
+                false => self.current_buffer_mut().scroll_view_horizontally(text_area.width as usize, text_area.height as usize),
             }
-            self.may_scroll = false;
         }
+        if self.wrap {
+            self.current_buffer_mut().left = 0;
+        }
+        self.may_scroll = false;
 
         let current_buffer = self.current_buffer();
 
-        let (cursor_x, cursor_y) = current_buffer.cursor_pos();
+        let (mut cursor_x, cursor_y) = current_buffer.cursor_pos();
+
+        // the rows on screen when wrapping
+        let rows = self.wrap.then(|| wrap::screen_rows(&current_buffer.content, current_buffer.top, text_area.height as usize, text_area.width as usize));
+        let columns = match self.wrap {
+            true => None,
+            false => current_buffer.visible_columns(text_area.height as usize),
+        };
+        if columns.is_some() {
+            cursor_x = current_buffer.display_column(text_area.height as usize) as u16;
+        }
+
+        if gutter_and_text[0].width > 0 {
+            f.render_widget(
+                line_numbers(current_buffer, cursor_y as usize, gutter_and_text[0], self.relative_numbers, self.config.line_numbers || self.relative_numbers, rows.as_deref()),
+                gutter_and_text[0]
+            );
+        }
 
         let cache = self.parse_caches.get(&current_buffer.name).unwrap().clone();
+        // where the cursor is shown if its line is mirrored
+        let mut rtl_cursor = None;
 
-        let buffer_widget = match highlight(current_buffer, buffer_and_scrollbar[0].height as usize, cache, &self.syntax_set, self.theme(), self.show_whitespace) {
-            Ok(tokens) => Paragraph::new(tokens),
+        let options = DisplayOptions {
+            show_whitespace: self.show_whitespace,
+            columns: columns.as_ref(),
+            brackets: self.show_rainbow_brackets.then_some(&self.config.bracket_colors[..]),
+            wrap: self.wrap.then_some(text_area.width as usize),
+            rows: rows.as_deref(),
+        };
+        let buffer_widget = match highlight(current_buffer, buffer_and_scrollbar[0].height as usize, cache, &self.syntax_set, self.theme(), &options) {
+            Ok(mut tokens) => {
+                // mirror right-to-left lines (the selection and search results aren't mirrored)
+                if self.bidi && rows.is_none() && columns.is_none() {
+                    for (i, line) in tokens.iter_mut().enumerate() {
+                        let text: String = line.spans.iter().map(|span| span.content.as_ref()).collect();
+                        if !bidi::line_is_rtl(&text) {
+                            continue;
+                        }
+                        let (mirrored, order) = bidi::mirror(line);
+                        *line = mirrored;
+                        if current_buffer.top + i == cursor_y as usize {
+                            let before = &current_buffer.content[..current_buffer.position];
+                            let index = bidi::display_index(&before[before.rfind('\n').map_or(0, |i| i + 1)..]);
+                            rtl_cursor = Some(bidi::visual_column(&order, index, text_area.width as usize) as u16);
+                        }
+                    }
+                }
+                Paragraph::new(tokens).scroll((0, current_buffer.left as u16))
+            },
             Err(e) => {
                 tracing::error!("{:?}", e);
                 // TODO unless we can cover stuff like tabs and showing whitespace here (and wordwrapping)
                 // we really should rely on our own parse function
                 // and this should be a hard error
-                Paragraph::new(current_buffer.content.as_str()).scroll((current_buffer.top as u16,0))
+                Paragraph::new(current_buffer.content.as_str()).scroll((current_buffer.top as u16, current_buffer.left as u16))
             },
         };
 
         f.render_widget(
             buffer_widget,
-            buffer_and_scrollbar[0]
+            text_area
         );
 
-        if cursor_y >= self.current_buffer().top as u16 {
-            f.set_cursor_position((cursor_x, cursor_y - self.current_buffer().top as u16));
+        // the guides don't follow wrapped rows
+        if self.show_indent_guides && !self.wrap {
+            indent_guides(f, text_area, self.current_buffer(), cursor_y as usize, self.theme());
         }
 
+        let left = self.current_buffer().left as u16;
+        let cursor = if let Some(rows) = &rows {
+            let content = &self.current_buffer().content;
+            let position = self.current_buffer().position;
+            wrap::row_of(content, rows, position).map(|i| {
+                let x = wrap::display_width(&content[rows[i].start..position]);
+                (text_area.x + x as u16, text_area.y + i as u16)
+            })
+        } else if let Some(x) = rtl_cursor {
+            Some((text_area.x + x, text_area.y + cursor_y - self.current_buffer().top as u16))
+        } else if cursor_y >= self.current_buffer().top as u16 && cursor_x >= left {
+            Some((text_area.x + cursor_x - left, text_area.y + cursor_y - self.current_buffer().top as u16))
+        } else {
+            None
+        };
+
+        // under the search results and the selection
+        if let Some(cursor) = cursor.filter(|_| self.show_crosshair) {
+            crosshair(f, text_area, cursor, self.theme());
+        }
+
+        if self.flashing {
+            f.buffer_mut().set_style(text_area, Style::new().reversed());
+        }
+
+        let visible = visible_bytes(self.current_buffer(), text_area.height as usize);
+        for highlight in self.current_buffer().highlights.iter().filter(|(start, end)| *end > visible.start && *start < visible.end) {
+            style_range(f, text_area, self.current_buffer(), *highlight, Style::new().black().on_yellow(), &options);
+        }
+
+        if let Some(selection) = self.current_buffer().selection {
+            style_range(f, text_area, self.current_buffer(), selection, Style::new().reversed(), &options);
+        }
+
+        if let Some(cursor) = cursor {
+            f.set_cursor_position(cursor);
+        }
+
+        // the content length is the amount of positions `top` can take,
+        // together with the viewport length the thumb shows how much is visible
         let scrollbar = Scrollbar::default();
-            let mut scrollbar_state = if large_file { ScrollbarState::new(1) } else {
-            ScrollbarState::new(content_height.saturating_sub(f.area().height as usize))
+        let height = text_area.height as usize;
+        let mut scrollbar_state = if large_file { ScrollbarState::new(1) } else {
+            ScrollbarState::new((content_height + 1).saturating_sub(height) + 1)
+            .viewport_content_length(height)
             .position(self.current_buffer().top)
         };
-        
+
         if scrollbar_width > 0 {
             f.render_stateful_widget(
                 scrollbar,
@@ -100,25 +215,27 @@ impl View for Model {
             );
         }
     
+        // the keys of an open utility replace the welcome, a pending sequence or slow operation replaces both
+        let hint = match (&self.busy, self.key_prefix.is_empty()) {
+            (Some(busy), _) => busy.clone(),
+            (None, true) => self.utility.as_ref().and_then(UtilityWindow::hint).unwrap_or(String::from("Welcome to Atto! Ctrl-h for help")),
+            (None, false) => format!("{} …", self.key_prefix),
+        };
         f.render_widget(
             Paragraph::new(
                 Line::styled(
                     std::format!(
                         " {:<} {:>width$} ",
-                        "Welcome to Atto! Ctrl-h for help",
-                        std::format!("{}{} {}/{}",
-                            self.current_buffer().name,
-                            if self.current_buffer().dirty().unwrap_or_else(|e| {tracing::error!("{:?}", e); true}) { "+" } else { "" },
-                            self.selected+1, self.buffers.len(),
-                        ),
-                        width = main[1].width as usize - "Welcome to Atto! Ctrl-h for help".len() - 3
+                        hint,
+                        self.status(),
+                        width = (main[2].width as usize).saturating_sub(hint.chars().count() + 3)
                     ),
                     Style::default()
                     .black()
                     .on_white()
                 )
             ),
-            main[1]
+            main[2]
         );
 
         match &self.utility {
@@ -127,6 +244,16 @@ impl View for Model {
             Some(UtilityWindow::Confirm(confirm)) => confirm.view(&self, f, utility_area),
             Some(UtilityWindow::Developer(developer)) => developer.view(&self, f, utility_area),
             Some(UtilityWindow::Shell(shell)) => shell.view(&self, f, utility_area),
+            Some(UtilityWindow::Command(command)) => command.view(&self, f, utility_area),
+            Some(UtilityWindow::Entry(entry)) => entry.view(&self, f, utility_area),
+            Some(UtilityWindow::UndoTree(undo_tree)) => undo_tree.view(&self, f, utility_area),
+            Some(UtilityWindow::Completion(completion)) => {
+                if let Some((x, y)) = cursor {
+                    let area = completion_area(completion.size(), x, y, main[1]);
+                    self.utility_area = area;
+                    completion.view(&self, f, area);
+                }
+            },
             None => {},
         }
 
@@ -159,10 +286,205 @@ impl View for Model {
     }
 }
 
+impl Model {
+    /// The right side of the status bar, as configured by `status_format`
+    fn status(&self) -> String {
+        let buffer = self.current_buffer();
+        status::render(&self.config.status_format, |placeholder| match placeholder {
+            "position" => column_indicator(buffer, self.config.column_indicator),
+            "line" => (buffer.cursor_pos().1 + 1).to_string(),
+            "col" => (buffer.cursor_pos().0 + 1).to_string(),
+            "encoding" => if buffer.encoding == encoding_rs::UTF_8 { String::new() } else { format!("{} ", buffer.encoding.name()) },
+            "name" if buffer.scratch => buffer.label().to_owned(),
+            "name" => project::relative_name(&buffer.name, &self.root),
+            "symlink" => match &buffer.symlink { Some(target) => format!(" → {}", target.display()), None => String::new() },
+            "dirty" => if buffer.unsaved() { String::from("+") } else { String::new() },
+            "readonly" => if buffer.opened_readonly || buffer.readonly { String::from(" [RO]") } else { String::new() },
+            "syntax" => buffer.syntax_name().to_owned(),
+            "buffer" => (self.selected + 1).to_string(),
+            "buffers" => self.buffers.len().to_string(),
+            _ => String::new(),
+        })
+    }
+}
+
+/// Where a list of completions of `size` goes: below the cursor at `x` and `y`,
+/// or above it if there is no room below, moved left to fit in `area`
+fn completion_area((width, height): (u16, u16), x: u16, y: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let x = x.saturating_sub(1).min(area.right().saturating_sub(width));
+    let (y, height) = match y + 1 + height <= area.bottom() {
+        true => (y + 1, height),
+        false => {
+            let height = height.min(y.saturating_sub(area.y));
+            (y - height, height)
+        },
+    };
+    Rect::new(x, y, width, height)
+}
+
+/// The line and column of the cursor for the status bar
+fn column_indicator(buffer: &Buffer, indicator: ColumnIndicator) -> String {
+    let (x, y) = buffer.cursor_pos();
+    let (byte, grapheme) = buffer.line_offsets();
+    match indicator {
+        ColumnIndicator::Visual => format!("{}:{}", y + 1, x + 1),
+        ColumnIndicator::Grapheme => format!("{}:{} g{}", y + 1, x + 1, grapheme + 1),
+        ColumnIndicator::Byte => format!("{}:{} b{}", y + 1, x + 1, byte),
+        ColumnIndicator::All => format!("{}:{} g{} b{}", y + 1, x + 1, grapheme + 1, byte),
+    }
+}
+
+/// The line numbers of the visible lines.
+/// Relative numbers count the distance to the cursor line,
+/// which itself shows its absolute number.
+/// Rows that continue a wrapped line are marked instead.
+fn line_numbers(buffer: &Buffer, cursor_y: usize, area: Rect, relative: bool, numbers: bool, rows: Option<&[wrap::Row]>) -> Paragraph<'static> {
+    let lines = buffer.content.split('\n').count();
+    let width = area.width as usize - 1;
+    let rows: Vec<(usize, bool)> = match rows {
+        Some(rows) => rows.iter().map(|row| (row.line, row.first(&buffer.content))).collect(),
+        None => (buffer.top..cmp::min(buffer.top + area.height as usize, lines)).map(|line| (line, true)).collect(),
+    };
+    let numbers: Vec<Line> = rows.into_iter().map(|(line, first)| {
+        if !first {
+            Line::styled(format!("{:>width$} ", "↪"), Style::new().dark_gray())
+        } else if !numbers {
+            Line::raw("")
+        } else if line == cursor_y {
+            Line::raw(format!("{:>width$} ", line + 1))
+        } else {
+            let number = if relative { line.abs_diff(cursor_y) } else { line + 1 };
+            Line::styled(format!("{number:>width$} "), Style::new().dark_gray())
+        }
+    }).collect();
+    Paragraph::new(numbers)
+}
+
 /// Parse and highlight a buffer
-fn highlight<'a>(buffer: &'a Buffer, height: usize, cache: Rc<RefCell<ParseCache>>, syntax_set: &SyntaxSet, theme: &Theme, show_whitespace: bool) -> anyhow::Result<Vec<Line<'a>>> {
+fn highlight<'a>(buffer: &'a Buffer, height: usize, cache: Rc<RefCell<ParseCache>>, syntax_set: &SyntaxSet, theme: &Theme, options: &DisplayOptions) -> anyhow::Result<Vec<Line<'a>>> {
     let lines = LinesWithEndings::from(&buffer.content);
     let hl = Highlighter::new(theme);
     let syntax = buffer.syntax.as_ref().unwrap_or(syntax_set.find_syntax_plain_text());
-    parse_from(buffer.top, lines, height, &mut cache.borrow_mut(), &hl, syntax, &syntax_set, show_whitespace)
+    parse_from(buffer.top, lines, height, &mut cache.borrow_mut(), &hl, syntax, &syntax_set, options)
+}
+
+/// Draw a line at every level of indentation of the visible lines.
+/// The guide of the block the cursor is in is highlighted.
+fn indent_guides(f: &mut Frame, area: Rect, buffer: &Buffer, cursor_y: usize, theme: &Theme) {
+    let lines: Vec<&str> = buffer.content.split('\n').skip(buffer.top).take(area.height as usize).collect();
+    let widths: Vec<Option<usize>> = lines.iter().map(|line| {
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        (indent.len() < line.len()).then(|| indent.chars().map(|c| if c == '\t' { TABSIZE } else { 1 }).sum())
+    }).collect();
+    // blank lines continue the guides around them
+    let widths: Vec<usize> = (0..widths.len()).map(|i| {
+        widths[i].unwrap_or_else(|| {
+            let previous = widths[..i].iter().rev().find_map(|w| *w).unwrap_or(0);
+            let next = widths[i + 1..].iter().find_map(|w| *w).unwrap_or(0);
+            cmp::min(previous, next)
+        })
+    }).collect();
+
+    // the block of the cursor is the lines around it indented deeper than its guide
+    let cursor_row = cursor_y.checked_sub(buffer.top).filter(|row| *row < widths.len());
+    let active = cursor_row.and_then(|row| {
+        let column = widths[row].checked_sub(1)? / TABSIZE * TABSIZE;
+        let start = (0..=row).rev().take_while(|i| widths[*i] > column).last()?;
+        let end = (row..widths.len()).take_while(|i| widths[*i] > column).last()?;
+        Some((column, start..=end))
+    });
+
+    let guide = theme.settings.guide.and_then(syntect_tui::translate_colour).unwrap_or(Color::DarkGray);
+    let active_guide = theme.settings.active_guide.and_then(syntect_tui::translate_colour).unwrap_or(Color::Gray);
+    for (row, width) in widths.into_iter().enumerate() {
+        for column in (0..width).step_by(TABSIZE) {
+            let Some(x) = column.checked_sub(buffer.left).filter(|x| *x < area.width as usize) else {
+                continue;
+            };
+            let color = match &active {
+                Some((active_column, rows)) if *active_column == column && rows.contains(&row) => active_guide,
+                _ => guide,
+            };
+            if let Some(cell) = f.buffer_mut().cell_mut((area.x + x as u16, area.y + row as u16)) {
+                if cell.symbol() == " " {
+                    cell.set_symbol("│").set_fg(color);
+                }
+            }
+        }
+    }
+}
+
+/// Highlight the row and the column of the cursor at `x` and `y` across the text
+fn crosshair(f: &mut Frame, area: Rect, (x, y): (u16, u16), theme: &Theme) {
+    let color = theme.settings.line_highlight.and_then(syntect_tui::translate_colour).unwrap_or(Color::Indexed(236));
+    let style = Style::new().bg(color);
+    if y < area.bottom() {
+        f.buffer_mut().set_style(Rect::new(area.x, y, area.width, 1), style);
+    }
+    if x < area.right() {
+        f.buffer_mut().set_style(Rect::new(x, area.y, 1, area.height), style);
+    }
+}
+
+/// The byte range of the lines in view
+fn visible_bytes(buffer: &Buffer, height: usize) -> std::ops::Range<usize> {
+    let mut lines = buffer.content.split_inclusive('\n');
+    let start: usize = lines.by_ref().take(buffer.top).map(str::len).sum();
+    let len: usize = lines.take(height).map(str::len).sum();
+    start..start + len
+}
+
+/// Apply a style to the cells displaying the byte range `start..end` of a buffer
+fn style_range(f: &mut Frame, area: Rect, buffer: &Buffer, (start, end): (usize, usize), style: Style, options: &DisplayOptions) {
+    if let Some(rows) = options.rows {
+        for (y, row) in rows.iter().enumerate() {
+            let mut x = 0;
+            // the newline ending the line can be styled too
+            let row_end = match buffer.content.as_bytes().get(row.end) {
+                Some(b'\n') => row.end + 1,
+                _ => row.end,
+            };
+            for (i, chr) in buffer.content[row.start..row_end].char_indices() {
+                let width = if chr == '\t' { TABSIZE as u16 } else { 1 };
+                if (start..end).contains(&(row.start + i)) {
+                    for col in x..cmp::min(x + width, area.width) {
+                        if let Some(cell) = f.buffer_mut().cell_mut((area.x + col, area.y + y as u16)) {
+                            cell.set_style(style);
+                        }
+                    }
+                }
+                x += width;
+            }
+        }
+        return;
+    }
+    let mut offset = 0;
+    for (row, line) in buffer.content.split_inclusive('\n').enumerate() {
+        let line_start = offset;
+        offset += line.len();
+        if row < buffer.top || offset <= start {
+            continue;
+        }
+        let y = (row - buffer.top) as u16;
+        if y >= area.height || line_start >= end {
+            break;
+        }
+        let mut x = 0;
+        for (i, chr) in line.char_indices() {
+            let width = if chr == '\t' { TABSIZE as u16 } else { 1 };
+            if let Some(columns) = options.columns {
+                x = columns.column(line, i) as u16;
+            }
+            if (start..end).contains(&(line_start + i)) {
+                let left = buffer.left as u16;
+                for col in cmp::max(x, left)..cmp::min(x + width, area.width + left) {
+                    if let Some(cell) = f.buffer_mut().cell_mut((area.x + col - left, area.y + y)) {
+                        cell.set_style(style);
+                    }
+                }
+            }
+            x += width;
+        }
+    }
 }
@@ -0,0 +1,195 @@
+//! Soft wrapping long lines at word boundaries
+
+use crate::parse::whitespace::TABSIZE;
+
+/// The byte offsets into `line` where the rows after the first start
+/// when it is wrapped at `width` columns.
+/// Lines break after whitespace, words longer than a row are broken anywhere.
+pub fn get_linebreak_locations(line: &str, width: usize) -> Vec<usize> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let mut breaks = vec![];
+    if width == 0 {
+        return breaks;
+    }
+    let mut row_start = 0;
+    let mut col = 0;
+    // where the row could be broken last
+    let mut opportunity = None;
+    for (i, c) in line.char_indices() {
+        let w = char_width(c);
+        // whitespace may hang over the edge so the next row starts with the word
+        if col + w > width && i > row_start && !c.is_whitespace() {
+            let at = match opportunity {
+                Some(at) if at > row_start => at,
+                _ => i,
+            };
+            breaks.push(at);
+            row_start = at;
+            col = display_width(&line[at..i]);
+            opportunity = None;
+        }
+        col += w;
+        if c.is_whitespace() {
+            opportunity = Some(i + c.len_utf8());
+        }
+    }
+    breaks
+}
+
+/// The row and column a byte offset into a line is displayed at
+pub fn row_and_column(line: &str, breaks: &[usize], byte: usize) -> (usize, usize) {
+    let row = breaks.iter().take_while(|at| **at <= byte).count();
+    let start = if row == 0 { 0 } else { breaks[row - 1] };
+    (row, display_width(&line[start..byte.min(line.len())]))
+}
+
+/// The byte offset into a line that is displayed at a row and column,
+/// columns past the end of the row give the end of the row
+pub fn byte_at(line: &str, breaks: &[usize], row: usize, column: usize) -> usize {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let row = row.min(breaks.len());
+    let start = if row == 0 { 0 } else { breaks[row - 1] };
+    let end = breaks.get(row).copied().unwrap_or(line.len());
+    let mut col = 0;
+    for (i, c) in line[start..end].char_indices() {
+        col += char_width(c);
+        if col > column {
+            return start + i;
+        }
+    }
+    // the last column of a broken row is the start of the next one
+    if end < line.len() && end > start {
+        return line[..end].char_indices().next_back().map_or(start, |(i, _)| i);
+    }
+    end
+}
+
+/// A row on screen showing the bytes `start..end` of line `line`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Row {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Row {
+    /// If this row is the first of its line
+    pub fn first(&self, content: &str) -> bool {
+        self.start == 0 || content.as_bytes()[self.start - 1] == b'\n'
+    }
+}
+
+/// The rows that fit in `height` when wrapping the lines from `top` at `width`
+pub fn screen_rows(content: &str, top: usize, height: usize, width: usize) -> Vec<Row> {
+    let mut rows = vec![];
+    let mut offset = 0;
+    for (line_no, line) in content.split_inclusive('\n').enumerate() {
+        let line_start = offset;
+        offset += line.len();
+        if line_no < top {
+            continue;
+        }
+        let text = line.trim_end_matches(['\n', '\r']);
+        let starts = std::iter::once(0).chain(get_linebreak_locations(line, width)).collect::<Vec<_>>();
+        for (i, start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(text.len());
+            rows.push(Row { line: line_no, start: line_start + start, end: line_start + end });
+            if rows.len() >= height {
+                return rows;
+            }
+        }
+    }
+    // the empty line after a final newline
+    if content.is_empty() || content.ends_with('\n') {
+        let line = content.split('\n').count() - 1;
+        if line >= top && rows.len() < height {
+            rows.push(Row { line, start: content.len(), end: content.len() });
+        }
+    }
+    rows
+}
+
+/// The index of the row showing a byte offset of the content.
+/// The end of a broken row belongs to the row after it.
+pub fn row_of(content: &str, rows: &[Row], byte: usize) -> Option<usize> {
+    let i = rows.iter().rposition(|row| row.start <= byte)?;
+    let last = matches!(content.as_bytes().get(rows[i].end), None | Some(b'\n') | Some(b'\r'));
+    (byte < rows[i].end || (byte == rows[i].end && last)).then_some(i)
+}
+
+/// The byte offset of the content that is displayed at a column of a row
+pub fn byte_in_row(content: &str, row: Row, column: usize) -> usize {
+    let mut col = 0;
+    for (i, c) in content[row.start..row.end].char_indices() {
+        col += char_width(c);
+        if col > column {
+            return row.start + i;
+        }
+    }
+    row.end
+}
+
+fn char_width(c: char) -> usize {
+    if c == '\t' { TABSIZE } else { 1 }
+}
+
+pub fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaks_at_words() {
+        let line = "the quick brown fox\n";
+        assert_eq!(get_linebreak_locations(line, 10), vec![10]);
+        assert_eq!(get_linebreak_locations(line, 6), vec![4, 10, 16]);
+        assert!(get_linebreak_locations(line, 20).is_empty());
+        // the space hangs over the edge instead of starting the next row
+        assert_eq!(get_linebreak_locations("abcd efgh", 4), vec![5]);
+    }
+
+    #[test]
+    fn breaks_long_words() {
+        assert_eq!(get_linebreak_locations("abcdefghij", 4), vec![4, 8]);
+        assert_eq!(get_linebreak_locations("ab cdefghij k", 4), vec![3, 7, 12]);
+        assert_eq!(get_linebreak_locations("äöüäöü", 3), vec![6]);
+    }
+
+    #[test]
+    fn maps_rows_and_columns() {
+        let line = "the quick brown fox";
+        let breaks = get_linebreak_locations(line, 6);
+        assert_eq!(row_and_column(line, &breaks, 0), (0, 0));
+        assert_eq!(row_and_column(line, &breaks, 5), (1, 1));
+        assert_eq!(row_and_column(line, &breaks, 19), (3, 3));
+        assert_eq!(byte_at(line, &breaks, 1, 1), 5);
+        // past the end of a broken row
+        assert_eq!(byte_at(line, &breaks, 1, 8), 9);
+        assert_eq!(byte_at(line, &breaks, 3, 8), 19);
+    }
+
+    #[test]
+    fn lists_screen_rows() {
+        let content = "abcd efgh\nij\n";
+        let rows = screen_rows(content, 0, 10, 4);
+        assert_eq!(rows, vec![
+            Row { line: 0, start: 0, end: 5 },
+            Row { line: 0, start: 5, end: 9 },
+            Row { line: 1, start: 10, end: 12 },
+            Row { line: 2, start: 13, end: 13 },
+        ]);
+        assert!(rows[0].first(content) && !rows[1].first(content) && rows[2].first(content));
+        assert_eq!(screen_rows(content, 1, 1, 4), vec![Row { line: 1, start: 10, end: 12 }]);
+        assert_eq!(row_of(content, &rows, 4), Some(0));
+        assert_eq!(row_of(content, &rows, 5), Some(1));
+        assert_eq!(row_of(content, &rows, 9), Some(1));
+        assert_eq!(row_of(content, &rows, 13), Some(3));
+        // the end of a broken row when the next row is out of view
+        assert_eq!(row_of(content, &rows[..1], 5), None);
+        assert_eq!(byte_in_row(content, rows[1], 2), 7);
+        assert_eq!(byte_in_row(content, rows[1], 10), 9);
+    }
+}